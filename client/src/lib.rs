@@ -0,0 +1,161 @@
+extern crate gringotts;
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+
+use gringotts::net::protocol::*;
+
+/// A connection to a `gringottsd` server.  Errors the server reports come back as
+/// `io::ErrorKind::Other`, with the server's message.
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Client> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+
+        return Ok(Client {
+            stream: stream,
+        });
+    }
+
+    /// Sends a request and waits for its response.
+    pub fn call(&mut self, request: &Request) -> io::Result<Response> {
+        write_frame(&mut self.stream, &request.encode())?;
+
+        let payload = match read_frame(&mut self.stream)? {
+            Some(p) => p,
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The server closed the connection")),
+        };
+
+        return match Response::decode(&payload)? {
+            Response::Error(message) => Err(io::Error::new(io::ErrorKind::Other, message)),
+            response => Ok(response),
+        };
+    }
+
+    fn call_for_ok(&mut self, request: &Request) -> io::Result<()> {
+        return match self.call(request)? {
+            Response::Ok => Ok(()),
+            _ => Err(unexpected_response()),
+        };
+    }
+
+    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
+        return match self.call(&Request::Get(String::from(key)))? {
+            Response::Value(value) => Ok(value),
+            _ => Err(unexpected_response()),
+        };
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        return self.call_for_ok(&Request::Set(String::from(key), String::from(value)));
+    }
+
+    pub fn delete(&mut self, key: &str) -> io::Result<()> {
+        return self.call_for_ok(&Request::Delete(String::from(key)));
+    }
+
+    /// Returns the full path and value of every key beneath `prefix`, in order.
+    pub fn scan(&mut self, prefix: &str) -> io::Result<Vec<(String, String)>> {
+        return match self.call(&Request::Scan(String::from(prefix)))? {
+            Response::Entries(entries) => Ok(entries),
+            _ => Err(unexpected_response()),
+        };
+    }
+
+    /// Starts a transaction.  Until it's committed, sets and deletes are only seen by this
+    /// connection.
+    pub fn begin(&mut self) -> io::Result<()> {
+        return self.call_for_ok(&Request::Begin);
+    }
+
+    pub fn commit(&mut self) -> io::Result<()> {
+        return self.call_for_ok(&Request::Commit);
+    }
+
+    pub fn rollback(&mut self) -> io::Result<()> {
+        return self.call_for_ok(&Request::Rollback);
+    }
+}
+
+fn unexpected_response() -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, "The server sent a response of the wrong kind");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gringotts::dbfile::testing::*;
+    use gringotts::net::server::*;
+    use std::net::SocketAddr;
+    use std::thread;
+
+    fn start_server(name: &str) -> (TempDb, SocketAddr) {
+        let temp_db = TempDb::new(name);
+        let server = Server::bind("127.0.0.1:0", temp_db.create()).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        return (temp_db, address);
+    }
+
+    #[test]
+    fn serving_clients() {
+        let (_temp_db, address) = start_server("gringotts-server-test.gdb");
+        let mut client = Client::connect(address).unwrap();
+        let mut other = Client::connect(address).unwrap();
+
+        client.set("a/b", "1").unwrap();
+        client.set("a/c", "2").unwrap();
+        assert_eq!(other.get("a/b").unwrap(), Some(String::from("1")));
+        assert_eq!(other.get("a/x").unwrap(), None);
+        assert_eq!(other.scan("a").unwrap(), vec!((String::from("a/b"), String::from("1")), (String::from("a/c"), String::from("2"))));
+
+        client.delete("a/c").unwrap();
+        assert_eq!(other.get("a/c").unwrap(), None);
+    }
+
+    #[test]
+    fn transactions() {
+        let (_temp_db, address) = start_server("gringotts-server-transactions-test.gdb");
+        let mut client = Client::connect(address).unwrap();
+        let mut other = Client::connect(address).unwrap();
+
+        client.begin().unwrap();
+        assert!(client.begin().is_err());
+        client.set("a", "1").unwrap();
+        assert_eq!(client.get("a").unwrap(), Some(String::from("1")));
+        assert_eq!(other.get("a").unwrap(), None);
+        client.commit().unwrap();
+        assert_eq!(other.get("a").unwrap(), Some(String::from("1")));
+
+        client.begin().unwrap();
+        client.delete("a").unwrap();
+        client.rollback().unwrap();
+        assert_eq!(other.get("a").unwrap(), Some(String::from("1")));
+        assert!(client.commit().is_err());
+    }
+
+    #[test]
+    fn refusing_requests_after_a_failure() {
+        let temp_db = TempDb::new("gringotts-server-poisoned-test.gdb");
+        let dbfile = share(temp_db.create());
+
+        let held = dbfile.clone();
+        assert!(thread::spawn(move || {
+            let _guard = held.lock().unwrap();
+            panic!("failed while writing");
+        }).join().is_err());
+
+        let server = Server::bind_shared("127.0.0.1:0", dbfile, Protocol::Binary).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut client = Client::connect(address).unwrap();
+        assert!(client.get("a").is_err());
+        assert!(client.set("a", "1").is_err());
+    }
+}
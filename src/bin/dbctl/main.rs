@@ -0,0 +1,704 @@
+extern crate ansi_term;
+extern crate env_logger;
+extern crate getopts;
+extern crate gringotts;
+extern crate rustyline;
+#[macro_use]
+extern crate serde_json;
+
+use ansi_term::Colour::*;
+use getopts::Options;
+use gringotts::*;
+use std::env;
+use std::fs::{self, OpenOptions};
+use gringotts::dbfile::dump::DumpFormat;
+use std::io::{self, Read};
+use std::panic;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+mod output;
+mod shell;
+
+use output::*;
+
+fn main() {
+    // Initialize the environment logger;
+    env_logger::init();
+
+    // Grab arguments and program name;
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    // Initialize the Options object
+    let mut opts = Options::new();
+
+    // Add a help flag
+    opts.optflag("h", "help", "print this help menu");
+
+    // Add the most important flag, to ID which database to work on.
+    opts.reqopt("f", "database-file", "Specify the path to the database file to use.", "FILE");
+
+    // Every command can print its results for scripts instead of people.
+    opts.optopt("", "format", "print results and errors as text (the default) or json; errors exit with 1 in json", "FORMAT");
+
+    // Options for create
+    opts.optflag("", "prefix-compression", "create: compress the shared prefixes of keys within each block");
+    opts.optflag("", "block-compression", "create: compress the body of each block");
+
+    // Encrypted databases are opened, or created, with the passphrase in this file.
+    opts.optopt("", "passphrase-file", "read the passphrase for an encrypted database from FILE", "FILE");
+    opts.optopt("", "new-passphrase-file", "rekey: read the new passphrase from FILE", "FILE");
+
+    // Options for set
+    opts.optopt("", "ttl", "set: expire the value after SECONDS", "SECONDS");
+
+    // Used by dump and load to choose JSON lines over a single nested object.
+    opts.optflag("l", "lines", "dump/load one {path, value} JSON object per line; either way, integers are dumped as strings and TTLs are left out");
+
+    // Options for block
+    opts.optflag("", "raw", "block: show the block's bytes in hex, section by section");
+
+    // Options for import
+    opts.optflag("", "sorted", "import: the lines are already in dump order, so pack them into blocks directly");
+
+    // Grab the command name, which comes before any options.
+    let command = match args.get(1) {
+        Some(c) if !c.starts_with("-") => c.clone(),
+        _ => {
+            print_usage(&program, &opts);
+            return;
+        }
+    };
+
+    // Look for --format before anything else, so that problems with the rest of the options are
+    // reported in the right format.
+    let format = match OutputFormat::parse(find_format(&args[2..])) {
+        Ok(f) => f,
+        Err(err) => process::exit(print_result(OutputFormat::Text, Err(err))),
+    };
+
+    // Compare the matches
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            let code = print_result(format, Err(CommandError::new(USAGE, f.to_string())));
+            if format == OutputFormat::Text {
+                println!("");
+                print_usage(&program, &opts);
+            }
+            process::exit(code); // Exit without continuing
+        }
+    };
+
+    // If the user asked for help, give it to them.
+    if matches.opt_present("h") {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    // Panics from inside the database are reported like any other error when scripts are
+    // reading the output.
+    let result = match format {
+        OutputFormat::Text => run_command(&command, &matches, format),
+        OutputFormat::Json => {
+            panic::set_hook(Box::new(|_| {}));
+            match panic::catch_unwind(panic::AssertUnwindSafe(|| run_command(&command, &matches, format))) {
+                Ok(result) => result,
+                Err(payload) => Err(CommandError::from_panic(payload)),
+            }
+        },
+    };
+
+    let is_usage_error = match result {
+        Err(ref err) => err.code == USAGE,
+        Ok(_) => false,
+    };
+
+    let code = print_result(format, result);
+    if is_usage_error && format == OutputFormat::Text {
+        println!("");
+        print_usage(&program, &opts);
+    }
+
+    process::exit(code);
+}
+
+/// Finds the value of --format among the arguments, without parsing the rest of them.
+fn find_format(args: &[String]) -> Option<String> {
+    for (i, arg) in args.iter().enumerate() {
+        if arg == "--format" {
+            return args.get(i + 1).cloned();
+        }
+        if arg.starts_with("--format=") {
+            return Some(String::from(&arg["--format=".len()..]));
+        }
+    }
+
+    return None;
+}
+
+fn run_command(command: &String, matches: &getopts::Matches, format: OutputFormat) -> CommandResult {
+    // Grab the indicated filename.
+    let filename = matches.opt_str("f").unwrap();
+
+    let passphrase = match read_passphrase(matches, "passphrase-file") {
+        Ok(p) => p,
+        Err(message) => return Err(CommandError::new(IO, message)),
+    };
+
+    return match command.as_ref() {
+        "set" | "get" | "incr" if matches.free.is_empty() => {
+            Err(CommandError::new(USAGE, format!("{} needs a key", command)))
+        },
+        "shell"     => shell::run(filename, &passphrase, format),
+        "create"    => create_db(filename, get_db_options(matches), &passphrase),
+        "info"      => get_info(filename),
+        "drop"      => drop_db(filename),
+        "stats"     => get_stats(filename, &passphrase),
+        "verify"    => verify_db(filename, &passphrase),
+        "graph"     => graph_db(filename, &passphrase),
+        "block"     => inspect_block(filename, &passphrase, &matches.free, matches.opt_present("raw")),
+        "ls"        => list_keys(filename, &passphrase, matches.free.get(0)),
+        "set"       => set_val(filename, &passphrase, &matches.free[0], matches.opt_str("ttl")),
+        "get"       => get_val(filename, &passphrase, &matches.free[0]),
+        "dump"      => dump_db(filename, &passphrase, get_dump_format(matches)),
+        "load"      => load_db(filename, &passphrase, get_dump_format(matches)),
+        "import"    => import_db(filename, &passphrase, matches.opt_present("sorted")),
+        "upgrade"   => upgrade_db(filename, &passphrase),
+        "rekey"     => rekey_db(filename, &passphrase, matches),
+        "incr"      => increment_val(filename, &passphrase, &matches.free),
+        "expire"    => expire_db(filename, &passphrase),
+        cmd => Err(CommandError::new(USAGE, format!("{} is not a recognized command.", cmd))),
+    };
+}
+
+fn get_db_options(matches: &getopts::Matches) -> dbfile::DbOptions {
+    let mut options = dbfile::DbOptions::new();
+    options.prefix_compression = matches.opt_present("prefix-compression");
+    options.block_compression = matches.opt_present("block-compression");
+    return options;
+}
+
+/// Reads a passphrase from the file named by the given option, without its trailing newline.
+fn read_passphrase(matches: &getopts::Matches, option: &str) -> Result<Option<String>, String> {
+    let path = match matches.opt_str(option) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+
+    return match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(String::from(contents.trim_end_matches(|c| c == '\n' || c == '\r')))),
+        Err(err) => Err(format!("Couldn't read the passphrase from {}: {}", path, err)),
+    };
+}
+
+/// Describes a failed file operation, with the code that best matches what went wrong.
+fn io_error(action: &str, err: io::Error) -> CommandError {
+    let code = match err.kind() {
+        io::ErrorKind::NotFound => NOT_FOUND,
+        io::ErrorKind::AlreadyExists => ALREADY_EXISTS,
+        io::ErrorKind::WouldBlock => IN_USE,
+        io::ErrorKind::InvalidData => NOT_A_DATABASE,
+        io::ErrorKind::Unsupported => UNSUPPORTED,
+        io::ErrorKind::InvalidInput => INVALID_INPUT,
+        _ => IO,
+    };
+
+    return CommandError::new(code, format!("{}: {}", action, err.to_string()));
+}
+
+fn check_exists(filename: &String) -> Result<(), CommandError> {
+    if !Path::new(filename).exists() {
+        return Err(CommandError::new(NOT_FOUND, format!("{} doesn't exist", Path::new(filename).display())));
+    }
+
+    return Ok(());
+}
+
+/// Opens the database, unlocking it with the passphrase if it's encrypted.
+fn open_db(filename: &String, passphrase: &Option<String>) -> Result<dbfile::Dbfile, CommandError> {
+    check_exists(filename)?;
+
+    let opened = match *passphrase {
+        Some(ref p) => dbfile::Dbfile::open_with_passphrase(filename, p),
+        None => dbfile::Dbfile::open(filename),
+    };
+
+    return match opened {
+        Ok(file) => Ok(file),
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied && passphrase.is_none() => {
+            Err(CommandError::new(LOCKED, String::from("Couldn't unlock database: The database is encrypted; use --passphrase-file")))
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => Err(CommandError::new(LOCKED, format!("Couldn't unlock database: {}", err))),
+        Err(err) => Err(io_error("Failed to open database", err)),
+    };
+}
+
+fn create_new_db(filename: &String, options: &dbfile::DbOptions, passphrase: &Option<String>) -> std::io::Result<dbfile::Dbfile> {
+    return match *passphrase {
+        Some(ref p) => dbfile::Dbfile::create_with_passphrase(filename, options, p),
+        None => dbfile::Dbfile::create_with_options(filename, options),
+    };
+}
+
+fn create_db(filename: String, options: dbfile::DbOptions, passphrase: &Option<String>) -> CommandResult {
+    if OpenOptions::new().read(true).open(&filename).is_ok() {
+        return Err(CommandError::new(ALREADY_EXISTS, String::from("Database already exists")));
+    }
+
+    if let Err(err) = create_new_db(&filename, &options, passphrase) {
+        return Err(io_error("Failed to create database", err));
+    }
+
+    let text = format!("Successfully created database: {}\n", Path::new(&filename).display());
+    return Ok(Report::new(text, json!({ "created": filename })));
+}
+
+fn on_off(flag: bool) -> &'static str {
+    return if flag { "on" } else { "off" };
+}
+
+fn get_info(filename: String) -> CommandResult {
+    check_exists(&filename)?;
+    // Only the header is read, so encrypted databases don't need their passphrase.
+    let mut header = match dbfile::Dbfile::read_header(&filename) {
+        Ok(h) => h,
+        Err(err) => return Err(io_error("Failed to open database", err)),
+    };
+
+    let version = match header.get_version() {
+        Ok(v) => v,
+        Err(err) => return Err(CommandError::new(NOT_A_DATABASE, format!("Failed to read the version: {}", err.get_message()))),
+    };
+    let block_size = header.get_block_size();
+    let number_of_blocks = header.get_number_of_blocks();
+    let options = dbfile::DbOptions::from_features(header.get_features());
+
+    let mut text = String::new();
+    text += &format!("Filename: {}\n", filename);
+    text += &format!("Version: {}\n", version);
+    text += &format!("Block Size: {}kb\n", block_size);
+    text += &format!("Number of Blocks: {}\n", number_of_blocks);
+    text += &format!("Prefix Compression: {}\n", on_off(options.prefix_compression));
+    text += &format!("Block Compression: {}\n", on_off(options.block_compression));
+    text += &format!("Encryption: {}\n", on_off(options.encryption));
+
+    return Ok(Report::new(text, json!({
+        "filename": filename,
+        "version": version.to_string(),
+        "block_size_kb": block_size,
+        "number_of_blocks": number_of_blocks,
+        "prefix_compression": options.prefix_compression,
+        "block_compression": options.block_compression,
+        "encryption": options.encryption,
+    })));
+}
+
+fn drop_db(filename: String) -> CommandResult {
+    if let Err(err) = dbfile::Dbfile::destroy(&filename) {
+        return Err(io_error("Failed to drop database", err));
+    }
+
+    let text = format!("Dropped database: {}\n", Path::new(&filename).display());
+    return Ok(Report::new(text, json!({ "dropped": filename })));
+}
+
+fn get_stats(filename: String, passphrase: &Option<String>) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+    return Ok(stats_report(&mut file));
+}
+
+/// Describes the shape of the database; used by both stats and the shell.
+fn stats_report(file: &mut dbfile::Dbfile) -> Report {
+    let stats = dbfile::stats::collect(file);
+    let mut text = String::new();
+
+    text += &format!("Keys: {}\n", stats.keys);
+    text += &format!("Subtrees: {}\n", stats.subtrees);
+    text += &format!("Value Bytes: {}\n", stats.value_bytes);
+
+    text += &format!("Max Depth: {}\n", stats.get_max_depth());
+    let mut depths = Vec::new();
+    for (depth, levels) in stats.levels_by_depth.iter() {
+        let keys = stats.keys_by_depth.get(depth).cloned().unwrap_or(0);
+        text += &format!("  depth {}: {} levels, {} keys\n", depth, levels, keys);
+        depths.push(json!({ "depth": depth, "levels": levels, "keys": keys }));
+    }
+
+    text += &format!("Sibling Chain Lengths (longest {}):\n", stats.get_max_chain_length());
+    let mut chain_lengths = Vec::new();
+    for (length, levels) in stats.chain_lengths.iter() {
+        text += &format!("  {} blocks: {} levels\n", length, levels);
+        chain_lengths.push(json!({ "blocks": length, "levels": levels }));
+    }
+
+    text += &format!("Block Fill ({} node blocks):\n", stats.node_blocks);
+    let mut fill_histogram = Vec::new();
+    for (i, count) in stats.fill_histogram.iter().enumerate() {
+        let width = 100 / dbfile::stats::FILL_BUCKETS;
+        text += &format!("  {:>3}-{:>3}%: {}\n", i * width, (i + 1) * width, count);
+        fill_histogram.push(json!({ "min_percent": i * width, "max_percent": (i + 1) * width, "blocks": count }));
+    }
+
+    text += "Value Sizes:\n";
+    let limits = dbfile::stats::VALUE_SIZE_LIMITS;
+    let mut value_sizes = Vec::new();
+    for (i, count) in stats.value_sizes.iter().enumerate() {
+        let (range, min, max) = match i {
+            0 => (format!("< {}", limits[0]), 0, Some(limits[0] - 1)),
+            i if i == limits.len() => (format!(">= {}", limits[i - 1]), limits[i - 1], None),
+            i => (format!("{}-{}", limits[i - 1], limits[i] - 1), limits[i - 1], Some(limits[i] - 1)),
+        };
+        text += &format!("  {:>9} bytes: {}\n", range, count);
+        value_sizes.push(json!({ "min_bytes": min, "max_bytes": max, "values": count }));
+    }
+
+    text += &format!("Total Blocks: {}\n", stats.total_blocks);
+    text += &format!("Free Blocks: {}\n", stats.free_blocks);
+    text += &format!("Orphaned Blocks: {}\n", stats.orphaned_blocks.len());
+    if !stats.orphaned_blocks.is_empty() {
+        let numbers: Vec<String> = stats.orphaned_blocks.iter().map(|n| n.to_string()).collect();
+        text += &format!("  {}\n", numbers.join(", "));
+    }
+
+    return Report::new(text, json!({
+        "keys": stats.keys,
+        "subtrees": stats.subtrees,
+        "value_bytes": stats.value_bytes,
+        "max_depth": stats.get_max_depth(),
+        "depths": depths,
+        "max_chain_length": stats.get_max_chain_length(),
+        "chain_lengths": chain_lengths,
+        "node_blocks": stats.node_blocks,
+        "fill_histogram": fill_histogram,
+        "value_sizes": value_sizes,
+        "total_blocks": stats.total_blocks,
+        "free_blocks": stats.free_blocks,
+        "orphaned_blocks": stats.orphaned_blocks,
+    }));
+}
+
+fn verify_db(filename: String, passphrase: &Option<String>) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+    let report = dbfile::verify::verify(&mut file);
+
+    let mut text = String::new();
+    if report.is_ok() {
+        text += &format!("OK: {} blocks checked\n", report.blocks_checked);
+    }
+    else {
+        text += &format!("Found {} problems in {} blocks checked:\n", report.problems.len(), report.blocks_checked);
+        for problem in report.problems.iter() {
+            text += &format!("  block {} {}\n", problem.block, problem.message);
+        }
+    }
+
+    let problems: Vec<_> = report.problems.iter()
+        .map(|p| json!({ "block": p.block, "message": p.message }))
+        .collect();
+
+    return Ok(Report::new(text, json!({
+        "ok": report.is_ok(),
+        "blocks_checked": report.blocks_checked,
+        "problems": problems,
+    })));
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn inspect_block(filename: String, passphrase: &Option<String>, args: &Vec<String>, raw: bool) -> CommandResult {
+    let block_number = match args.get(0).map(|s| s.parse::<u64>()) {
+        Some(Ok(n)) => n,
+        _ => return Err(CommandError::new(USAGE, String::from("block needs a block number"))),
+    };
+
+    let mut file = open_db(&filename, passphrase)?;
+    let not_found = |err: gringotts::error::InvalidInputError| CommandError::new(NOT_FOUND, err.get_message());
+
+    if raw {
+        let sections = dbfile::inspect::get_raw_sections(&mut file, block_number).map_err(&not_found)?;
+        let lines = dbfile::inspect::format_raw_block(&mut file, block_number).map_err(&not_found)?;
+        let sections: Vec<_> = sections.iter().map(|section| json!({
+            "name": section.name,
+            "start": section.start,
+            "end": section.start + section.bytes.len(),
+            "bytes": to_hex(&section.bytes),
+        })).collect();
+
+        let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        return Ok(Report::new(text, json!({ "block": block_number, "sections": sections })));
+    }
+
+    let description = dbfile::inspect::describe_block(&mut file, block_number).map_err(&not_found)?;
+    let next_block = description.next_block.map_or(String::from("none"), |n| n.to_string());
+
+    let mut text = String::new();
+    text += &format!("Block: {}\n", description.block_number);
+    text += &format!("BodySize: {}\n", description.body_size);
+    text += &format!("Type: {} ({})\n", description.block_type.get_name(), description.type_code);
+    text += &format!("NextBlock: {}\n", next_block);
+    text += &format!("Format: {}\n", description.format);
+    text += &format!("Flags: {:#04b}\n", description.flags);
+
+    let mut json = json!({
+        "block": description.block_number,
+        "body_size": description.body_size,
+        "type": description.block_type.get_name(),
+        "type_code": description.type_code,
+        "next_block": description.next_block,
+        "format": description.format,
+        "flags": description.flags,
+        "entries": null,
+        "body_error": null,
+    });
+
+    let entries = match description.entries {
+        Ok(e) => e,
+        Err(message) => {
+            // The header was still worth showing, so this isn't an error.
+            text += &format!("{}\n", Red.bold().paint(format!("Couldn't read the body: {}", message)));
+            json["body_error"] = json!(message);
+            return Ok(Report::new(text, json));
+        }
+    };
+
+    text += &format!("Entries: {}\n", entries.len());
+    let mut json_entries = Vec::new();
+    for entry in entries {
+        let mut fields = Vec::new();
+        if let Some(ref value) = entry.value {
+            fields.push(format!("value {:?}", value));
+        }
+        if let Some(integer) = entry.integer {
+            fields.push(format!("integer {}", integer));
+        }
+        if let Some(expires) = entry.expires {
+            fields.push(format!("expires {}", expires));
+        }
+        if let Some(block_ref) = entry.block_ref {
+            fields.push(format!("-> block {}", block_ref));
+        }
+
+        text += &format!("  {:?}: {}\n", entry.key, fields.join(", "));
+        json_entries.push(json!({
+            "key": entry.key,
+            "value": entry.value,
+            "integer": entry.integer,
+            "expires": entry.expires,
+            "block_ref": entry.block_ref,
+        }));
+    }
+    json["entries"] = json!(json_entries);
+
+    return Ok(Report::new(text, json));
+}
+
+fn graph_db(filename: String, passphrase: &Option<String>) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+
+    let mut buffer = Vec::new();
+    if let Err(err) = dbfile::graph::write_dot(&mut file, &mut buffer) {
+        return Err(io_error("Failed to graph database", err));
+    }
+
+    let dot = String::from_utf8_lossy(&buffer).into_owned();
+    return Ok(Report::new(dot.clone(), json!({ "dot": dot })));
+}
+
+/// Lists the keys directly beneath a path, the same way the shell's ls does.
+fn list_keys(filename: String, passphrase: &Option<String>, path: Option<&String>) -> CommandResult {
+    let path = path.map_or("", |p| p.as_str());
+    let keys = shell::resolve_path(&Vec::new(), path);
+
+    let mut file = open_db(&filename, passphrase)?;
+    let listing = match file.list_level(&keys) {
+        Some(l) => l,
+        None => return Err(CommandError::new(NOT_FOUND, format!("Nothing beneath /{}", dbfile::keychain::KeyChain::join(&keys)))),
+    };
+
+    let text: String = listing.iter()
+        .map(|&(ref key, ref value, has_subtree)| format!("{}\n", shell::format_listing(key, value, has_subtree)))
+        .collect();
+    let json_keys: Vec<_> = listing.into_iter()
+        .map(|(key, value, has_subtree)| json!({ "key": key, "value": value, "has_subtree": has_subtree }))
+        .collect();
+
+    return Ok(Report::new(text, json!({
+        "path": dbfile::keychain::KeyChain::join(&keys),
+        "keys": json_keys,
+    })));
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} COMMAND [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn set_val(filename: String, passphrase: &Option<String>, key: &String, ttl: Option<String>) -> CommandResult {
+    let ttl = match ttl.map(|s| s.parse::<u64>()) {
+        None => None,
+        Some(Ok(seconds)) => Some(Duration::from_secs(seconds)),
+        Some(Err(_)) => return Err(CommandError::new(USAGE, String::from("--ttl must be a whole number of seconds"))),
+    };
+
+    let mut file = open_db(&filename, passphrase)?;
+    let mut buffer = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut buffer) {
+        return Err(io_error("Couldn't read the value", err));
+    }
+
+    let written = match ttl {
+        Some(duration) => file.set_with_ttl(key, buffer, duration),
+        None => file.set_val(key, buffer),
+    };
+    if let Err(err) = written {
+        return Err(CommandError::new(INVALID_INPUT, err.get_message()));
+    }
+
+    return Ok(Report::new(String::new(), json!({ "key": key })));
+}
+
+fn expire_db(filename: String, passphrase: &Option<String>) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+
+    let removed = file.expire();
+    let text = format!("Removed {} expired keys from {}\n", removed, Path::new(&filename).display());
+    return Ok(Report::new(text, json!({ "removed": removed })));
+}
+
+/// Prints the key's value, or nothing if it has none; in JSON a missing value is null, rather than
+/// an error, just as it is in text.
+fn get_val(filename: String, passphrase: &Option<String>, key: &String) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+    let value = file.get_val(key);
+
+    let text = value.clone().unwrap_or(String::new());
+    return Ok(Report::new(text, json!({ "key": key, "value": value })));
+}
+
+/// Adds to an integer key and prints the new value.  The amount defaults to 1; put `--` before a
+/// negative amount so that it isn't read as an option.
+fn increment_val(filename: String, passphrase: &Option<String>, args: &Vec<String>) -> CommandResult {
+    let delta = match args.get(1).map(|s| s.parse::<i64>()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return Err(CommandError::new(USAGE, format!("{} is not an integer", args[1]))),
+    };
+
+    let mut file = open_db(&filename, passphrase)?;
+
+    return match file.increment(&args[0], delta) {
+        Ok(value) => Ok(Report::new(format!("{}\n", value), json!({ "key": args[0], "value": value }))),
+        Err(err) => {
+            let message = format!("Failed to increment {}: {}", args[0], err.get_message());
+            Err(CommandError::new(INVALID_INPUT, message))
+        }
+    };
+}
+
+fn get_dump_format(matches: &getopts::Matches) -> DumpFormat {
+    return match matches.opt_present("l") {
+        true  => DumpFormat::Lines,
+        false => DumpFormat::Nested,
+    };
+}
+
+/// Dumps are already JSON, so they're written straight out whatever the format.
+fn dump_db(filename: String, passphrase: &Option<String>, format: DumpFormat) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+    let stdout = io::stdout();
+
+    if let Err(err) = dbfile::dump::dump(&mut file, format, &mut stdout.lock()) {
+        return Err(io_error("Failed to dump database", err));
+    }
+
+    return Ok(Report::raw(String::new()));
+}
+
+/// Opens the database, creating it first if it doesn't exist yet.
+fn open_or_create_db(filename: &String, passphrase: &Option<String>) -> Result<dbfile::Dbfile, CommandError> {
+    if Path::new(filename).exists() {
+        return open_db(filename, passphrase);
+    }
+
+    return create_new_db(filename, &dbfile::DbOptions::new(), passphrase)
+        .map_err(|err| io_error("Failed to create database", err));
+}
+
+fn load_db(filename: String, passphrase: &Option<String>, format: DumpFormat) -> CommandResult {
+    let mut file = open_or_create_db(&filename, passphrase)?;
+
+    let stdin = io::stdin();
+    return match dbfile::dump::load(&mut file, format, stdin.lock()) {
+        Ok(count) => {
+            let text = format!("Loaded {} keys into {}\n", count, Path::new(&filename).display());
+            Ok(Report::new(text, json!({ "loaded": count })))
+        },
+        Err(err) => Err(CommandError::new(INVALID_INPUT, format!("Failed to load database: {}", err.get_message()))),
+    };
+}
+
+fn import_db(filename: String, passphrase: &Option<String>, sorted: bool) -> CommandResult {
+    let mut file = open_or_create_db(&filename, passphrase)?;
+
+    let stdin = io::stdin();
+    let result = match sorted {
+        true  => dbfile::dump::import_sorted(&mut file, stdin.lock()),
+        false => dbfile::dump::load(&mut file, DumpFormat::Lines, stdin.lock()),
+    };
+
+    return match result {
+        Ok(count) => {
+            let text = format!("Imported {} keys into {}\n", count, Path::new(&filename).display());
+            Ok(Report::new(text, json!({ "imported": count })))
+        },
+        Err(err) => Err(CommandError::new(INVALID_INPUT, format!("Failed to import: {}", err.get_message()))),
+    };
+}
+
+fn upgrade_db(filename: String, passphrase: &Option<String>) -> CommandResult {
+    let mut file = open_db(&filename, passphrase)?;
+
+    let applied = match dbfile::migrations::upgrade(&mut file) {
+        Ok(a) => a,
+        Err(err) => return Err(CommandError::new(INVALID_INPUT, format!("Failed to upgrade database: {}", err.get_message()))),
+    };
+
+    let version = file.get_version();
+    let mut text = String::new();
+    if applied.len() == 0 {
+        text += &format!("Database is already at version {}\n", version);
+    }
+    for migration in applied.iter() {
+        text += &format!("Upgraded from {} to {}: {}\n", migration.from, migration.to, migration.description);
+    }
+
+    let applied: Vec<_> = applied.iter().map(|migration| json!({
+        "from": migration.from.to_string(),
+        "to": migration.to.to_string(),
+        "description": migration.description,
+    })).collect();
+
+    return Ok(Report::new(text, json!({ "version": version.to_string(), "applied": applied })));
+}
+
+fn rekey_db(filename: String, passphrase: &Option<String>, matches: &getopts::Matches) -> CommandResult {
+    let new_passphrase = match read_passphrase(matches, "new-passphrase-file") {
+        Ok(Some(p)) => p,
+        Ok(None) => return Err(CommandError::new(USAGE, String::from("rekey needs --new-passphrase-file"))),
+        Err(message) => return Err(CommandError::new(IO, message)),
+    };
+
+    let mut file = open_db(&filename, passphrase)?;
+
+    if let Err(err) = file.change_passphrase(&new_passphrase) {
+        return Err(io_error("Failed to change the passphrase", err));
+    }
+
+    let text = format!("Changed the passphrase for {}\n", Path::new(&filename).display());
+    return Ok(Report::new(text, json!({ "rekeyed": filename })));
+}
@@ -0,0 +1,113 @@
+use ansi_term::Colour::*;
+use gringotts::error::describe_panic;
+use serde_json::Value;
+use std::any::Any;
+
+/// How results and errors are printed.  Text is for people; JSON is for scripts, which can rely on
+/// its field names and error codes staying the same.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(name: Option<String>) -> Result<OutputFormat, CommandError> {
+        return match name.as_ref().map(|s| s.as_str()) {
+            None | Some("text") => Ok(OutputFormat::Text),
+            Some("json") => Ok(OutputFormat::Json),
+            Some(other) => Err(CommandError::new(USAGE, format!("{} is not a format; use text or json", other))),
+        };
+    }
+}
+
+// Error codes.  These are part of the JSON output, so once added they shouldn't change.
+/// The command line didn't make sense.
+pub const USAGE: &'static str = "usage";
+/// A key, path or block that was asked for doesn't exist.
+pub const NOT_FOUND: &'static str = "not_found";
+/// The database being created already exists.
+pub const ALREADY_EXISTS: &'static str = "already_exists";
+/// The input, or a value in the database, wasn't what the command needed.
+pub const INVALID_INPUT: &'static str = "invalid_input";
+/// The database is encrypted and couldn't be unlocked.
+pub const LOCKED: &'static str = "locked";
+/// Another process is using the database.
+pub const IN_USE: &'static str = "in_use";
+/// The file isn't a Gringotts database.
+pub const NOT_A_DATABASE: &'static str = "not_a_database";
+/// The database was written by a newer version of Gringotts, or uses features this one lacks.
+pub const UNSUPPORTED: &'static str = "unsupported";
+/// Reading or writing a file failed.
+pub const IO: &'static str = "io";
+/// The database failed a check, or something went wrong inside Gringotts itself.
+pub const INTERNAL: &'static str = "internal";
+
+pub struct CommandError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: &'static str, message: String) -> CommandError {
+        return CommandError {
+            code: code,
+            message: message,
+        };
+    }
+
+    /// Describes a panic from inside the database, which means something went wrong in Gringotts
+    /// itself.
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> CommandError {
+        return CommandError::new(INTERNAL, describe_panic(payload));
+    }
+
+    pub fn to_json(&self) -> Value {
+        return json!({ "error": { "code": self.code, "message": self.message } });
+    }
+}
+
+/// What a command found or did, written out for people and for scripts.
+pub struct Report {
+    pub text: String,
+    /// None for output that's already meant for scripts, like a dump, which is printed as it is
+    /// either way.
+    pub json: Option<Value>,
+}
+
+impl Report {
+    pub fn new(text: String, json: Value) -> Report {
+        return Report {
+            text: text,
+            json: Some(json),
+        };
+    }
+
+    pub fn raw(text: String) -> Report {
+        return Report {
+            text: text,
+            json: None,
+        };
+    }
+}
+
+pub type CommandResult = Result<Report, CommandError>;
+
+/// Prints a command's result, returning the status to exit with.  JSON output exits with 1 on
+/// errors; text output always exits with 0, as it always has.
+pub fn print_result(format: OutputFormat, result: CommandResult) -> i32 {
+    match (format, result) {
+        (OutputFormat::Text, Ok(report)) => print!("{}", report.text),
+        (OutputFormat::Text, Err(err)) => println!("{}", Red.bold().paint(err.message)),
+        (OutputFormat::Json, Ok(report)) => match report.json {
+            Some(json) => println!("{}", json),
+            None => print!("{}", report.text),
+        },
+        (OutputFormat::Json, Err(err)) => {
+            println!("{}", err.to_json());
+            return 1;
+        },
+    }
+
+    return 0;
+}
@@ -0,0 +1,386 @@
+use ansi_term::Colour::*;
+use gringotts::dbfile::Dbfile;
+use gringotts::dbfile::batch::WriteBatch;
+use gringotts::dbfile::keychain::KeyChain;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use output::*;
+
+const HELP: &'static str = "\
+get KEY          print a key's value
+set KEY VALUE    set a key's value to the rest of the line
+rm KEY           remove a key's value
+ls [PATH]        list the keys beneath a path, marking those with keys beneath them with a /
+cd [PATH]        move to a path; .. goes up a level, and paths starting with / are from the top
+pwd              print the current path
+stats            print statistics about the database
+begin            start a transaction, holding on to sets and removes until it's committed
+commit           apply the transaction's changes all at once
+rollback         throw away the transaction's changes
+exit             leave the shell";
+
+/// Turns a path into the keys it names.  Paths are relative to `cwd` unless they start with a /.
+pub fn resolve_path(cwd: &Vec<String>, path: &str) -> Vec<String> {
+    let (mut keys, path) = match path.starts_with("/") {
+        true  => (Vec::new(), &path[1..]),
+        false => (cwd.clone(), path),
+    };
+
+    let keychain = KeyChain::parse(&String::from(path));
+    let mut pieces = keychain.as_vec();
+    pieces.push(keychain.get_final_key());
+
+    for piece in pieces {
+        match piece.as_ref() {
+            "" | "." => {},
+            ".." => {
+                keys.pop();
+            },
+            _ => keys.push(piece),
+        }
+    }
+
+    return keys;
+}
+
+/// Formats one key from a listing, marking those with keys beneath them with a /.
+pub fn format_listing(key: &String, value: &Option<String>, has_subtree: bool) -> String {
+    let name = KeyChain::join(&vec!(key.clone())) + if has_subtree { "/" } else { "" };
+    return match *value {
+        Some(ref v) => format!("{} = {:?}", name, v),
+        None => name,
+    };
+}
+
+/// An open database, along with where in its keychain the shell is and any transaction that's
+/// under way.
+pub struct Shell {
+    file: Dbfile,
+    cwd: Vec<String>,
+    batch: Option<WriteBatch>,
+}
+
+impl Shell {
+    pub fn new(file: Dbfile) -> Shell {
+        return Shell {
+            file: file,
+            cwd: Vec::new(),
+            batch: None,
+        };
+    }
+
+    fn get_prompt(&self) -> String {
+        let pending = match self.batch {
+            Some(ref batch) => format!(" ({} pending)", batch.len()),
+            None => String::new(),
+        };
+
+        return format!("gringotts:/{}{}> ", KeyChain::join(&self.cwd), pending);
+    }
+
+    /// Turns a path typed at the prompt into the keys it names.
+    fn resolve(&self, path: &str) -> Vec<String> {
+        return resolve_path(&self.cwd, path);
+    }
+
+    fn resolve_key(&self, path: Option<&str>) -> Result<String, String> {
+        return match path.map(|p| self.resolve(p)) {
+            Some(ref keys) if !keys.is_empty() => Ok(KeyChain::join(keys)),
+            _ => Err(String::from("A key is needed")),
+        };
+    }
+
+    /// Runs one line typed at the prompt, returning what to print.
+    pub fn execute(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(c) => c,
+            None => return Ok(Vec::new()),
+        };
+        let argument = words.next();
+
+        match command {
+            "help" => {
+                return Ok(HELP.lines().map(String::from).collect());
+            },
+            "pwd" => {
+                return Ok(vec!(format!("/{}", KeyChain::join(&self.cwd))));
+            },
+            "ls" => {
+                let keys = self.resolve(argument.unwrap_or(""));
+                let listing = match self.file.list_level(&keys) {
+                    Some(l) => l,
+                    None => return Err(format!("Nothing beneath /{}", KeyChain::join(&keys))),
+                };
+
+                return Ok(listing.iter()
+                    .map(|&(ref key, ref value, has_subtree)| format_listing(key, value, has_subtree))
+                    .collect());
+            },
+            "cd" => {
+                let keys = self.resolve(argument.unwrap_or("/"));
+                if self.file.list_level(&keys).is_none() {
+                    return Err(format!("Nothing beneath /{}", KeyChain::join(&keys)));
+                }
+
+                self.cwd = keys;
+                return Ok(Vec::new());
+            },
+            "get" => {
+                let key = self.resolve_key(argument)?;
+                let value = match self.batch.as_ref().and_then(|b| b.get(&key)) {
+                    Some(pending) => pending,
+                    None => self.file.get_val(&key),
+                };
+
+                return match value {
+                    Some(v) => Ok(vec!(v)),
+                    None => Err(format!("{} has no value", key)),
+                };
+            },
+            "set" => {
+                let key = self.resolve_key(argument)?;
+                // Everything after the key is the value, spaces and all.  The line starts with
+                // the command, followed by the key, however much space is between them.
+                let after_command = line[command.len()..].trim_start();
+                let value = after_command[argument.unwrap_or("").len()..].trim_start();
+
+                return match self.batch {
+                    Some(ref mut batch) => Ok(batch.set(&key, String::from(value))),
+                    None => self.file.set_val(&key, String::from(value)).map_err(|e| e.get_message()),
+                }.map(|_| Vec::new());
+            },
+            "rm" => {
+                let key = self.resolve_key(argument)?;
+
+                match self.batch {
+                    Some(ref mut batch) => batch.delete(&key),
+                    None => {
+                        self.file.delete_val(&key);
+                    },
+                }
+                return Ok(Vec::new());
+            },
+            "stats" => {
+                let report = super::stats_report(&mut self.file);
+                return Ok(report.text.lines().map(String::from).collect());
+            },
+            "begin" => {
+                if self.batch.is_some() {
+                    return Err(String::from("A transaction is already under way"));
+                }
+
+                self.batch = Some(WriteBatch::new());
+                return Ok(Vec::new());
+            },
+            "commit" => {
+                let batch = match self.batch.take() {
+                    Some(b) => b,
+                    None => return Err(String::from("No transaction is under way")),
+                };
+
+                let count = batch.len();
+                return match self.file.write_batch(batch) {
+                    Ok(_) => Ok(vec!(format!("Committed {} changes", count))),
+                    Err(e) => Err(format!("Couldn't commit: {}", e)),
+                };
+            },
+            "rollback" => {
+                return match self.batch.take() {
+                    Some(batch) => Ok(vec!(format!("Rolled back {} changes", batch.len()))),
+                    None => Err(String::from("No transaction is under way")),
+                };
+            },
+            cmd => {
+                return Err(format!("{} is not a recognized command; try help", cmd));
+            },
+        }
+    }
+
+    /// Completes the last word of the line as a path, with the keys beneath the path typed so far.
+    /// Returns where the completed part starts, and the keys that could go there.
+    fn complete(&mut self, line: &str) -> (usize, Vec<String>) {
+        let word_start = line.rfind(' ').map_or(0, |i| i + 1);
+        if word_start == 0 {
+            return (0, Vec::new());
+        }
+
+        let word = &line[word_start..];
+        let key_start = match word.rfind('/') {
+            Some(i) if !word[..i].ends_with('\\') => i + 1,
+            _ => 0,
+        };
+
+        let partial = &word[key_start..];
+        let keys = self.resolve(&word[..key_start]);
+        let candidates = match self.file.list_level(&keys) {
+            Some(listing) => listing.into_iter()
+                .map(|(key, _, has_subtree)| KeyChain::join(&vec!(key)) + if has_subtree { "/" } else { "" })
+                .filter(|key| key.starts_with(partial))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        return (word_start + key_start, candidates);
+    }
+}
+
+struct ShellHelper {
+    shell: Rc<RefCell<Shell>>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<String>), ReadlineError> {
+        return Ok(self.shell.borrow_mut().complete(&line[..pos]));
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+impl Validator for ShellHelper {}
+impl Helper for ShellHelper {}
+
+fn get_history_path() -> Option<PathBuf> {
+    return env::var_os("HOME").map(|home| PathBuf::from(home).join(".dbctl_history"));
+}
+
+/// Prints what a command found, or why it failed, in the chosen format.
+fn print_response(format: OutputFormat, response: Result<Vec<String>, String>) {
+    match (format, response) {
+        (OutputFormat::Text, Ok(output)) => for output_line in output {
+            println!("{}", output_line);
+        },
+        (OutputFormat::Text, Err(message)) => println!("{}", Red.bold().paint(message)),
+        (OutputFormat::Json, Ok(output)) => println!("{}", json!({ "output": output })),
+        (OutputFormat::Json, Err(message)) => println!("{}", CommandError::new(INVALID_INPUT, message).to_json()),
+    }
+}
+
+/// Opens the database and reads commands until the user leaves.  In JSON, each command's output
+/// is printed as one line, either `{"output": [...]}` or an error object.
+pub fn run(filename: String, passphrase: &Option<String>, format: OutputFormat) -> CommandResult {
+    let file = super::open_db(&filename, passphrase)?;
+
+    let shell = Rc::new(RefCell::new(Shell::new(file)));
+    let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+        Ok(e) => e,
+        Err(err) => return Err(CommandError::new(IO, format!("Couldn't start the shell: {}", err))),
+    };
+    editor.set_helper(Some(ShellHelper { shell: shell.clone() }));
+
+    let history_path = get_history_path();
+    if let Some(ref path) = history_path {
+        let _ = editor.load_history(path);
+    }
+
+    let mut result = Ok(Report::raw(String::new()));
+    loop {
+        let prompt = shell.borrow().get_prompt();
+        let line = match editor.readline(&prompt) {
+            Ok(l) => l,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                result = Err(CommandError::new(IO, err.to_string()));
+                break;
+            }
+        };
+
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        match line.trim() {
+            "exit" | "quit" => break,
+            _ => {},
+        }
+
+        let response = shell.borrow_mut().execute(&line);
+        print_response(format, response);
+    }
+
+    if let Some(batch) = shell.borrow_mut().batch.take() {
+        print_response(format, Ok(vec!(format!("Rolled back {} uncommitted changes", batch.len()))));
+    }
+
+    if let Some(ref path) = history_path {
+        let _ = editor.save_history(path);
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gringotts::dbfile::testing::*;
+
+    fn temp_shell(name: &str) -> (TempDb, Shell) {
+        let temp_db = TempDb::new(name);
+        let shell = Shell::new(temp_db.create());
+        return (temp_db, shell);
+    }
+
+    fn run_lines(shell: &mut Shell, lines: &[&str]) -> Vec<Result<Vec<String>, String>> {
+        return lines.iter().map(|line| shell.execute(line)).collect();
+    }
+
+    #[test]
+    fn moving_around_and_listing() {
+        let (_temp_db, mut shell) = temp_shell("gringotts-shell-paths-test.gdb");
+        run_lines(&mut shell, &["set config/port 80", "set config/name my  service", "set top 1"]);
+
+        assert_eq!(shell.execute("ls"), Ok(vec!(String::from("config/"), String::from("top = \"1\""))));
+        assert_eq!(shell.execute("cd config"), Ok(Vec::new()));
+        assert_eq!(shell.execute("pwd"), Ok(vec!(String::from("/config"))));
+        assert_eq!(shell.execute("get name"), Ok(vec!(String::from("my  service"))));
+        assert_eq!(shell.execute("get /top"), Ok(vec!(String::from("1"))));
+        assert_eq!(shell.execute("get ../top"), Ok(vec!(String::from("1"))));
+        assert!(shell.execute("cd missing").is_err());
+
+        assert_eq!(shell.complete("get p"), (4, vec!(String::from("port"))));
+        assert_eq!(shell.complete("ls /c"), (4, vec!(String::from("config/"))));
+        assert_eq!(shell.complete("get /config/"), (12, vec!(String::from("name"), String::from("port"))));
+
+        shell.execute("rm port").ok();
+        assert!(shell.execute("get port").is_err());
+
+        run_lines(&mut shell, &["set   spaced  a  b", "set\ttabbed\tc d"]);
+        assert_eq!(shell.execute("get spaced"), Ok(vec!(String::from("a  b"))));
+        assert_eq!(shell.execute("get tabbed"), Ok(vec!(String::from("c d"))));
+    }
+
+    #[test]
+    fn transactions() {
+        let (_temp_db, mut shell) = temp_shell("gringotts-shell-transactions-test.gdb");
+
+        run_lines(&mut shell, &["set a 1", "begin", "set a 2", "set b 3"]);
+        assert_eq!(shell.get_prompt(), "gringotts:/ (2 pending)> ");
+        assert_eq!(shell.execute("get a"), Ok(vec!(String::from("2"))));
+        assert_eq!(shell.file.get_val(&String::from("a")), Some(String::from("1")));
+        assert_eq!(shell.execute("commit"), Ok(vec!(String::from("Committed 2 changes"))));
+        assert_eq!(shell.file.get_val(&String::from("b")), Some(String::from("3")));
+
+        run_lines(&mut shell, &["begin", "rm a"]);
+        assert!(shell.execute("begin").is_err());
+        assert_eq!(shell.execute("rollback"), Ok(vec!(String::from("Rolled back 1 changes"))));
+        assert_eq!(shell.execute("get a"), Ok(vec!(String::from("2"))));
+        assert!(shell.execute("commit").is_err());
+    }
+}
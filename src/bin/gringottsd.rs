@@ -0,0 +1,126 @@
+extern crate ansi_term;
+extern crate env_logger;
+extern crate getopts;
+extern crate gringotts;
+
+use ansi_term::Colour::*;
+use getopts::Options;
+use gringotts::*;
+use gringotts::net::server::*;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::thread;
+
+/// Where the server listens unless `--listen` says otherwise.  Only local clients can connect.
+const DEFAULT_ADDRESS: &'static str = "127.0.0.1:7420";
+
+fn main() {
+    // Initialize the environment logger;
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.reqopt("f", "database-file", "the database to serve", "FILE");
+    opts.optopt("", "listen", &format!("the address to listen on, instead of {}", DEFAULT_ADDRESS), "ADDRESS");
+    opts.optopt("", "resp-listen", "also serve Redis clients, such as redis-cli, on ADDRESS", "ADDRESS");
+    opts.optopt("", "http-listen", "also serve the HTTP/JSON API, beneath /kv/, on ADDRESS", "ADDRESS");
+    opts.optopt("", "passphrase-file", "read the passphrase for an encrypted database from FILE", "FILE");
+
+    if args[1..].iter().any(|a| a == "-h" || a == "--help") {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", Red.bold().paint(f.to_string()));
+            print_usage(&program, &opts);
+            process::exit(2);
+        }
+    };
+
+    let filename = matches.opt_str("f").unwrap();
+    let address = matches.opt_str("listen").unwrap_or(String::from(DEFAULT_ADDRESS));
+    let passphrase = match matches.opt_str("passphrase-file").map(|path| fs::read_to_string(&path)) {
+        None => None,
+        Some(Ok(contents)) => Some(String::from(contents.trim_end_matches(|c| c == '\n' || c == '\r'))),
+        Some(Err(err)) => fail(&format!("Couldn't read the passphrase: {}", err)),
+    };
+
+    if !Path::new(&filename).exists() {
+        fail(&format!("{} doesn't exist; create it with dbctl create", filename));
+    }
+
+    let opened = match passphrase {
+        Some(ref p) => dbfile::Dbfile::open_with_passphrase(&filename, p),
+        None => dbfile::Dbfile::open(&filename),
+    };
+    let file = match opened {
+        Ok(f) => f,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied && passphrase.is_none() => {
+            fail("Couldn't unlock database: The database is encrypted; use --passphrase-file")
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => fail(&format!("Couldn't unlock database: {}", err)),
+        Err(err) => fail(&format!("Couldn't open {}: {}", filename, err)),
+    };
+
+    // Every listener serves the same database, so their clients take turns with it.
+    let file = share(file);
+    let server = bind(&address, &file, Protocol::Binary);
+    let mut other_servers = Vec::new();
+    if let Some(resp_address) = matches.opt_str("resp-listen") {
+        other_servers.push(bind(&resp_address, &file, Protocol::Resp));
+    }
+    if let Some(http_address) = matches.opt_str("http-listen") {
+        other_servers.push(bind(&http_address, &file, Protocol::Http));
+    }
+
+    for other_server in other_servers {
+        thread::spawn(move || {
+            if let Err(err) = other_server.serve() {
+                fail(&format!("Stopped serving: {}", err));
+            }
+        });
+    }
+
+    if let Err(err) = server.serve() {
+        fail(&format!("Stopped serving: {}", err));
+    }
+}
+
+fn bind(address: &String, file: &SharedDbfile, protocol: Protocol) -> Server {
+    let server = match Server::bind_shared(address, file.clone(), protocol) {
+        Ok(s) => s,
+        Err(err) => fail(&format!("Couldn't listen on {}: {}", address, err)),
+    };
+
+    let name = match protocol {
+        Protocol::Binary => "gringotts",
+        Protocol::Resp => "Redis",
+        Protocol::Http => "HTTP",
+    };
+    match server.local_addr() {
+        Ok(bound) => println!("Serving {} clients on {}", name, bound),
+        Err(_) => println!("Serving {} clients on {}", name, address),
+    }
+
+    return server;
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} --database-file FILE [--listen ADDRESS] [--resp-listen ADDRESS] [--http-listen ADDRESS]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Prints the message and exits with an error.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", Red.bold().paint(message));
+    process::exit(1);
+}
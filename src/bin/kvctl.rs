@@ -0,0 +1,182 @@
+extern crate ansi_term;
+extern crate env_logger;
+extern crate getopts;
+extern crate gringotts;
+
+use ansi_term::Colour::*;
+use getopts::Options;
+use gringotts::*;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::panic;
+use std::path::Path;
+use std::process;
+
+/// The database used when neither `--dbfile` nor `GRINGOTTS_DBFILE` names one.  It's created if
+/// it doesn't exist yet.
+const DEFAULT_DBFILE: &'static str = "//data/default.gdb";
+const DBFILE_VARIABLE: &'static str = "GRINGOTTS_DBFILE";
+
+const EXIT_OK: i32 = 0;
+/// `get` found no value for the key.
+const EXIT_NOT_FOUND: i32 = 1;
+/// The command line didn't make sense.
+const EXIT_USAGE: i32 = 2;
+/// The database couldn't be opened, created or unlocked, or something went wrong using it.
+const EXIT_ERROR: i32 = 3;
+
+fn main() {
+    // Initialize the environment logger;
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("", "dbfile", &format!("the database to use, instead of ${} or {}", DBFILE_VARIABLE, DEFAULT_DBFILE), "FILE");
+    opts.optopt("", "passphrase-file", "read the passphrase for an encrypted database from FILE", "FILE");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", Red.bold().paint(f.to_string()));
+            print_usage(&program, &opts);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    let filename = get_dbfile(&matches);
+    let passphrase = match matches.opt_str("passphrase-file").map(|path| fs::read_to_string(&path)) {
+        None => None,
+        Some(Ok(contents)) => Some(String::from(contents.trim_end_matches(|c| c == '\n' || c == '\r'))),
+        Some(Err(err)) => fail(&format!("Couldn't read the passphrase: {}", err)),
+    };
+
+    let command = matches.free.get(0).map(|s| s.as_str());
+    let key = matches.free.get(1).cloned();
+    let value = matches.free.get(2).cloned();
+
+    let code = match (command, key) {
+        (Some("get"), Some(key)) if value.is_none() => run(|| get_val(&filename, &passphrase, &key)),
+        (Some("set"), Some(key)) => run(|| set_val(&filename, &passphrase, &key, value.clone())),
+        _ => {
+            print_usage(&program, &opts);
+            EXIT_USAGE
+        },
+    };
+
+    process::exit(code);
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} get KEY\n       {} set KEY [VALUE]\n\n\
+                         set reads the value from stdin if it isn't given, and prints the old value.\n\
+                         Exits with {} if get finds no value, {} for usage errors and {} for database errors.",
+                        program, program, EXIT_NOT_FOUND, EXIT_USAGE, EXIT_ERROR);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Prints the message and exits with the error code.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", Red.bold().paint(message));
+    process::exit(EXIT_ERROR);
+}
+
+/// Runs a command, treating a panic inside the database as an error.  The panic's message has
+/// already been printed by then.
+fn run<F>(command: F) -> i32 where F: FnOnce() -> i32 + panic::UnwindSafe {
+    return match panic::catch_unwind(command) {
+        Ok(code) => code,
+        Err(_) => EXIT_ERROR,
+    };
+}
+
+/// Picks the database from `--dbfile`, then the environment, then the default.
+fn get_dbfile(matches: &getopts::Matches) -> String {
+    if let Some(filename) = matches.opt_str("dbfile") {
+        return filename;
+    }
+
+    return match env::var(DBFILE_VARIABLE) {
+        Ok(ref filename) if !filename.is_empty() => filename.clone(),
+        _ => String::from(DEFAULT_DBFILE),
+    };
+}
+
+/// Opens the database, creating it and any missing directories first if it's the default one.
+fn open_db(filename: &String, passphrase: &Option<String>) -> dbfile::Dbfile {
+    let path = Path::new(filename);
+    if !path.exists() {
+        if filename != DEFAULT_DBFILE {
+            fail(&format!("{} doesn't exist", path.display()));
+        }
+
+        if let Some(dir) = path.parent() {
+            if let Err(err) = fs::create_dir_all(dir) {
+                fail(&format!("Couldn't create {}: {}", dir.display(), err));
+            }
+        }
+
+        let created = match *passphrase {
+            Some(ref p) => dbfile::Dbfile::create_with_passphrase(filename, &dbfile::DbOptions::new(), p),
+            None => dbfile::Dbfile::create(filename),
+        };
+        if let Err(err) = created {
+            fail(&format!("Couldn't create {}: {}", path.display(), err));
+        }
+    }
+
+    let opened = match *passphrase {
+        Some(ref p) => dbfile::Dbfile::open_with_passphrase(filename, p),
+        None => dbfile::Dbfile::open(filename),
+    };
+    return match opened {
+        Ok(f) => f,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied && passphrase.is_none() => {
+            fail("Couldn't unlock database: The database is encrypted; use --passphrase-file")
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => fail(&format!("Couldn't unlock database: {}", err)),
+        Err(err) => fail(&format!("Couldn't open {}: {}", path.display(), err)),
+    };
+}
+
+fn get_val(filename: &String, passphrase: &Option<String>, key: &String) -> i32 {
+    let mut file = open_db(filename, passphrase);
+
+    return match file.get_val(key) {
+        Some(value) => {
+            print!("{}", value);
+            EXIT_OK
+        },
+        None => EXIT_NOT_FOUND,
+    };
+}
+
+fn set_val(filename: &String, passphrase: &Option<String>, key: &String, value: Option<String>) -> i32 {
+    let value = match value {
+        Some(v) => v,
+        None => {
+            let mut buffer = String::new();
+            if let Err(err) = io::stdin().read_to_string(&mut buffer) {
+                fail(&format!("Couldn't read the value: {}", err));
+            }
+            buffer
+        },
+    };
+
+    let mut file = open_db(filename, passphrase);
+    match file.swap_val(key, value) {
+        Ok(Some(old)) => print!("{}", old),
+        Ok(None) => {},
+        Err(err) => fail(&err.get_message()),
+    }
+
+    return EXIT_OK;
+}
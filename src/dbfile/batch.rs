@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use dbfile::keychain::*;
+
+/// A group of sets and deletes that `Dbfile::write_batch` applies all at once.  Changes are kept
+/// sorted by keychain, so that each subtree is only walked down to once, and a later change to a
+/// key replaces an earlier one.
+pub struct WriteBatch {
+    changes: BTreeMap<(Vec<String>, String), (String, Option<String>)>,
+}
+
+/// One change in a batch: the full key, the keys leading to its level of the keychain, the key
+/// within that level, and its new value, or None to delete it.
+pub struct BatchChange {
+    pub key: String,
+    pub address: Vec<String>,
+    pub final_key: String,
+    pub value: Option<String>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        return WriteBatch {
+            changes: BTreeMap::new(),
+        };
+    }
+
+    pub fn set(&mut self, key: &String, val: String) {
+        self.add(key, Some(val));
+    }
+
+    pub fn delete(&mut self, key: &String) {
+        self.add(key, None);
+    }
+
+    fn add(&mut self, key: &String, val: Option<String>) {
+        let keychain = KeyChain::parse(key);
+        self.changes.insert((keychain.as_vec(), keychain.get_final_key()), (key.clone(), val));
+    }
+
+    /// Returns the batch's change to the key, if it has one: its new value, or None if it's being
+    /// deleted.
+    pub fn get(&self, key: &String) -> Option<Option<String>> {
+        let keychain = KeyChain::parse(key);
+        return self.changes.get(&(keychain.as_vec(), keychain.get_final_key())).map(|&(_, ref value)| value.clone());
+    }
+
+    pub fn len(&self) -> usize {
+        return self.changes.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.changes.is_empty();
+    }
+
+    /// Returns the changes ordered by keychain, so that the changes to each level are together.
+    pub fn into_changes(self) -> Vec<BatchChange> {
+        return self.changes.into_iter().map(|((address, final_key), (key, value))| BatchChange {
+            key: key,
+            address: address,
+            final_key: final_key,
+            value: value,
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordering_by_keychain() {
+        let mut batch = WriteBatch::new();
+        batch.set(&String::from("b/x"), String::from("1"));
+        batch.set(&String::from("a"), String::from("2"));
+        batch.set(&String::from("a/z"), String::from("3"));
+        batch.delete(&String::from("a/y"));
+        batch.set(&String::from("b/x"), String::from("4"));
+        assert_eq!(batch.get(&String::from("b/x")), Some(Some(String::from("4"))));
+        assert_eq!(batch.get(&String::from("a/y")), Some(None));
+        assert_eq!(batch.get(&String::from("q")), None);
+
+        let changes = batch.into_changes();
+        let keys: Vec<&str> = changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!("a", "a/y", "a/z", "b/x"));
+        assert_eq!(changes[1].value, None);
+        assert_eq!(changes[3].value, Some(String::from("4")));
+    }
+}
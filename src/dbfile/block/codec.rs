@@ -0,0 +1,159 @@
+// Every integer stored on disk goes through these functions, so that a file is laid out the same
+// way no matter which architecture wrote it.  Everything is little-endian.
+//
+// Inside a KVSet, block pointers, lengths, integers and expiry times are all varints.  The fields
+// of the block header, such as the body size, stay fixed width, since they sit at fixed offsets
+// in a header that's the same size either way; so do the slots of a slotted page, which have to
+// be a fixed width to be binary searched.
+
+pub fn encode_u32(number: u32) -> Vec<u8> {
+    return number.to_le_bytes().to_vec();
+}
+
+/// Decodes the first four bytes as a u32, or returns None if there aren't enough of them.
+pub fn decode_u32(bytes: &[u8]) -> Option<u32> {
+    if (bytes.len() < 4) {
+        return None;
+    }
+
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&bytes[0..4]);
+    return Some(u32::from_le_bytes(buffer));
+}
+
+pub fn encode_u64(number: u64) -> Vec<u8> {
+    return number.to_le_bytes().to_vec();
+}
+
+/// Decodes the first eight bytes as a u64, or returns None if there aren't enough of them.
+pub fn decode_u64(bytes: &[u8]) -> Option<u64> {
+    if (bytes.len() < 8) {
+        return None;
+    }
+
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(&bytes[0..8]);
+    return Some(u64::from_le_bytes(buffer));
+}
+
+/// Encodes a number in as few bytes as it needs, seven bits at a time, lowest bits first.  The
+/// high bit of each byte says whether another byte follows, much like UTF-8 continuation bytes.
+/// Numbers below 128 take a single byte, and a full u64 takes ten.
+pub fn encode_varint(number: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(10);
+    let mut remaining = number;
+
+    while (remaining >= 0x80) {
+        bytes.push((remaining as u8 & 0x7f) | 0x80);
+        remaining >>= 7;
+    }
+    bytes.push(remaining as u8);
+
+    return bytes;
+}
+
+/// How many bytes `encode_varint` takes to store the number.
+pub fn varint_length(number: u64) -> usize {
+    let mut length = 1;
+    let mut remaining = number >> 7;
+    while (remaining > 0) {
+        length += 1;
+        remaining >>= 7;
+    }
+
+    return length;
+}
+
+/// Decodes a varint from the start of the bytes, returning the number along with how many bytes
+/// it took up.  Returns None if the bytes end early or the number doesn't fit in a u64.
+pub fn decode_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut number: u64 = 0;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        if (i >= 10 || (i == 9 && *byte > 1)) {
+            return None;
+        }
+
+        number |= ((*byte & 0x7f) as u64) << (7 * i);
+        if (*byte & 0x80 == 0) {
+            return Some((number, i + 1));
+        }
+    }
+
+    return None;
+}
+
+/// Encodes a signed number as a varint, interleaving positive and negative numbers so that small
+/// ones of either sign stay short: 0, -1, 1, -2 are stored as 0, 1, 2, 3.
+pub fn encode_signed_varint(number: i64) -> Vec<u8> {
+    return encode_varint(((number << 1) ^ (number >> 63)) as u64);
+}
+
+pub fn signed_varint_length(number: i64) -> usize {
+    return varint_length(((number << 1) ^ (number >> 63)) as u64);
+}
+
+pub fn decode_signed_varint(bytes: &[u8]) -> Option<(i64, usize)> {
+    let (zigzag, length) = decode_varint(bytes)?;
+    return Some((((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64), length));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn little_endian_layout() {
+        assert_eq!(encode_u32(0x01020304), vec!(4, 3, 2, 1));
+        assert_eq!(encode_u64(22), vec!(22, 0, 0, 0, 0, 0, 0, 0));
+
+        assert_eq!(decode_u32(&encode_u32(0xdeadbeef)), Some(0xdeadbeef));
+        assert_eq!(decode_u64(&encode_u64(u64::max_value() - 1)), Some(u64::max_value() - 1));
+    }
+
+    #[test]
+    fn short_input() {
+        assert_eq!(decode_u32(&vec!(1, 2, 3)), None);
+        assert_eq!(decode_u64(&vec!()), None);
+    }
+
+    #[test]
+    fn varint_lengths() {
+        assert_eq!(encode_varint(0), vec!(0));
+        assert_eq!(encode_varint(22), vec!(22));
+        assert_eq!(encode_varint(300), vec!(0xac, 0x02));
+        assert_eq!(encode_varint(u64::max_value()).len(), 10);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for number in vec!(0, 1, 127, 128, 16383, 16384, 1 << 32, u64::max_value()) {
+            let bytes = encode_varint(number);
+            assert_eq!(decode_varint(&bytes), Some((number, bytes.len())));
+            assert_eq!(varint_length(number), bytes.len());
+        }
+
+        // Trailing bytes are left alone.
+        assert_eq!(decode_varint(&vec!(0xac, 0x02, 0xff)), Some((300, 2)));
+    }
+
+    #[test]
+    fn signed_varint_round_trip() {
+        assert_eq!(encode_signed_varint(0), vec!(0));
+        assert_eq!(encode_signed_varint(-1), vec!(1));
+        assert_eq!(encode_signed_varint(1), vec!(2));
+
+        for number in vec!(0, -64, 63, -65, 1 << 40, i64::min_value(), i64::max_value()) {
+            let bytes = encode_signed_varint(number);
+            assert_eq!(decode_signed_varint(&bytes), Some((number, bytes.len())));
+            assert_eq!(signed_varint_length(number), bytes.len());
+        }
+    }
+
+    #[test]
+    fn invalid_varints() {
+        assert_eq!(decode_varint(&vec!()), None);
+        assert_eq!(decode_varint(&vec!(0x80, 0x80)), None);
+        assert_eq!(decode_varint(&vec!(0xff; 11)), None);
+    }
+}
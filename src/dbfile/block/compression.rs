@@ -0,0 +1,54 @@
+use lz4_flex::block::{compress_prepend_size, decompress as lz4_decompress};
+use dbfile::block::codec::*;
+use error::*;
+
+// Block bodies are compressed with LZ4, with the uncompressed length written in front of them as
+// a little-endian u32.
+
+pub fn compress(bytes: &Vec<u8>) -> Vec<u8> {
+    return compress_prepend_size(bytes);
+}
+
+/// Decompresses a block body, which can't be any bigger than `max_size` once it's decompressed.
+/// The length in front of it is checked against that before anything is allocated for it.
+pub fn decompress(bytes: &Vec<u8>, max_size: usize) -> Result<Vec<u8>, CorruptDataError> {
+    let size = match decode_u32(bytes) {
+        Some(size) => size as usize,
+        None => return Err(CorruptDataError::new("Compressed block body is missing its length")),
+    };
+    if (size > max_size) {
+        let message = format!("Compressed block body claims to be {} bytes, which won't fit in a block", size);
+        return Err(CorruptDataError::new(&message));
+    }
+
+    return match lz4_decompress(&bytes[4..], size) {
+        Ok(decompressed) => Ok(decompressed),
+        Err(e) => Err(CorruptDataError::new(&format!("Couldn't decompress block body: {}", e))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let bytes = "config/service-a/setting=value\n".repeat(50).into_bytes();
+        let compressed = compress(&bytes);
+
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed, 4096).ok(), Some(bytes));
+    }
+
+    #[test]
+    fn corrupt_input() {
+        assert!(decompress(&vec!(100, 0, 0, 0, 1, 2, 3), 4096).is_err());
+    }
+
+    #[test]
+    fn oversized_length() {
+        let compressed = compress(&vec!(0; 8192));
+        assert!(decompress(&compressed, 4096).is_err());
+        assert!(decompress(&vec!(255, 255, 255, 255, 0), 4096).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::aead::rand_core::RngCore;
+use dbfile::block::codec::*;
+use error::*;
+
+// Encrypted block bodies are sealed with ChaCha20-Poly1305 under a key derived from the
+// passphrase with Argon2id.  Each body is written as a fresh random nonce followed by the
+// ciphertext and its tag, and the block number is passed as associated data so that a body
+// can't be moved into a different block without failing to decrypt.
+//
+// The header keeps the salt the key was derived from, along with a key check: sixteen zero bytes
+// sealed under the key.  Each key gets its own salt, so the fixed nonce used for the check is
+// never reused with the same key.
+
+pub const SALT_LENGTH: usize = 16;
+pub const KEY_CHECK_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+/// How many bytes longer an encrypted body is than the plaintext it was sealed from.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_LENGTH + TAG_LENGTH;
+const KEY_CHECK_CONTEXT: &'static [u8] = b"gringotts key check";
+
+#[derive(Clone)]
+pub struct BlockKey {
+    cipher: ChaCha20Poly1305,
+}
+
+/// Returns a new random salt to derive a key from.
+pub fn new_salt() -> Vec<u8> {
+    let mut salt = vec![0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    return salt;
+}
+
+impl BlockKey {
+    pub fn derive(passphrase: &str, salt: &[u8]) -> Result<BlockKey, InvalidInputError> {
+        let mut key_bytes = [0u8; 32];
+        if let Err(e) = Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key_bytes) {
+            return Err(InvalidInputError::new(&format!("Couldn't derive a key from the passphrase: {}", e)));
+        }
+
+        return Ok(BlockKey {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key_bytes)),
+        });
+    }
+
+    pub fn encrypt(&self, block_number: u64, bytes: &Vec<u8>) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let associated_data = encode_u64(block_number);
+        let payload = Payload {
+            msg: bytes,
+            aad: &associated_data,
+        };
+
+        let mut encrypted = nonce.to_vec();
+        match self.cipher.encrypt(&nonce, payload) {
+            Ok(mut ciphertext) => encrypted.append(&mut ciphertext),
+            Err(_) => panic!("Couldn't encrypt block {}", block_number),
+        }
+
+        return encrypted;
+    }
+
+    pub fn decrypt(&self, block_number: u64, bytes: &Vec<u8>) -> Result<Vec<u8>, CorruptDataError> {
+        let msg = format!("Couldn't decrypt block {}", block_number);
+        if (bytes.len() < NONCE_LENGTH) {
+            return Err(CorruptDataError::new(&msg));
+        }
+
+        let associated_data = encode_u64(block_number);
+        let payload = Payload {
+            msg: &bytes[NONCE_LENGTH..],
+            aad: &associated_data,
+        };
+
+        return match self.cipher.decrypt(Nonce::from_slice(&bytes[..NONCE_LENGTH]), payload) {
+            Ok(decrypted) => Ok(decrypted),
+            Err(_) => Err(CorruptDataError::new(&msg)),
+        };
+    }
+
+    /// The value stored in the header to tell whether a passphrase is the right one.
+    pub fn key_check(&self) -> Vec<u8> {
+        let payload = Payload {
+            msg: &[0u8; 16],
+            aad: KEY_CHECK_CONTEXT,
+        };
+
+        return match self.cipher.encrypt(Nonce::from_slice(&[0u8; NONCE_LENGTH]), payload) {
+            Ok(check) => check,
+            Err(_) => panic!("Couldn't compute the key check"),
+        };
+    }
+
+    pub fn matches_key_check(&self, check: &[u8]) -> bool {
+        return self.key_check().as_slice() == check;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive(passphrase: &str, salt: &[u8]) -> BlockKey {
+        return match BlockKey::derive(passphrase, salt) {
+            Ok(key) => key,
+            Err(e) => panic!("{}", e.get_message()),
+        };
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = derive("correct horse", &new_salt());
+        let bytes = "some secret value".to_string().into_bytes();
+        let encrypted = key.encrypt(7, &bytes);
+
+        assert!(encrypted.len() > bytes.len());
+        assert_eq!(key.decrypt(7, &encrypted).ok(), Some(bytes));
+        assert!(key.decrypt(8, &encrypted).is_err());
+    }
+
+    #[test]
+    fn key_checks() {
+        let salt = new_salt();
+        let key = derive("correct horse", &salt);
+        let wrong_key = derive("battery staple", &salt);
+        let check = key.key_check();
+
+        assert_eq!(check.len(), KEY_CHECK_LENGTH);
+        assert!(derive("correct horse", &salt).matches_key_check(&check));
+        assert!(!wrong_key.matches_key_check(&check));
+    }
+}
@@ -0,0 +1,148 @@
+use dbfile::block::codec::*;
+use std::collections::BTreeMap;
+use error::*;
+use super::Entry;
+
+// The escaped format was used for KVSets before the slotted format.  Records are written one after
+// another, with 0x00 as an escape byte: "0,0" separates records, "0,1" starts a value, "0,3"
+// starts a pointer, and "0,2" stands for a literal 0x00.  It's only read now, so that older
+// databases can be migrated.
+
+enum Character {
+    Regular(u8),
+    RecordSeperator,
+    ValueStart,
+    PointerStart
+}
+
+fn get_character(bytes: &mut Vec<u8>) -> Option<Character> {
+    if (bytes.len() == 0) {
+        return None;
+    }
+
+    let byte = bytes.pop();
+
+    match byte {
+        Some(0) => {
+            match bytes.pop() {
+                Some(0) => return Some(Character::RecordSeperator),
+                Some(1) => return Some(Character::ValueStart),
+                Some(2) => return Some(Character::Regular(0)),
+                Some(3) => return Some(Character::PointerStart),
+                _ => panic!("Invalid character sequence")
+            }
+        },
+        Some(ch) => return Some(Character::Regular(ch)),
+        None => return None,
+    }
+}
+
+fn get_value_vec(bytes: &mut Vec<u8>) -> Vec<u8> {
+    let mut value_bytes = Vec::new();
+    loop {
+        match get_character(bytes) {
+            Some(Character::Regular(byte)) => value_bytes.push(byte),
+            Some(ch) => {
+                // Put back what you have taken
+                let mut bytes_to_put_back = ch.get_value();
+                // Since we're working on a reversed buffer, we'll need to reverse the buffer first;
+                bytes_to_put_back.reverse();
+                // Now, pop it back on.
+                bytes.append(&mut bytes_to_put_back);
+                break; // we're done here.
+            },
+            None => break,
+        }
+    }
+    return value_bytes;
+}
+
+
+impl Character {
+    fn get_value(&self) -> Vec<u8> {
+        let mut duple: Vec<u8> = vec!(0);
+        match *self {
+            Character::Regular(0)      => duple.push(2),
+            Character::Regular(val)    => duple.push(val),
+            Character::RecordSeperator => duple.push(0),
+            Character::ValueStart      => duple.push(1),
+            Character::PointerStart    => duple.push(3),
+        }
+        return duple;
+    }
+}
+
+/// Decodes an escaped KVSet.  Pointers are varints if `varint_pointers` is set, and eight
+/// little-endian bytes otherwise.
+pub fn decode(bytes3: &Vec<u8>, varint_pointers: bool) -> Result<BTreeMap<String, Entry>, CorruptDataError> {
+    let mut entries = BTreeMap::new();
+    let mut bytes = bytes3.clone();
+    let length = bytes.len();
+
+    // If the byte vector is empty, we're good.
+    if (length == 0) {
+        return Ok(entries);
+    }
+
+    bytes.reverse();
+
+    let mut key_buffer = Vec::new();
+    let mut val_buffer = Vec::new();
+    let mut ptr_buffer = Vec::new();
+    let mut has_value = false;
+
+    while(bytes.len() > 0 || key_buffer.len() > 0) {
+        match get_character(&mut bytes) {
+            Some(Character::ValueStart)   => {
+                has_value = true;
+                val_buffer.append(&mut get_value_vec(&mut bytes));
+            },
+            Some(Character::PointerStart) => ptr_buffer.append(&mut get_value_vec(&mut bytes)),
+            Some(Character::RecordSeperator) | None => {
+                let key = match String::from_utf8(key_buffer.clone()) {
+                    Ok(k) => k,
+                    Err(_) => return Err(CorruptDataError::new("Key is not valid UTF-8")),
+                };
+
+                let mut entry = Entry::new();
+
+                // Keys that only point to a subtree don't carry a value.
+                if (has_value) {
+                    match String::from_utf8(val_buffer.clone()) {
+                        Ok(v) => entry.value = Some(v),
+                        Err(_) => return Err(CorruptDataError::new(&format!("Value for key {} is not valid UTF-8", key))),
+                    }
+                }
+
+                if (ptr_buffer.len() > 0) {
+                    let block_number = match varint_pointers {
+                        false if ptr_buffer.len() == 8 => decode_u64(&ptr_buffer),
+                        true => match decode_varint(&ptr_buffer) {
+                            Some((n, length)) if length == ptr_buffer.len() => Some(n),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    match block_number {
+                        Some(n) => entry.pointer = Some(n),
+                        None => return Err(CorruptDataError::new(&format!("Invalid pointer for key {}", key))),
+                    };
+                }
+
+                entries.insert(key, entry);
+
+                key_buffer.clear();
+                val_buffer.clear();
+                ptr_buffer.clear();
+                has_value = false;
+            },
+            Some(Character::Regular(ch)) => {
+                bytes.push(ch);
+                key_buffer.append(&mut get_value_vec(&mut bytes));
+            }
+        }
+    }
+
+    return Ok(entries);
+}
@@ -0,0 +1,765 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use error::*;
+
+mod escaped;
+mod slotted;
+
+use self::slotted::SlottedPage;
+
+/// The keys stored in a block, each with a value, a pointer to the block holding its subtree, or
+/// both.  A value is either a string or an integer, and can be given a time after which it expires
+/// and is treated as though it had never been set.
+///
+/// A KVSet read from a slotted page keeps the raw page and answers lookups straight out of it, so
+/// that reading a block never has to decode all of its records.  The records are only decoded into
+/// a map the first time the set is changed.
+pub struct KVSet {
+    page: Option<Vec<u8>>,
+    page_format: KVSetFormat,
+    entries: BTreeMap<String, Entry>
+}
+
+struct Entry {
+    value: Option<String>,
+    integer: Option<i64>,
+    expires: Option<u64>,
+    pointer: Option<u64>
+}
+
+impl Entry {
+    fn new() -> Entry {
+        return Entry {
+            value: None,
+            integer: None,
+            expires: None,
+            pointer: None
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.value.is_none() && self.integer.is_none() && self.pointer.is_none();
+    }
+
+    fn clear_value(&mut self) -> Option<String> {
+        self.integer = None;
+        self.expires = None;
+        return self.value.take();
+    }
+}
+
+/// The current time in milliseconds since the Unix epoch, which is how expiry times are stored.
+pub fn now_millis() -> u64 {
+    return match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() * 1000 + (d.subsec_millis() as u64),
+        Err(_) => 0,
+    };
+}
+
+/// A copy of the value a key held, including its type and when it expires, so that it can be put
+/// back.
+#[derive(Clone)]
+pub struct SavedValue {
+    value: Option<String>,
+    integer: Option<i64>,
+    expires: Option<u64>
+}
+
+impl SavedValue {
+    pub fn has_value(&self) -> bool {
+        return self.value.is_some() || self.integer.is_some();
+    }
+
+    /// The same value, set to expire at the given time instead.
+    pub fn expiring_at(self, expires: u64) -> SavedValue {
+        return SavedValue {
+            expires: Some(expires),
+            ..self
+        };
+    }
+}
+
+fn is_live(expires: Option<u64>) -> bool {
+    return match expires {
+        Some(t) => t > now_millis(),
+        None => true,
+    };
+}
+
+/// The ways a KVSet can be laid out in a block body.  Each block records which one it was written
+/// with, so that blocks from older versions can still be read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KVSetFormat {
+    /// Escaped records with pointers stored as eight little-endian bytes.
+    FixedPointers,
+    /// Escaped records with pointers stored as varints.
+    VarintPointers,
+    /// Length-prefixed records behind a table of slots, sorted by key.
+    Slotted,
+    /// Slotted records with prefix compressed keys, for databases created with that option.
+    SlottedPrefix,
+}
+
+pub const CURRENT_KVSET_FORMAT: KVSetFormat = KVSetFormat::Slotted;
+
+impl KVSetFormat {
+    pub fn get_code(&self) -> u8 {
+        return match *self {
+            KVSetFormat::FixedPointers  => 0,
+            KVSetFormat::VarintPointers => 1,
+            KVSetFormat::Slotted        => 2,
+            KVSetFormat::SlottedPrefix  => 3,
+        };
+    }
+
+    pub fn from_code(code: u8) -> Result<KVSetFormat, CorruptDataError> {
+        return match code {
+            0 => Ok(KVSetFormat::FixedPointers),
+            1 => Ok(KVSetFormat::VarintPointers),
+            2 => Ok(KVSetFormat::Slotted),
+            3 => Ok(KVSetFormat::SlottedPrefix),
+            c => Err(CorruptDataError::new(&format!("Unknown KVSet format: {}", c))),
+        };
+    }
+}
+
+impl KVSet {
+    pub fn new() -> KVSet {
+        return KVSet::from_entries(BTreeMap::new());
+    }
+
+    fn from_entries(entries: BTreeMap<String, Entry>) -> KVSet {
+        return KVSet {
+            page: None,
+            page_format: CURRENT_KVSET_FORMAT,
+            entries: entries
+        };
+    }
+
+    fn get_page(&self) -> Option<SlottedPage<'_>> {
+        let compressed = self.page_format == KVSetFormat::SlottedPrefix;
+        return match self.page {
+            // The page was checked when the set was deserialized.
+            Some(ref bytes) => SlottedPage::new(bytes, compressed).ok(),
+            None => None,
+        };
+    }
+
+    fn decode_page(page: &SlottedPage) -> BTreeMap<String, Entry> {
+        let mut entries = BTreeMap::new();
+        for record in page.records() {
+            entries.insert(record.key.into_owned(), Entry {
+                value: record.value.map(String::from),
+                integer: record.integer,
+                expires: record.expires,
+                pointer: record.pointer
+            });
+        }
+
+        return entries;
+    }
+
+    /// Decodes the raw page, if there is one, so that the set can be changed.
+    fn materialize(&mut self) {
+        let entries = match self.get_page() {
+            Some(page) => KVSet::decode_page(&page),
+            None => return,
+        };
+
+        self.page = None;
+        self.entries = entries;
+    }
+
+    pub fn get_keys(&self) -> Vec<String> {
+        if let Some(page) = self.get_page() {
+            return page.records().map(|record| record.key.into_owned()).collect();
+        }
+
+        return self.entries.keys().cloned().collect();
+    }
+
+    pub fn get_last_key(&self) -> Option<String> {
+        if let Some(page) = self.get_page() {
+            return page.last_record().map(|record| record.key.into_owned());
+        }
+
+        return self.entries.keys().next_back().cloned();
+    }
+
+    pub fn split(&mut self) -> KVSet {
+        self.materialize();
+
+        let split_point = self.entries.len() / 2;
+        let split_key = match self.entries.keys().nth(split_point) {
+            Some(k) => k.clone(),
+            None => return KVSet::new(),
+        };
+
+        return KVSet::from_entries(self.entries.split_off(&split_key));
+    }
+
+    /// Adds a new key/value pair to the KVSet.  The return value will be a String, if there was
+    /// a previous value and this is therefore an update, or None, if this was a true insert.
+    pub fn put(&mut self, key: &String, value: String) -> Option<String> {
+        self.materialize();
+        let entry = self.entries.entry(key.clone()).or_insert_with(Entry::new);
+        entry.integer = None;
+        entry.expires = None;
+        return entry.value.replace(value); // this returns the old value or None
+    }
+
+    /// Adds a value that expires at the given time, in milliseconds since the Unix epoch.
+    pub fn put_with_expiry(&mut self, key: &String, value: String, expires: u64) -> Option<String> {
+        let old = self.put(key, value);
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.expires = Some(expires);
+        }
+
+        return old;
+    }
+
+    /// Returns when the key's value expires, if it has a value that does.
+    pub fn get_expiry(&self, key: &String) -> Option<u64> {
+        if let Some(page) = self.get_page() {
+            return page.find(key).and_then(|record| record.expires);
+        }
+
+        return match self.entries.get(key) {
+            Some(entry) => entry.expires,
+            None => None,
+        };
+    }
+
+    /// Removes every value that expired at or before `now`, returning how many there were.  Keys
+    /// that still point to a subtree are kept.
+    pub fn remove_expired(&mut self, now: u64) -> u64 {
+        let is_expired = |expires: Option<u64>| expires.map_or(false, |t| t <= now);
+
+        // Don't decode an untouched page unless there's something to remove from it.
+        if let Some(page) = self.get_page() {
+            if (!page.records().any(|record| is_expired(record.expires))) {
+                return 0;
+            }
+        }
+
+        self.materialize();
+        let expired: Vec<String> = self.entries.iter()
+            .filter(|&(_, entry)| is_expired(entry.expires))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired.iter() {
+            self.remove_field(key, |entry| {
+                entry.clear_value();
+                Some(())
+            });
+        }
+
+        return expired.len() as u64;
+    }
+
+    /// Stores an integer as the key's value, in place of any string value it had.
+    pub fn put_integer(&mut self, key: &String, value: i64) -> Option<i64> {
+        self.materialize();
+        let entry = self.entries.entry(key.clone()).or_insert_with(Entry::new);
+        entry.value = None;
+        entry.expires = None;
+        return entry.integer.replace(value);
+    }
+
+    /// Returns the key's value if it's an integer.
+    pub fn get_integer(&self, key: &String) -> Option<i64> {
+        if let Some(page) = self.get_page() {
+            return page.find(key).filter(|record| is_live(record.expires)).and_then(|record| record.integer);
+        }
+
+        return match self.entries.get(key) {
+            Some(entry) if is_live(entry.expires) => entry.integer,
+            _ => None,
+        };
+    }
+
+    /// Returns the key's string value, unless it has expired.
+    pub fn get(&self, key: &String) -> Option<&str> {
+        if let Some(page) = self.get_page() {
+            return page.find(key).filter(|record| is_live(record.expires)).and_then(|record| record.value);
+        }
+
+        return match self.entries.get(key) {
+            Some(entry) if is_live(entry.expires) => entry.value.as_ref().map(|v| v.as_str()),
+            _ => None,
+        };
+    }
+
+    /// Copies the key's value so that `restore_value` can put it back, even if it has expired.
+    pub fn save_value(&mut self, key: &String) -> SavedValue {
+        self.materialize();
+        return match self.entries.get(key) {
+            Some(entry) => SavedValue {
+                value: entry.value.clone(),
+                integer: entry.integer,
+                expires: entry.expires
+            },
+            None => SavedValue { value: None, integer: None, expires: None },
+        };
+    }
+
+    /// Puts back a value copied by `save_value`, in place of whatever the key holds now.  Its
+    /// block pointer, if it has one, is left alone.
+    pub fn restore_value(&mut self, key: &String, saved: SavedValue) {
+        self.materialize();
+        if (saved.value.is_none() && saved.integer.is_none()) {
+            self.remove_field(key, |entry| entry.clear_value());
+            return;
+        }
+
+        let entry = self.entries.entry(key.clone()).or_insert_with(Entry::new);
+        entry.value = saved.value;
+        entry.integer = saved.integer;
+        entry.expires = saved.expires;
+    }
+
+    /// Removes the key's value, returning it if it was a string.  Integer values are removed too.
+    pub fn delete(&mut self, key: &String) -> Option<String> {
+        self.materialize();
+        return self.remove_field(key, |entry| entry.clear_value());
+    }
+
+    pub fn put_block_ref(&mut self, key: &String, value: u64) -> Option<u64> {
+        self.materialize();
+        let entry = self.entries.entry(key.clone()).or_insert_with(Entry::new);
+        return entry.pointer.replace(value);
+    }
+
+    pub fn get_block_ref(&self, key: &String) -> Option<u64> {
+        if let Some(page) = self.get_page() {
+            return page.find(key).and_then(|record| record.pointer);
+        }
+
+        return match self.entries.get(key) {
+            Some(entry) => entry.pointer,
+            None => None,
+        };
+    }
+
+    pub fn delete_block_ref(&mut self, key: &String) -> Option<u64> {
+        self.materialize();
+        return self.remove_field(key, |entry| entry.pointer.take());
+    }
+
+    /// Takes one field out of a key's entry, dropping the entry once it has nothing left in it.
+    fn remove_field<T, F>(&mut self, key: &String, take: F) -> Option<T> where F: FnOnce(&mut Entry) -> Option<T> {
+        let (removed, is_empty) = match self.entries.get_mut(key) {
+            Some(entry) => {
+                let removed = take(entry);
+                (removed, entry.is_empty())
+            },
+            None => return None,
+        };
+
+        if (is_empty) {
+            self.entries.remove(key);
+        }
+
+        return removed;
+    }
+
+    pub fn deserialize(bytes: Vec<u8>) -> Result<KVSet, CorruptDataError> {
+        return KVSet::deserialize_format(bytes, CURRENT_KVSET_FORMAT);
+    }
+
+    /// Reads a set out of a block body.  Slotted pages keep the body as it is, and read it in
+    /// place.
+    pub fn deserialize_format(bytes: Vec<u8>, format: KVSetFormat) -> Result<KVSet, CorruptDataError> {
+        // An empty body is an empty set, whatever the format.
+        if (bytes.len() == 0) {
+            return Ok(KVSet::new());
+        }
+
+        return match format {
+            KVSetFormat::FixedPointers  => Ok(KVSet::from_entries(escaped::decode(&bytes, false)?)),
+            KVSetFormat::VarintPointers => Ok(KVSet::from_entries(escaped::decode(&bytes, true)?)),
+            KVSetFormat::Slotted | KVSetFormat::SlottedPrefix => {
+                SlottedPage::new(&bytes, format == KVSetFormat::SlottedPrefix)?;
+                Ok(KVSet {
+                    page: Some(bytes),
+                    page_format: format,
+                    entries: BTreeMap::new()
+                })
+            },
+        };
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        return self.serialize_format(CURRENT_KVSET_FORMAT);
+    }
+
+    /// How long `serialize_format` would make the set, which is cheaper to work out than
+    /// serializing it.
+    pub fn serialized_len(&self, format: KVSetFormat) -> usize {
+        let compressed = format == KVSetFormat::SlottedPrefix;
+        if let Some(ref page) = self.page {
+            if (self.page_format == format) {
+                return page.len();
+            }
+        }
+
+        if let Some(page) = self.get_page() {
+            let entries = KVSet::decode_page(&page);
+            return slotted::encoded_len(entries.iter(), entries.len(), compressed);
+        }
+
+        return slotted::encoded_len(self.entries.iter(), self.entries.len(), compressed);
+    }
+
+    /// Serializes the set in the given format, which must be one of the slotted ones.
+    pub fn serialize_format(&self, format: KVSetFormat) -> Vec<u8> {
+        // An untouched page can be written back as it is.
+        if let Some(ref page) = self.page {
+            if (self.page_format == format) {
+                return page.clone();
+            }
+        }
+
+        let compressed = format == KVSetFormat::SlottedPrefix;
+        if let Some(page) = self.get_page() {
+            let entries = KVSet::decode_page(&page);
+            return slotted::encode(entries.iter(), entries.len(), compressed);
+        }
+
+        return slotted::encode(self.entries.iter(), self.entries.len(), compressed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+
+    #[test]
+    fn basic_serialization() {
+        let mut keyset = KVSet::new();
+        keyset.put(&String::from("yes"),   String::from("no"));
+        keyset.put(&String::from("hello"), String::from("goodbye"));
+
+        let serialized_vector = vec!(
+            2,
+            2, 0,
+            0, 0,
+            15, 0,
+            1, 5, 104, 101, 108, 108, 111,
+            7, 103, 111, 111, 100, 98, 121, 101,
+            1, 3, 121, 101, 115,
+            2, 110, 111,
+        );
+
+        assert_eq!(keyset.serialize(), serialized_vector);
+    }
+
+    #[test]
+    fn ignoring_bytes_after_the_last_record() {
+        let mut keyset = KVSet::new();
+        keyset.put(&String::from("yes"),   String::from("no"));
+        keyset.put(&String::from("hello"), String::from("goodbye"));
+
+        // These would read as a record for "x" if the page's record count weren't kept to.
+        let mut bytes = keyset.serialize_format(KVSetFormat::Slotted);
+        bytes.extend_from_slice(&[0, 1, 120]);
+
+        let keyset = KVSet::deserialize_format(bytes, KVSetFormat::Slotted).ok().unwrap();
+        assert_eq!(keyset.get_keys(), vec!("hello", "yes"));
+    }
+
+    #[test]
+    fn escaped_deserialization() {
+        let mut bytes = vec!(
+            104, 101, 108, 108, 111,
+            0, 1,
+            103, 111, 111, 100, 98, 121, 101,
+            0, 0,
+            121, 101, 115,
+            0, 1,
+            110, 111,
+        );
+
+        let keyset = match KVSet::deserialize_format(bytes, KVSetFormat::VarintPointers) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset.get_keys(), vec!("hello", "yes"));
+        assert_eq!(keyset.get(&String::from("hello")), Some("goodbye"));
+        assert_eq!(keyset.get(&String::from("yes")), Some("no"));
+    }
+
+    #[test]
+    fn serialization_block_ref() {
+        let key = String::from("key");
+        let blockno: u64 = 22;
+        let value = String::from("value");
+
+        let mut keyset = KVSet::new();
+        keyset.put(&key, value.clone());
+        keyset.put_block_ref(&key, blockno);
+
+        let vector = keyset.serialize();
+        let keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        match keyset2.get(&key) {
+            Some(val) => assert_eq!(&value.clone(), val),
+            None => panic!("Expected a value from keyset")
+        };
+
+        match keyset2.get_block_ref(&key) {
+            Some(val) => assert_eq!(blockno, val),
+            None => panic!("Expected a value from keyset")
+        }
+    }
+
+    #[test]
+    fn serialization_multiple_keys() {
+        let key  = String::from("key");
+        let key2 = String::from("key2");
+        let value  = String::from("value");
+        let value2 = String::from("value2");
+        let blockno: u64 = 22;
+
+        let mut keyset = KVSet::new();
+        keyset.put(&key,  value.clone());
+        keyset.put(&key2, value2.clone());
+        keyset.put_block_ref(&key, blockno);
+
+        let vector = keyset.serialize();
+        let keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        match keyset2.get(&key) {
+            Some(val) => assert_eq!(&value.clone(), val),
+            None => panic!("Expected a value from keyset")
+        };
+
+        match keyset2.get(&key2) {
+            Some(val) => assert_eq!(&value2.clone(), val),
+            None => panic!("Expected a value from keyset")
+        };
+
+        match keyset2.get_block_ref(&key) {
+            Some(val) => assert_eq!(blockno, val),
+            None => panic!("Expected a value from keyset")
+        }
+    }
+
+    #[test]
+    fn deserialization_varint_pointers() {
+        let key = String::from("key");
+        let mut bytes = vec!(107, 101, 121, 0, 3, 0xac, 0x02);
+
+        let keyset = match KVSet::deserialize_format(bytes, KVSetFormat::VarintPointers) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset.get_block_ref(&key), Some(300));
+    }
+
+    #[test]
+    fn lookups_on_an_untouched_page() {
+        let mut keyset = KVSet::new();
+        for i in 0..100 {
+            keyset.put(&format!("key{:03}", i), format!("value{}", i));
+        }
+        keyset.put_block_ref(&String::from("key050"), 7);
+
+        let keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert!(keyset2.page.is_some());
+        assert_eq!(keyset2.get(&String::from("key000")), Some("value0"));
+        assert_eq!(keyset2.get(&String::from("key099")), Some("value99"));
+        assert_eq!(keyset2.get(&String::from("key100")), None);
+        assert_eq!(keyset2.get_block_ref(&String::from("key050")), Some(7));
+        assert_eq!(keyset2.get_last_key(), Some(String::from("key099")));
+        assert_eq!(keyset2.serialize(), keyset.serialize());
+    }
+
+    #[test]
+    fn integer_values() {
+        let mut keyset = KVSet::new();
+        keyset.put(&String::from("a"), String::from("1"));
+        keyset.put_integer(&String::from("b"), -300);
+        keyset.put_block_ref(&String::from("b"), 9);
+        assert_eq!(keyset.put_integer(&String::from("a"), 2), None);
+
+        for format in vec!(KVSetFormat::Slotted, KVSetFormat::SlottedPrefix) {
+            let keyset2 = match KVSet::deserialize_format(keyset.serialize_format(format), format) {
+                Ok(val) => val,
+                Err(_) => panic!("Error deserializing KVSet"),
+            };
+
+            assert_eq!(keyset2.get(&String::from("a")), None);
+            assert_eq!(keyset2.get_integer(&String::from("a")), Some(2));
+            assert_eq!(keyset2.get_integer(&String::from("b")), Some(-300));
+            assert_eq!(keyset2.get_block_ref(&String::from("b")), Some(9));
+        }
+
+        keyset.put(&String::from("b"), String::from("text"));
+        assert_eq!(keyset.get_integer(&String::from("b")), None);
+    }
+
+    #[test]
+    fn serialized_lengths() {
+        let mut keyset = KVSet::new();
+        for i in 0..40 {
+            keyset.put(&format!("shared/prefix/{:03}", i), "v".repeat(i * 7));
+        }
+        keyset.put_integer(&String::from("count"), i64::min_value());
+        keyset.put_with_expiry(&String::from("later"), String::from("x"), u64::max_value());
+        keyset.put_block_ref(&String::from("later"), 1 << 40);
+
+        for format in vec!(KVSetFormat::Slotted, KVSetFormat::SlottedPrefix) {
+            assert_eq!(keyset.serialized_len(format), keyset.serialize_format(format).len());
+
+            let page = KVSet::deserialize_format(keyset.serialize_format(format), format).ok().unwrap();
+            assert_eq!(page.serialized_len(format), keyset.serialize_format(format).len());
+        }
+
+        // Big enough to need four-byte slots.
+        keyset.put(&String::from("huge"), "h".repeat(70000));
+        assert_eq!(keyset.serialized_len(KVSetFormat::Slotted), keyset.serialize_format(KVSetFormat::Slotted).len());
+    }
+
+    #[test]
+    fn expired_values() {
+        let mut keyset = KVSet::new();
+        keyset.put_with_expiry(&String::from("a"), String::from("1"), 1);
+        keyset.put_with_expiry(&String::from("b"), String::from("2"), u64::max_value());
+        keyset.put_block_ref(&String::from("a"), 5);
+        keyset.put(&String::from("c"), String::from("3"));
+
+        let mut keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset2.get(&String::from("a")), None);
+        assert_eq!(keyset2.get(&String::from("b")), Some("2"));
+        assert_eq!(keyset2.get_expiry(&String::from("b")), Some(u64::max_value()));
+
+        assert_eq!(keyset2.remove_expired(now_millis()), 1);
+        assert_eq!(keyset2.get_keys(), vec!("a", "b", "c"));
+        assert_eq!(keyset2.get_expiry(&String::from("a")), None);
+        assert_eq!(keyset2.get_block_ref(&String::from("a")), Some(5));
+    }
+
+    #[test]
+    fn changing_a_page() {
+        let mut keyset = KVSet::new();
+        keyset.put(&String::from("a"), String::from("1"));
+        keyset.put(&String::from("b"), String::from("2"));
+
+        let mut keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset2.put(&String::from("a"), String::from("3")), Some(String::from("1")));
+        assert_eq!(keyset2.delete(&String::from("b")), Some(String::from("2")));
+        assert!(keyset2.page.is_none());
+        assert_eq!(keyset2.get_keys(), vec!("a"));
+        assert_eq!(keyset2.get(&String::from("a")), Some("3"));
+    }
+
+    #[test]
+    fn prefix_compressed_lookups() {
+        let mut keyset = KVSet::new();
+        for i in 0..100 {
+            keyset.put(&format!("user_{:08}", i), format!("value{}", i));
+        }
+        keyset.put_block_ref(&String::from("user_00000050"), 7);
+
+        let plain = keyset.serialize_format(KVSetFormat::Slotted);
+        let mut compressed = keyset.serialize_format(KVSetFormat::SlottedPrefix);
+        assert!(compressed.len() < plain.len());
+
+        let keyset2 = match KVSet::deserialize_format(compressed, KVSetFormat::SlottedPrefix) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        for i in 0..100 {
+            assert_eq!(keyset2.get(&format!("user_{:08}", i)), Some(&*format!("value{}", i)));
+        }
+        assert_eq!(keyset2.get(&String::from("user_")), None);
+        assert_eq!(keyset2.get(&String::from("user_00000100")), None);
+        assert_eq!(keyset2.get(&String::from("user_000000505")), None);
+        assert_eq!(keyset2.get_block_ref(&String::from("user_00000050")), Some(7));
+        assert_eq!(keyset2.get_last_key(), Some(String::from("user_00000099")));
+        assert_eq!(keyset2.get_keys(), keyset.get_keys());
+        assert_eq!(keyset2.serialize_format(KVSetFormat::Slotted), plain);
+    }
+
+    #[test]
+    fn prefix_compression_with_multibyte_keys() {
+        let mut keyset = KVSet::new();
+        keyset.put(&String::from("caf\u{e9}"), String::from("1"));
+        keyset.put(&String::from("caf\u{e8}"), String::from("2"));
+
+        let mut compressed = keyset.serialize_format(KVSetFormat::SlottedPrefix);
+        let keyset2 = match KVSet::deserialize_format(compressed, KVSetFormat::SlottedPrefix) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset2.get(&String::from("caf\u{e9}")), Some("1"));
+        assert_eq!(keyset2.get(&String::from("caf\u{e8}")), Some("2"));
+    }
+
+    #[test]
+    fn corrupt_page() {
+        let mut bytes = vec!(2, 200, 0, 1, 2, 3);
+        assert!(KVSet::deserialize(bytes).is_err());
+    }
+
+    #[test]
+    fn deserialization_fixed_pointers() {
+        let key = String::from("key");
+        let mut bytes = vec!(107, 101, 121, 0, 3, 22, 0, 2, 0, 2, 0, 2, 0, 2, 0, 2, 0, 2, 0, 2);
+
+        let keyset = match KVSet::deserialize_format(bytes, KVSetFormat::FixedPointers) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        assert_eq!(keyset.get_block_ref(&key), Some(22));
+    }
+
+    #[test]
+    fn serialization_pointer_only() {
+        let key = String::from("key");
+        let blockno: u64 = 22;
+
+        let mut keyset = KVSet::new();
+        keyset.put_block_ref(&key, blockno);
+
+        let vector = keyset.serialize();
+        let keyset2 = match KVSet::deserialize(keyset.serialize()) {
+            Ok(val) => val,
+            Err(_) => panic!("Error deserializing KVSet"),
+        };
+
+        match keyset2.get_block_ref(&key) {
+            Some(val) => assert_eq!(blockno, val),
+            None => panic!("Expected a value from keyset")
+        }
+
+        assert_eq!(keyset2.get(&key), None);
+    }
+}
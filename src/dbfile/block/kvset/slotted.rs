@@ -0,0 +1,459 @@
+use dbfile::block::codec::*;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::str;
+use error::*;
+use super::Entry;
+
+// A slotted page starts with the width of its slots (2 or 4 bytes), the number of records, and a
+// table of where each record starts relative to the end of the table.  The count and slots are
+// little-endian and take up the slot width each; small pages use two-byte slots so that they pack
+// tightly, while larger blocks can still be addressed with four.  The records follow, sorted by
+// key, so a lookup can binary search the slot table and only decode the records it lands on.
+// Each record is laid out as:
+//
+//     [flags: u8][key length: varint][key][value length: varint][value][integer: varint]
+//     [expiry: varint][pointer: varint]
+//
+// where the value, integer, expiry and pointer are only present if their flag is set.  A key holds
+// either a string value or an integer, which is stored as a signed varint.  The expiry is when the
+// value stops being visible, in milliseconds since the Unix epoch.
+//
+// A prefix compressed page stores each key as the length of the prefix it shares with the key
+// before it, followed by the rest of the key.  Every RESTART_INTERVAL records the full key is
+// written again, and only those restart records get a slot.  The header gains the restart interval
+// after the slot width and the number of slots after the record count:
+//
+//     [width: u8][interval: u8][count][slot count][slots...]
+//     [flags: u8][shared length: varint][suffix length: varint][suffix][value][pointer]
+//
+// A lookup binary searches the restart records and then reads forward through at most one
+// interval's worth of records.
+
+const HAS_VALUE: u8   = 0b01;
+const HAS_POINTER: u8 = 0b10;
+const HAS_INTEGER: u8 = 0b100;
+const HAS_EXPIRY: u8  = 0b1000;
+
+pub const RESTART_INTERVAL: usize = 16;
+
+fn read_slot(bytes: &[u8], width: usize) -> Option<usize> {
+    return match width {
+        2 if bytes.len() >= 2 => Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize),
+        4 => decode_u32(bytes).map(|n| n as usize),
+        _ => None,
+    };
+}
+
+fn write_slot(bytes: &mut Vec<u8>, width: usize, number: usize) {
+    match width {
+        2 => bytes.extend_from_slice(&(number as u16).to_le_bytes()),
+        _ => bytes.append(&mut encode_u32(number as u32)),
+    }
+}
+
+/// A record read out of a page.  The value is borrowed from the page, as is the key unless it had
+/// to be put back together from a shared prefix.
+pub struct Record<'a> {
+    pub key: Cow<'a, str>,
+    pub value: Option<&'a str>,
+    pub integer: Option<i64>,
+    pub expires: Option<u64>,
+    pub pointer: Option<u64>,
+}
+
+pub struct SlottedPage<'a> {
+    bytes: &'a [u8],
+    compressed: bool,
+    width: usize,
+    count: usize,
+    slot_count: usize,
+    slots_start: usize,
+    records_start: usize,
+}
+
+/// Reads forward through a page's records, one at a time.
+pub struct Records<'p, 'a: 'p> {
+    page: &'p SlottedPage<'a>,
+    pos: usize,
+    previous_key: String,
+    /// How many of the page's records are left, so that nothing after the last one is read as a
+    /// record.
+    remaining: usize,
+}
+
+fn read_length_prefixed<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a str> {
+    let (length, varint_length) = decode_varint(bytes.get(*pos..)?)?;
+    let start = *pos + varint_length;
+    let end = start.checked_add(length as usize)?;
+    if (end > bytes.len()) {
+        return None;
+    }
+
+    *pos = end;
+    return str::from_utf8(&bytes[start..end]).ok();
+}
+
+impl<'a> SlottedPage<'a> {
+    /// Checks that the slot table fits within the bytes.  Records are only checked as they're read.
+    pub fn new(bytes: &'a [u8], compressed: bool) -> Result<SlottedPage<'a>, CorruptDataError> {
+        let width = match bytes.first() {
+            Some(&w) if w == 2 || w == 4 => w as usize,
+            _ => return Err(CorruptDataError::new("Slotted page has an invalid slot width")),
+        };
+
+        let mut pos = 1;
+        if (compressed) {
+            // The restart interval is only needed to write pages, not to read them.
+            pos += 1;
+        }
+
+        let count = match bytes.get(pos..).and_then(|b| read_slot(b, width)) {
+            Some(n) => n,
+            None => return Err(CorruptDataError::new("Slotted page is missing its record count")),
+        };
+        pos += width;
+
+        let mut slot_count = count;
+        if (compressed) {
+            slot_count = match bytes.get(pos..).and_then(|b| read_slot(b, width)) {
+                Some(n) => n,
+                None => return Err(CorruptDataError::new("Slotted page is missing its slot count")),
+            };
+            pos += width;
+        }
+
+        let records_start = pos + slot_count * width;
+        if (records_start > bytes.len() || slot_count > count || (count > 0 && slot_count == 0)) {
+            return Err(CorruptDataError::new("Slotted page is too short for its slot table"));
+        }
+
+        return Ok(SlottedPage {
+            bytes: bytes,
+            compressed: compressed,
+            width: width,
+            count: count,
+            slot_count: slot_count,
+            slots_start: pos,
+            records_start: records_start,
+        });
+    }
+
+    fn slot_position(&self, index: usize) -> Option<usize> {
+        let slot = self.slots_start + index * self.width;
+        return self.records_start.checked_add(read_slot(&self.bytes[slot..], self.width)?);
+    }
+
+    /// Reads the record at `pos`, returning it along with where the next one starts.
+    fn read_record(&self, mut pos: usize, previous_key: &str) -> Option<(Record<'a>, usize)> {
+        let bytes = self.bytes;
+        let flags = *bytes.get(pos)?;
+        pos += 1;
+
+        let key = match self.compressed {
+            true => {
+                let (shared, varint_length) = decode_varint(bytes.get(pos..)?)?;
+                pos += varint_length;
+                let suffix = read_length_prefixed(bytes, &mut pos)?;
+
+                match shared {
+                    0 => Cow::Borrowed(suffix),
+                    n => {
+                        let mut key = String::from(previous_key.get(..(n as usize))?);
+                        key.push_str(suffix);
+                        Cow::Owned(key)
+                    },
+                }
+            },
+            false => Cow::Borrowed(read_length_prefixed(bytes, &mut pos)?),
+        };
+
+        let mut value = None;
+        if (flags & HAS_VALUE != 0) {
+            value = Some(read_length_prefixed(bytes, &mut pos)?);
+        }
+
+        let mut integer = None;
+        if (flags & HAS_INTEGER != 0) {
+            let (n, varint_length) = decode_signed_varint(bytes.get(pos..)?)?;
+            integer = Some(n);
+            pos += varint_length;
+        }
+
+        let mut expires = None;
+        if (flags & HAS_EXPIRY != 0) {
+            let (n, varint_length) = decode_varint(bytes.get(pos..)?)?;
+            expires = Some(n);
+            pos += varint_length;
+        }
+
+        let mut pointer = None;
+        if (flags & HAS_POINTER != 0) {
+            let (n, varint_length) = decode_varint(bytes.get(pos..)?)?;
+            pointer = Some(n);
+            pos += varint_length;
+        }
+
+        let record = Record {
+            key: key,
+            value: value,
+            integer: integer,
+            expires: expires,
+            pointer: pointer,
+        };
+
+        return Some((record, pos));
+    }
+
+    /// Reads the records from the given slot onwards.
+    fn records_from<'p>(&'p self, slot: usize) -> Records<'p, 'a> {
+        // Compressed pages only have a slot for every restart.
+        let records_per_slot = if (self.compressed) { RESTART_INTERVAL } else { 1 };
+        let (pos, remaining) = match self.slot_position(slot) {
+            Some(p) => (p, self.count.saturating_sub(slot * records_per_slot)),
+            None => (self.bytes.len(), 0),
+        };
+
+        return Records {
+            page: self,
+            pos: pos,
+            previous_key: String::new(),
+            remaining: remaining,
+        };
+    }
+
+    pub fn records<'p>(&'p self) -> Records<'p, 'a> {
+        return Records {
+            page: self,
+            pos: self.records_start,
+            previous_key: String::new(),
+            remaining: self.count,
+        };
+    }
+
+    pub fn last_record(&self) -> Option<Record<'a>> {
+        if (self.slot_count == 0) {
+            return None;
+        }
+
+        return self.records_from(self.slot_count - 1).last();
+    }
+
+    /// Binary searches the slots for the last one at or before the key, then reads forward from
+    /// there.
+    pub fn find(&self, key: &str) -> Option<Record<'a>> {
+        let mut low = 0;
+        let mut high = self.slot_count;
+
+        while (low < high) {
+            let middle = low + (high - low) / 2;
+            let record = match self.slot_position(middle).and_then(|pos| self.read_record(pos, "")) {
+                Some((r, _)) => r,
+                None => {
+                    error!("Corrupt record in slot {}", middle);
+                    return None;
+                },
+            };
+
+            match record.key.as_ref().cmp(key) {
+                Ordering::Equal   => return Some(record),
+                Ordering::Less    => low = middle + 1,
+                Ordering::Greater => high = middle,
+            }
+        }
+
+        // Uncompressed pages have a slot for every record, so there's nothing left to read.
+        if (low == 0 || !self.compressed) {
+            return None;
+        }
+
+        for record in self.records_from(low - 1).skip(1).take(RESTART_INTERVAL - 1) {
+            match record.key.as_ref().cmp(key) {
+                Ordering::Equal   => return Some(record),
+                Ordering::Less    => continue,
+                Ordering::Greater => return None,
+            }
+        }
+
+        return None;
+    }
+}
+
+impl<'p, 'a> Iterator for Records<'p, 'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        if (self.remaining == 0) {
+            return None;
+        }
+
+        return match self.page.read_record(self.pos, &self.previous_key) {
+            Some((record, next_pos)) => {
+                self.pos = next_pos;
+                self.remaining -= 1;
+                if (self.page.compressed) {
+                    self.previous_key = String::from(record.key.as_ref());
+                }
+                Some(record)
+            },
+            None => {
+                error!("Corrupt record at byte {}", self.pos);
+                self.remaining = 0;
+                None
+            },
+        };
+    }
+}
+
+fn write_length_prefixed(bytes: &mut Vec<u8>, data: &str) {
+    bytes.append(&mut encode_varint(data.len() as u64));
+    bytes.extend_from_slice(data.as_bytes());
+}
+
+fn shared_prefix_length(a: &str, b: &str) -> usize {
+    let mut length = 0;
+    for ((i, x), y) in a.char_indices().zip(b.chars()) {
+        if (x != y) {
+            break;
+        }
+        length = i + x.len_utf8();
+    }
+
+    return length;
+}
+
+/// Returns how long `encode` would make the page, without building it.
+pub fn encoded_len<'a, I>(entries: I, count: usize, compressed: bool) -> usize where I: Iterator<Item = (&'a String, &'a Entry)> {
+    let mut slot_count = 0;
+    let mut records_length = 0;
+    let mut previous_key: &str = "";
+
+    for (i, (key, entry)) in entries.enumerate() {
+        let is_restart = !compressed || i % RESTART_INTERVAL == 0;
+        if (is_restart) {
+            slot_count += 1;
+        }
+
+        // The flags.
+        records_length += 1;
+
+        let mut key_suffix: &str = key;
+        if (compressed) {
+            let shared = match is_restart {
+                true  => 0,
+                false => shared_prefix_length(previous_key, key),
+            };
+            records_length += varint_length(shared as u64);
+            key_suffix = &key[shared..];
+            previous_key = key;
+        }
+        records_length += varint_length(key_suffix.len() as u64) + key_suffix.len();
+
+        if let Some(ref value) = entry.value {
+            records_length += varint_length(value.len() as u64) + value.len();
+        }
+        if let Some(integer) = entry.integer {
+            records_length += signed_varint_length(integer);
+        }
+        if let Some(expires) = entry.expires {
+            records_length += varint_length(expires);
+        }
+        if let Some(pointer) = entry.pointer {
+            records_length += varint_length(pointer);
+        }
+    }
+
+    let width = match records_length <= (u16::max_value() as usize) && count <= (u16::max_value() as usize) {
+        true  => 2,
+        false => 4,
+    };
+
+    let mut header_length = 1 + width * (slot_count + 1);
+    if (compressed) {
+        header_length += 1 + width;
+    }
+
+    return header_length + records_length;
+}
+
+/// Encodes records, which must already be sorted by key, as a slotted page.  Keys are prefix
+/// compressed if `compressed` is set.
+pub fn encode<'a, I>(entries: I, count: usize, compressed: bool) -> Vec<u8> where I: Iterator<Item = (&'a String, &'a Entry)> {
+    let mut offsets = Vec::with_capacity(count);
+    let mut records = Vec::new();
+    let mut previous_key: &str = "";
+
+    for (i, (key, entry)) in entries.enumerate() {
+        let is_restart = !compressed || i % RESTART_INTERVAL == 0;
+        if (is_restart) {
+            offsets.push(records.len());
+        }
+
+        let mut flags = 0;
+        if (entry.value.is_some()) {
+            flags |= HAS_VALUE;
+        }
+        if (entry.integer.is_some()) {
+            flags |= HAS_INTEGER;
+        }
+        if (entry.expires.is_some()) {
+            flags |= HAS_EXPIRY;
+        }
+        if (entry.pointer.is_some()) {
+            flags |= HAS_POINTER;
+        }
+        records.push(flags);
+
+        if (compressed) {
+            let shared = match is_restart {
+                true  => 0,
+                false => shared_prefix_length(previous_key, key),
+            };
+            records.append(&mut encode_varint(shared as u64));
+            write_length_prefixed(&mut records, &key[shared..]);
+            previous_key = key;
+        }
+        else {
+            write_length_prefixed(&mut records, key);
+        }
+
+        if let Some(ref value) = entry.value {
+            write_length_prefixed(&mut records, value);
+        }
+
+        if let Some(integer) = entry.integer {
+            records.append(&mut encode_signed_varint(integer));
+        }
+
+        if let Some(expires) = entry.expires {
+            records.append(&mut encode_varint(expires));
+        }
+
+        if let Some(pointer) = entry.pointer {
+            records.append(&mut encode_varint(pointer));
+        }
+    }
+
+    let width = match records.len() <= (u16::max_value() as usize) && count <= (u16::max_value() as usize) {
+        true  => 2,
+        false => 4,
+    };
+
+    let mut bytes = Vec::with_capacity(2 + width * (offsets.len() + 2) + records.len());
+    bytes.push(width as u8);
+    if (compressed) {
+        bytes.push(RESTART_INTERVAL as u8);
+    }
+
+    write_slot(&mut bytes, width, count);
+    if (compressed) {
+        write_slot(&mut bytes, width, offsets.len());
+    }
+
+    for offset in offsets {
+        write_slot(&mut bytes, width, offset);
+    }
+
+    bytes.append(&mut records);
+    return bytes;
+}
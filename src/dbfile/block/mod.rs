@@ -1,10 +1,13 @@
-use abomonation::{encode, decode};
-
+pub mod codec;
+pub mod compression;
+pub mod encryption;
 pub mod kvset;
 mod sections;
 pub mod types;
 
+use self::codec::*;
 use self::sections::header::{HasBlockHeader};
+pub use self::sections::header::HEADER_SIZE;
 pub use self::types::*;
 
 pub trait HasSectionAddress {
@@ -28,7 +31,9 @@ pub enum CommonSection {
     Body,
     BodySize,
     Type,
-    NextBlock
+    NextBlock,
+    Format,
+    Flags
 }
 
 impl HasSectionAddress for CommonSection {
@@ -37,34 +42,53 @@ impl HasSectionAddress for CommonSection {
             CommonSection::Body      => [256,  0],
             CommonSection::BodySize  => [2,    6],
             CommonSection::Type      => [6,   10],
-            CommonSection::NextBlock => [10,  18]
+            CommonSection::NextBlock => [10,  18],
+            CommonSection::Format    => [18,  19],
+            CommonSection::Flags     => [19,  20]
         }
     }
 }
 
+/// Set in a block's flags when its body is compressed.
+pub const BLOCK_FLAG_COMPRESSED: u8 = 0b01;
+/// Set in a block's flags when its body is encrypted.
+pub const BLOCK_FLAG_ENCRYPTED: u8  = 0b10;
+
 pub enum BlockType {
     Header,
     Node,
     Root,
+    Free,
 }
 
 impl BlockType {
-    fn get_code(&self) -> u32 {
+    pub fn get_code(&self) -> u32 {
         let code: u32 = match *self {
             BlockType::Root => 22,
             BlockType::Node => 40,
             BlockType::Header => 9,
+            BlockType::Free => 61,
         };
 
         return code;
     }
 
-    fn get_block_type(code: u32) -> BlockType {
+    pub fn get_block_type(code: u32) -> BlockType {
         match code {
             22 => BlockType::Root,
+            61 => BlockType::Free,
             _ => BlockType::Node
         }
     }
+
+    pub fn get_name(&self) -> &'static str {
+        return match *self {
+            BlockType::Root => "Root",
+            BlockType::Node => "Node",
+            BlockType::Header => "Header",
+            BlockType::Free => "Free",
+        };
+    }
 }
 
 pub trait SerializeableBlock {
@@ -76,46 +100,32 @@ pub trait BasicBlock : HasBlockHeader {
     fn set_block_type(&mut self, block_type: BlockType) {
         let code: u32 = block_type.get_code();
 
-        let mut bytes = Vec::new();
-        unsafe { encode(&code, &mut bytes); }
-
-        self.get_header().write_section(CommonSection::Type, bytes);
+        self.get_header().write_section(CommonSection::Type, encode_u32(code));
     }
 
     fn body_length(&mut self) -> u32 {
-        let mut bytes = self.get_header().read_section(CommonSection::BodySize);
-        if let Some(result) = unsafe { decode::<u32>(&mut bytes) } {
-            return result.0.clone();
-        }
-        else {
-            return 0;
-        }
+        let bytes = self.get_header().read_section(CommonSection::BodySize);
+        return match decode_u32(&bytes) {
+            Some(length) => length,
+            None => 0,
+        };
     }
 
     fn set_body_length(&mut self, length: u32) {
-        let mut vector = Vec::new();
-        unsafe { encode(&length, &mut vector); }
-        self.get_header().write_section(CommonSection::BodySize, vector);
+        self.get_header().write_section(CommonSection::BodySize, encode_u32(length));
     }
 }
 
 pub trait Navigable : HasBlockHeader {
     fn set_right_block(&mut self, num: u64) {
-        let mut bytes = Vec::new();
-        unsafe { encode(&num, &mut bytes); }
-        self.get_header().write_section(CommonSection::NextBlock, bytes);
+        self.get_header().write_section(CommonSection::NextBlock, encode_u64(num));
     }
 
     fn get_right_block(&mut self) -> Option<u64> {
-        let mut bytes = self.get_header().read_section(CommonSection::NextBlock);
-        if let Some(result) = unsafe { decode::<u64>(&mut bytes) } {
-            return match *result.0 {
-                0 => None,
-                n => Some(n)
-            }
-        }
-        else {
-            return None;
-        }
+        let bytes = self.get_header().read_section(CommonSection::NextBlock);
+        return match decode_u64(&bytes) {
+            Some(0) | None => None,
+            Some(n) => Some(n),
+        };
     }
 }
@@ -1,5 +1,5 @@
 use dbfile::block::*;
-use abomonation::{encode, decode};
+use dbfile::block::codec::*;
 
 pub const HEADER_SIZE: usize = 256;
 
@@ -102,19 +102,15 @@ impl BlockHeader {
     }
 
     pub fn body_length(&self) -> u32 {
-        let mut bytes = self.read_section(CommonSection::BodySize);
-        if let Some(result) = unsafe { decode::<u32>(&mut bytes) } {
-            return result.0.clone();
-        }
-        else {
-            return 0;
-        }
+        let bytes = self.read_section(CommonSection::BodySize);
+        return match decode_u32(&bytes) {
+            Some(length) => length,
+            None => 0,
+        };
     }
 
     pub fn set_body_length(&mut self, length: u32) {
-        let mut vector = Vec::new();
-        unsafe { encode(&length, &mut vector); }
-        self.write_section(CommonSection::BodySize, vector);
+        self.write_section(CommonSection::BodySize, encode_u32(length));
     }
 }
 
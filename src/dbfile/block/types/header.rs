@@ -1,23 +1,34 @@
-use abomonation::{encode, decode};
-
+use dbfile::block::codec::*;
 use dbfile::block::*;
 use dbfile::block::sections::header::*;
+use error::*;
 use version::*;
 
 pub const MAGIC_STRING: &'static str = "GringottsDBFile - https://github.com/JonathonRichardson/gringotts";
 pub const HEADER_BLOCK_SIZE: u64 = 256;
 const DEFAULT_BLOCK_SIZE: usize = 4;
-const CURRENT_DB_VERSION: Version = Version {
+pub const CURRENT_DB_VERSION: Version = Version {
     major: 0,
-    minor: 0,
-    build: 1,
+    minor: 6,
+    build: 0,
 };
 
+/// Features are optional parts of the format that a database is created with.  Each one is a bit
+/// in the header's feature flags, and a database using any bits we don't know about can't be read.
+pub const FEATURE_PREFIX_COMPRESSION: u32 = 0b001;
+pub const FEATURE_BLOCK_COMPRESSION: u32  = 0b010;
+pub const FEATURE_ENCRYPTION: u32         = 0b100;
+pub const KNOWN_FEATURES: u32 = FEATURE_PREFIX_COMPRESSION | FEATURE_BLOCK_COMPRESSION | FEATURE_ENCRYPTION;
+
 enum HeaderSection {
     MagicString,
     Version,
     BlockSize,
-    NumBlocks
+    NumBlocks,
+    Features,
+    Salt,
+    KeyCheck,
+    FreeList
 }
 
 impl HasSectionAddress for HeaderSection {
@@ -27,6 +38,10 @@ impl HasSectionAddress for HeaderSection {
             HeaderSection::Version     => [65, 71],
             HeaderSection::BlockSize   => [71, 72],
             HeaderSection::NumBlocks   => [72, 80],
+            HeaderSection::Features    => [80, 84],
+            HeaderSection::Salt        => [84, 100],
+            HeaderSection::KeyCheck    => [100, 132],
+            HeaderSection::FreeList    => [132, 140],
         }
     }
 }
@@ -68,25 +83,21 @@ impl HeaderBlock {
     }
 
     pub fn get_number_of_blocks(&self) -> u64 {
-        let mut bytes: Vec<u8>  = self.header.read_section(HeaderSection::NumBlocks);
+        let bytes: Vec<u8>  = self.header.read_section(HeaderSection::NumBlocks);
 
         debug!("Bytes read: {:?}", bytes);
-        if let Some(result) = unsafe { decode::<u64>(&mut bytes) } {
-            return result.0.clone();
-        }
-        else {
-            return 0;
-        }
+        return match decode_u64(&bytes) {
+            Some(number) => number,
+            None => 0,
+        };
     }
 
     pub fn set_number_of_blocks(&mut self, number: u64) {
         debug!("Setting number of blocks to: {}", number);
-        let mut bytes = Vec::new();
-        unsafe { encode(&number, &mut bytes); }
+        let bytes = encode_u64(number);
 
-        //let bytes: [u8; 8] = unsafe { mem::transmute(number) };
         debug!("Setting number of blocks to: {:?}", bytes);
-        self.header.write_section(HeaderSection::NumBlocks, bytes.to_vec());
+        self.header.write_section(HeaderSection::NumBlocks, bytes);
     }
 
     pub fn get_block_size(&mut self) -> u8 {
@@ -105,10 +116,57 @@ impl HeaderBlock {
         self.header.write_section(HeaderSection::BlockSize, bytes);
     }
 
-    pub fn get_version(&self) -> Version {
+    pub fn get_features(&self) -> u32 {
+        let bytes = self.header.read_section(HeaderSection::Features);
+        return match decode_u32(&bytes) {
+            Some(features) => features,
+            None => 0,
+        };
+    }
+
+    pub fn set_features(&mut self, features: u32) {
+        self.header.write_section(HeaderSection::Features, encode_u32(features));
+    }
+
+    /// The salt that an encrypted database's key is derived from.
+    pub fn get_salt(&self) -> Vec<u8> {
+        return self.header.read_section(HeaderSection::Salt);
+    }
+
+    pub fn set_salt(&mut self, salt: Vec<u8>) {
+        self.header.write_section(HeaderSection::Salt, salt);
+    }
+
+    pub fn get_key_check(&self) -> Vec<u8> {
+        return self.header.read_section(HeaderSection::KeyCheck);
+    }
+
+    pub fn set_key_check(&mut self, check: Vec<u8>) {
+        self.header.write_section(HeaderSection::KeyCheck, check);
+    }
+
+    /// The first block in the list of blocks that have been freed and can be used again, if there
+    /// are any.  Each free block points to the next one with its right block.
+    pub fn get_free_list(&self) -> Option<u64> {
+        let bytes = self.header.read_section(HeaderSection::FreeList);
+        return match decode_u64(&bytes) {
+            Some(0) | None => None,
+            Some(n) => Some(n),
+        };
+    }
+
+    pub fn set_free_list(&mut self, block_number: Option<u64>) {
+        self.header.write_section(HeaderSection::FreeList, encode_u64(block_number.unwrap_or(0)));
+    }
+
+    pub fn get_version(&self) -> Result<Version, CorruptDataError> {
         let bytes = self.header.read_section(HeaderSection::Version);
         return Version::from_bytes(bytes);
     }
+
+    pub fn set_version(&mut self, version: &Version) {
+        self.header.write_section(HeaderSection::Version, version.to_bytes());
+    }
 }
 
 impl SerializeableBlock for HeaderBlock {
@@ -125,9 +183,16 @@ impl SerializeableBlock for HeaderBlock {
 mod tests {
     use super::*;
     use dbfile::block::{HasSectionAddress};
+    use dbfile::block::encryption::*;
 
     #[test]
     fn magic_string_length() {
         assert_eq!(MAGIC_STRING.len(), super::HeaderSection::MagicString.get_length());
     }
+
+    #[test]
+    fn encryption_section_lengths() {
+        assert_eq!(SALT_LENGTH, super::HeaderSection::Salt.get_length());
+        assert_eq!(KEY_CHECK_LENGTH, super::HeaderSection::KeyCheck.get_length());
+    }
 }
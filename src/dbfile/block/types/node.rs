@@ -1,5 +1,7 @@
+use std::mem;
 use error::*;
 use dbfile::block::*;
+use dbfile::block::encryption::*;
 use dbfile::block::kvset::*;
 use dbfile::block::sections::header::*;
 
@@ -7,35 +9,68 @@ pub struct NodeBlock {
     header: BlockHeader,
     data: KVSet,
     size: usize,
+    format: KVSetFormat,
+    compress: bool,
+    key: Option<BlockKey>,
 }
 
 pub trait DataBlock {
     fn set(&mut self, key: &String, val: String) -> Result<Option<String>, NoRoomError>;
     fn get(&self, key: &String) -> Option<String>;
+    fn delete(&mut self, key: &String) -> Option<String>;
+    fn get_integer(&self, key: &String) -> Option<i64>;
+    fn set_integer(&mut self, key: &String, val: i64) -> Result<Option<i64>, NoRoomError>;
+    fn set_with_expiry(&mut self, key: &String, val: String, expires: u64) -> Result<Option<String>, NoRoomError>;
+    fn save_value(&mut self, key: &String) -> SavedValue;
+    fn restore_value(&mut self, key: &String, saved: SavedValue) -> Result<(), NoRoomError>;
+    fn remove_expired(&mut self, now: u64) -> u64;
     fn get_block_ref(&self, key: &String) -> Option<u64>;
     fn set_block_ref(&mut self, key: &String, blockref: u64) -> Result<Option<u64>, NoRoomError>;
+    fn delete_block_ref(&mut self, key: &String) -> Option<u64>;
     fn get_last_key(&self) -> Option<String>;
+    fn get_keys(&self) -> Vec<String>;
     fn set_kvset(&mut self, kvset: KVSet);
+    fn take_kvset(&mut self) -> KVSet;
+    fn is_empty(&self) -> bool;
     fn split(&mut self) -> KVSet;
 }
 
 impl NodeBlock {
     pub fn from_bytes(blocknumber: u64, bytes_vec: Vec<u8>) -> Result<NodeBlock, CorruptDataError> {
+        return NodeBlock::from_bytes_with_key(blocknumber, bytes_vec, None);
+    }
+
+    /// Reads a block whose body may be encrypted with the given key.
+    pub fn from_bytes_with_key(blocknumber: u64, bytes_vec: Vec<u8>, key: Option<&BlockKey>) -> Result<NodeBlock, CorruptDataError> {
         let header = BlockHeader::from_bytes(blocknumber, &bytes_vec);
         let body_length = header.body_length();
+        // Copy out the body, padding with zeros if the block was cut short.
+        let body_end = HEADER_SIZE + (body_length as usize);
         let mut body = Vec::with_capacity(body_length as usize);
-        for i in 0..body_length {
-            if ((i + (HEADER_SIZE as u32) + 1) > (bytes_vec.len() as u32)) { // use i+1 instead of len() - 1 to prevent issues when len() is 0
-                body.push(0);
-            }
-            else {
-                body.push(bytes_vec[(i as usize) + HEADER_SIZE]);
-            }
+        if (bytes_vec.len() > HEADER_SIZE) {
+            body.extend_from_slice(&bytes_vec[HEADER_SIZE..body_end.min(bytes_vec.len())]);
         }
+        body.resize(body_length as usize, 0);
 
         let msg: &str = &*(format!("Block {} is corrupt", blocknumber));
 
-        let data = match KVSet::deserialize(&mut body) {
+        let flags = header.read_section(CommonSection::Flags)[0];
+        if (flags & BLOCK_FLAG_ENCRYPTED != 0) {
+            body = match key {
+                Some(k) => k.decrypt(blocknumber, &body)?,
+                None => return Err(CorruptDataError::new(&format!("Block {} is encrypted", blocknumber))),
+            };
+        }
+
+        if (flags & BLOCK_FLAG_COMPRESSED != 0) {
+            body = match compression::decompress(&body, bytes_vec.len()) {
+                Ok(decompressed) => decompressed,
+                Err(_) => return Err(CorruptDataError::new(msg)),
+            };
+        }
+
+        let format = KVSetFormat::from_code(header.read_section(CommonSection::Format)[0])?;
+        let data = match KVSet::deserialize_format(body, format) {
             Ok(set) => set,
             Err(_) => return Err(CorruptDataError::new(msg)),
         };
@@ -43,58 +78,149 @@ impl NodeBlock {
         return Ok(NodeBlock {
             header: header,
             data: data,
-            size: bytes_vec.len()
+            size: bytes_vec.len(),
+            format: CURRENT_KVSET_FORMAT,
+            compress: false,
+            key: key.cloned(),
         });
     }
+
+    /// Sets whether the block's body should be compressed when it's written.  Bodies that don't
+    /// get any smaller are written as they are.
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// Sets the key the block's body is encrypted with when it's written, if any.
+    pub fn set_key(&mut self, key: Option<BlockKey>) {
+        self.key = key;
+    }
+
+    /// Sets the format the block will be written in.
+    pub fn set_kvset_format(&mut self, format: KVSetFormat) {
+        self.format = format;
+    }
 }
 
-impl DataBlock for NodeBlock {
-    fn set(&mut self, key: &String, val: String) -> Result<Option<String>, NoRoomError> {
-        let retval = self.data.put(key, val);
+impl NodeBlock {
+    /// Whether the block still fits once it's serialized.  That's worked out from the length of
+    /// the plain records, plus what encryption adds, rather than by serializing the block.
+    /// Compression is only tried when the records don't fit as they are, since it can't make a
+    /// body any bigger: a body that doesn't shrink is written uncompressed.
+    fn fits(&mut self) -> bool {
+        let mut length = HEADER_SIZE + self.data.serialized_len(self.format);
+        if (self.key.is_some()) {
+            length += ENCRYPTION_OVERHEAD;
+        }
 
-        return match(self.serialize().len() <= self.size) {
-            true => Ok(retval),
-            false => {
-                self.data.delete(key);
-                return Err(NoRoomError::new("No Room in block"));
-            }
+        if (length <= self.size) {
+            return true;
         }
+
+        return self.compress && self.serialize().len() <= self.size;
+    }
+
+    /// Makes a change to the key's value, keeping it only if the block still fits.  Otherwise the
+    /// key gets back the value it had, along with its type and expiry time.
+    fn change_value<T, F>(&mut self, key: &String, change: F) -> Result<T, NoRoomError> where F: FnOnce(&mut KVSet) -> T {
+        let saved = self.data.save_value(key);
+        let retval = change(&mut self.data);
+
+        if (self.fits()) {
+            return Ok(retval);
+        }
+
+        self.data.restore_value(key, saved);
+        return Err(NoRoomError::new("No Room in block"));
+    }
+}
+
+impl DataBlock for NodeBlock {
+    fn set(&mut self, key: &String, val: String) -> Result<Option<String>, NoRoomError> {
+        return self.change_value(key, |data| data.put(key, val));
     }
 
+    /// Returns the key's value, with integers written out in decimal.
     fn get(&self, key: &String) -> Option<String> {
         return match self.data.get(key) {
-            Some(s) => Some(s.clone()),
-            None => None,
+            Some(s) => Some(String::from(s)),
+            None => self.data.get_integer(key).map(|n| n.to_string()),
         };
     }
 
+    fn delete(&mut self, key: &String) -> Option<String> {
+        let old = self.get(key);
+        self.data.delete(key);
+        return old;
+    }
+
+    fn set_with_expiry(&mut self, key: &String, val: String, expires: u64) -> Result<Option<String>, NoRoomError> {
+        return self.change_value(key, |data| data.put_with_expiry(key, val, expires));
+    }
+
+    fn save_value(&mut self, key: &String) -> SavedValue {
+        return self.data.save_value(key);
+    }
+
+    fn restore_value(&mut self, key: &String, saved: SavedValue) -> Result<(), NoRoomError> {
+        return self.change_value(key, |data| data.restore_value(key, saved));
+    }
+
+    fn remove_expired(&mut self, now: u64) -> u64 {
+        return self.data.remove_expired(now);
+    }
+
+    fn get_integer(&self, key: &String) -> Option<i64> {
+        return self.data.get_integer(key);
+    }
+
+    fn set_integer(&mut self, key: &String, val: i64) -> Result<Option<i64>, NoRoomError> {
+        return self.change_value(key, |data| data.put_integer(key, val));
+    }
+
     fn get_block_ref(&self, key: &String) -> Option<u64> {
-        return match self.data.get_block_ref(key) {
-            Some(n) => Some(n.clone()),
-            None => None,
-        }
+        return self.data.get_block_ref(key);
     }
 
     fn set_block_ref(&mut self, key: &String, blockref: u64) -> Result<Option<u64>, NoRoomError> {
         let retval = self.data.put_block_ref(&key, blockref);
 
-        return match(self.serialize().len() <= self.size) {
+        return match(self.fits()) {
             true => Ok(retval),
             false => {
-                self.data.delete_block_ref(&key);
+                match retval {
+                    Some(n) => { self.data.put_block_ref(&key, n); },
+                    None => { self.data.delete_block_ref(&key); },
+                }
                 return Err(NoRoomError::new("No Room in block"));
             }
         }
     }
 
+    fn delete_block_ref(&mut self, key: &String) -> Option<u64> {
+        return self.data.delete_block_ref(key);
+    }
+
     fn get_last_key(&self) -> Option<String> {
         return self.data.get_last_key();
     }
 
+    fn get_keys(&self) -> Vec<String> {
+        return self.data.get_keys();
+    }
+
     fn set_kvset(&mut self, kvset: KVSet) {
         self.data = kvset;
     }
 
+    fn take_kvset(&mut self) -> KVSet {
+        return mem::replace(&mut self.data, KVSet::new());
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.data.get_last_key().is_none();
+    }
+
     fn split(&mut self) -> KVSet {
         return self.data.split();
     }
@@ -102,8 +228,25 @@ impl DataBlock for NodeBlock {
 
 impl SerializeableBlock for NodeBlock {
     fn serialize(&mut self) -> Vec<u8> {
-        let mut data_bytes = self.data.serialize();
+        let mut data_bytes = self.data.serialize_format(self.format);
+
+        let mut flags = 0;
+        if (self.compress) {
+            let compressed = compression::compress(&data_bytes);
+            if (compressed.len() < data_bytes.len()) {
+                data_bytes = compressed;
+                flags |= BLOCK_FLAG_COMPRESSED;
+            }
+        }
+
+        if let Some(ref key) = self.key {
+            data_bytes = key.encrypt(self.header.get_block_number(), &data_bytes);
+            flags |= BLOCK_FLAG_ENCRYPTED;
+        }
+
         self.set_body_length(data_bytes.len() as u32);
+        self.header.write_section(CommonSection::Format, vec!(self.format.get_code()));
+        self.header.write_section(CommonSection::Flags, vec!(flags));
         let mut serialized_bytes = self.header.serialize();
         serialized_bytes[0] = 66;
         serialized_bytes[1] = 76;
@@ -0,0 +1,191 @@
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::keychain::*;
+use error::*;
+
+/// The key at the end of a level that's still being added to.  It's held back until the next key
+/// comes along, since its subtree may still need a pointer.
+struct PendingKey {
+    key: String,
+    value: Option<String>,
+    pointer: Option<u64>,
+}
+
+/// One level of the keychain being filled in, block by block.
+struct LevelBuilder {
+    block: NodeBlock,
+    pending: Option<PendingKey>,
+}
+
+impl Dbfile {
+    /// Loads keys into an empty database, building each level's blocks full and in order rather
+    /// than splitting them as they fill up.  The pairs must be sorted the way `dump` writes them:
+    /// by the keys along their path, with a key's own value before anything beneath it.  Returns
+    /// the number of keys loaded.  If the input turns out not to be sorted, the keys before that
+    /// point stay loaded.
+    pub fn bulk_load<I>(&mut self, pairs: I) -> Result<u64, InvalidInputError> where I: IntoIterator<Item = (String, String)> {
+        let _lock = self.lock();
+
+        let root = self.read_block(1);
+        if (root.get_last_key().is_some()) {
+            return Err(InvalidInputError::new("Bulk loading needs an empty database"));
+        }
+
+        let mut levels = vec!(LevelBuilder { block: root, pending: None });
+        let mut previous: Vec<String> = Vec::new();
+        let mut count = 0;
+
+        for (path, value) in pairs {
+            let keychain = KeyChain::parse(&path);
+            let mut keys = keychain.as_vec();
+            keys.push(keychain.get_final_key());
+
+            if (count > 0 && keys <= previous) {
+                return Err(InvalidInputError::new(&format!("{} is out of order", path)));
+            }
+
+            // Finish off the levels beneath keys that this one doesn't share.
+            let shared = keys.iter().zip(previous.iter()).take_while(|&(a, b)| a == b).count();
+            while (levels.len() > shared + 1) {
+                let level = levels.pop().unwrap();
+                self.finish_level(level)?;
+            }
+
+            for depth in shared..keys.len() {
+                if (depth == levels.len()) {
+                    let first_block = self.new_block();
+                    levels[depth - 1].pending.as_mut().unwrap().pointer = Some(first_block.get_block_number());
+                    levels.push(LevelBuilder { block: first_block, pending: None });
+                }
+
+                let finished = levels[depth].pending.take();
+                if let Some(pending) = finished {
+                    self.add_to_level(&mut levels[depth], pending)?;
+                }
+
+                levels[depth].pending = Some(PendingKey {
+                    key: keys[depth].clone(),
+                    value: None,
+                    pointer: None,
+                });
+            }
+
+            levels[keys.len() - 1].pending.as_mut().unwrap().value = Some(value);
+            previous = keys;
+            count += 1;
+        }
+
+        while let Some(level) = levels.pop() {
+            self.finish_level(level)?;
+        }
+
+        return Ok(count);
+    }
+
+    /// Puts a key at the end of the level, starting a new block to the right when the current one
+    /// is full.  Fails if the key doesn't fit even in a block of its own.
+    fn add_to_level(&mut self, level: &mut LevelBuilder, pending: PendingKey) -> Result<(), InvalidInputError> {
+        if (put_pending(&mut level.block, &pending)) {
+            return Ok(());
+        }
+
+        let mut alone = self.empty_block(0);
+        if (!put_pending(&mut alone, &pending)) {
+            return Err(InvalidInputError::too_big(&format!("The value for {} is too big to fit in a block", pending.key)));
+        }
+
+        let mut next_block = self.new_block();
+        level.block.set_right_block(next_block.get_block_number());
+        self.write_block(&mut level.block);
+
+        put_pending(&mut next_block, &pending);
+        level.block = next_block;
+        return Ok(());
+    }
+
+    fn finish_level(&mut self, mut level: LevelBuilder) -> Result<(), InvalidInputError> {
+        if let Some(pending) = level.pending.take() {
+            self.add_to_level(&mut level, pending)?;
+        }
+
+        self.write_block(&mut level.block);
+        return Ok(());
+    }
+}
+
+/// Puts the key in the block, leaving the block as it was and returning false if it doesn't fit.
+fn put_pending(block: &mut NodeBlock, pending: &PendingKey) -> bool {
+    if let Some(ref value) = pending.value {
+        if (block.set(&pending.key, value.clone()).is_err()) {
+            return false;
+        }
+    }
+
+    if let Some(pointer) = pending.pointer {
+        if (block.set_block_ref(&pending.key, pointer).is_err()) {
+            block.delete(&pending.key);
+            return false;
+        }
+    }
+
+    return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::dump;
+    use dbfile::testing::*;
+
+    #[test]
+    fn loading_sorted_keys() {
+        let temp_db = TempDb::new("gringotts-bulk-load-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        let mut pairs = Vec::new();
+        for i in 0..400 {
+            pairs.push((format!("users/{:04}", i), format!("user {}", i)));
+            pairs.push((format!("users/{:04}/email", i), format!("user{}@example.com", i)));
+        }
+        pairs.push((String::from("z"), String::from("last")));
+
+        let loaded = match dbfile.bulk_load(pairs.clone()) {
+            Ok(n) => n,
+            Err(e) => panic!("{}", e.get_message()),
+        };
+        assert_eq!(loaded, 801);
+
+        let mut walked = Vec::new();
+        dump::walk(&mut dbfile, 1, &mut Vec::new(), &mut |path, value| walked.push((KeyChain::join(path), value)));
+        assert_eq!(walked, pairs);
+
+        assert_eq!(dbfile.get_val(&String::from("users/0399/email")), Some(String::from("user399@example.com")));
+
+        // Only an empty database can be bulk loaded.
+        assert!(dbfile.bulk_load(vec!((String::from("a"), String::from("b")))).is_err());
+    }
+
+    #[test]
+    fn unsorted_input() {
+        let temp_db = TempDb::new("gringotts-bulk-load-unsorted-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        let pairs = vec!(
+            (String::from("b"), String::from("1")),
+            (String::from("a"), String::from("2")),
+        );
+        assert!(dbfile.bulk_load(pairs).is_err());
+    }
+
+    #[test]
+    fn value_too_big_for_a_block() {
+        let temp_db = TempDb::new("gringotts-bulk-load-too-big-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        let pairs = vec!(
+            (String::from("a"), String::from("1")),
+            (String::from("b"), "x".repeat(5000)),
+        );
+        assert!(dbfile.bulk_load(pairs).is_err());
+    }
+}
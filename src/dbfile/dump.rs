@@ -0,0 +1,269 @@
+use serde_json;
+use serde_json::{Map, Value};
+use std::io;
+use std::io::prelude::*;
+
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::keychain::*;
+use error::*;
+
+/// Within a nested dump, a key that has both a value and a subtree is written as an object, and
+/// its own value is stored in that object under this key.  Slashes in the keys themselves are
+/// escaped, as they are in paths, so no key is ever written as a lone slash.
+pub const OWN_VALUE_KEY: &'static str = "/";
+
+pub enum DumpFormat {
+    /// A single JSON object that mirrors the keychain hierarchy.
+    Nested,
+    /// One `{"path": ..., "value": ...}` object per line, in key order.
+    Lines,
+}
+
+/// Walks every level of the keychain beneath `block_number`, calling `visit` with the path and
+/// value of each key that holds a value.  Keys are visited in order, and a key's own value is
+/// always visited before its subtree.
+pub fn walk<F>(dbfile: &mut Dbfile, block_number: u64, path: &mut Vec<String>, visit: &mut F) where F: FnMut(&Vec<String>, String) {
+    for block in dbfile.get_level_blocks(block_number) {
+        for key in block.get_keys() {
+            path.push(key.clone());
+
+            if let Some(value) = block.get(&key) {
+                visit(path, value);
+            }
+
+            if let Some(child) = block.get_block_ref(&key) {
+                walk(dbfile, child, path, visit);
+            }
+
+            path.pop();
+        }
+    }
+}
+
+/// Escapes a key for a nested dump, so that it can't be mistaken for `OWN_VALUE_KEY`.
+fn escape_key(key: &str) -> String {
+    return key.replace("/", "\\/");
+}
+
+fn unescape_key(key: &str) -> String {
+    return key.replace("\\/", "/");
+}
+
+fn insert_nested(tree: &mut Map<String, Value>, path: &[String], value: String) {
+    let key = escape_key(&path[0]);
+
+    if (path.len() == 1) {
+        match tree.get_mut(&key) {
+            Some(&mut Value::Object(ref mut subtree)) => {
+                subtree.insert(String::from(OWN_VALUE_KEY), Value::String(value));
+                return;
+            },
+            _ => {},
+        }
+
+        tree.insert(key, Value::String(value));
+        return;
+    }
+
+    // Promote a plain value to an object if this key turns out to have a subtree too.
+    let entry = tree.entry(key).or_insert_with(|| Value::Object(Map::new()));
+    if let Value::String(own_value) = entry.clone() {
+        let mut subtree = Map::new();
+        subtree.insert(String::from(OWN_VALUE_KEY), Value::String(own_value));
+        *entry = Value::Object(subtree);
+    }
+
+    if let Value::Object(ref mut subtree) = *entry {
+        insert_nested(subtree, &path[1..], value);
+    }
+}
+
+/// Returns the keys beneath `address` as an object in the same form as a nested dump, or None if
+/// there's no such level.
+pub fn to_nested(dbfile: &mut Dbfile, address: &Vec<String>) -> Option<Map<String, Value>> {
+    let level = match dbfile.find_level(address, false) {
+        Some(n) => n,
+        None => return None,
+    };
+
+    let mut tree = Map::new();
+    walk(dbfile, level, &mut Vec::new(), &mut |path, value| insert_nested(&mut tree, path, value));
+    return Some(tree);
+}
+
+/// Writes every key in the database to `out` in the given format.
+pub fn dump<W: Write>(dbfile: &mut Dbfile, format: DumpFormat, out: &mut W) -> io::Result<()> {
+    let mut path = Vec::new();
+
+    match format {
+        DumpFormat::Nested => {
+            let tree = to_nested(dbfile, &path).unwrap_or(Map::new());
+            serde_json::to_writer_pretty(&mut *out, &Value::Object(tree))?;
+            writeln!(out)?;
+        },
+        DumpFormat::Lines => {
+            let mut lines = Vec::new();
+            walk(dbfile, 1, &mut path, &mut |path, value| {
+                lines.push(json!({ "path": KeyChain::join(path), "value": value }).to_string());
+            });
+
+            for line in lines {
+                writeln!(out, "{}", line)?;
+            }
+        },
+    }
+
+    return Ok(());
+}
+
+fn load_nested(dbfile: &mut Dbfile, tree: &Map<String, Value>, path: &mut Vec<String>) -> Result<u64, InvalidInputError> {
+    let mut count = 0;
+
+    for (key, value) in tree {
+        let is_own_value = key == OWN_VALUE_KEY;
+        if (is_own_value && path.is_empty()) {
+            return Err(InvalidInputError::new("The top level can't have a value of its own"));
+        }
+        if (!is_own_value) {
+            path.push(unescape_key(key));
+        }
+
+        match *value {
+            Value::String(ref s) => {
+                dbfile.set_val(&KeyChain::join(path), s.clone())?;
+                count += 1;
+            },
+            Value::Object(ref subtree) if !is_own_value => {
+                count += load_nested(dbfile, subtree, path)?;
+            },
+            _ => {
+                let message = format!("Expected a string value for {}", KeyChain::join(path));
+                return Err(InvalidInputError::new(&message));
+            },
+        }
+
+        if (!is_own_value) {
+            path.pop();
+        }
+    }
+
+    return Ok(count);
+}
+
+fn parse_line(line: &str, line_number: usize) -> Result<(String, String), InvalidInputError> {
+    let message = format!("Line {} is not a valid {{\"path\", \"value\"}} object", line_number);
+
+    let record: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(_) => return Err(InvalidInputError::new(&message)),
+    };
+
+    return match (record.get("path"), record.get("value")) {
+        (Some(&Value::String(ref path)), Some(&Value::String(ref value))) => Ok((path.clone(), value.clone())),
+        _ => Err(InvalidInputError::new(&message)),
+    };
+}
+
+/// Reads the `{"path", "value"}` objects on each line of `input`, skipping blank lines.
+fn read_lines<R: BufRead>(input: R) -> impl Iterator<Item = Result<(String, String), InvalidInputError>> {
+    return input.lines().enumerate().filter_map(|(i, line)| match line {
+        Ok(ref l) if l.trim().is_empty() => None,
+        Ok(l) => Some(parse_line(&l, i + 1)),
+        Err(e) => Some(Err(InvalidInputError::new(&e.to_string()))),
+    });
+}
+
+/// Reads keys written by `dump` in the given format and stores each of them in the database.
+/// Returns the number of keys that were stored.
+pub fn load<R: BufRead>(dbfile: &mut Dbfile, format: DumpFormat, input: R) -> Result<u64, InvalidInputError> {
+    match format {
+        DumpFormat::Nested => {
+            let tree: Value = match serde_json::from_reader(input) {
+                Ok(v) => v,
+                Err(e) => return Err(InvalidInputError::new(&format!("Invalid JSON: {}", e))),
+            };
+
+            return match tree {
+                Value::Object(ref map) => load_nested(dbfile, map, &mut Vec::new()),
+                _ => Err(InvalidInputError::new("Expected a JSON object at the top level")),
+            };
+        },
+        DumpFormat::Lines => {
+            let mut count = 0;
+            for pair in read_lines(input) {
+                let (path, value) = pair?;
+                dbfile.set_val(&path, value)?;
+                count += 1;
+            }
+
+            return Ok(count);
+        },
+    }
+}
+
+/// Reads JSON lines already sorted the way `dump` writes them, and bulk loads them into an empty
+/// database.  Returns the number of keys that were stored.  Every line is read before any of them
+/// are stored, so one that can't be read leaves the database empty.
+pub fn import_sorted<R: BufRead>(dbfile: &mut Dbfile, input: R) -> Result<u64, InvalidInputError> {
+    let pairs = read_lines(input).collect::<Result<Vec<(String, String)>, InvalidInputError>>()?;
+    return dbfile.bulk_load(pairs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+    use serde_json::{Map, Value};
+
+    #[test]
+    fn nesting_values_and_subtrees() {
+        let mut tree = Map::new();
+        insert_nested(&mut tree, &vec!(String::from("a")), String::from("1"));
+        insert_nested(&mut tree, &vec!(String::from("a"), String::from("b")), String::from("2"));
+        insert_nested(&mut tree, &vec!(String::from("c"), String::from("d")), String::from("3"));
+
+        let expected = json!({
+            "a": { "/": "1", "b": "2" },
+            "c": { "d": "3" }
+        });
+
+        assert_eq!(Value::Object(tree), expected);
+    }
+
+    #[test]
+    fn dumping_keys_that_look_like_own_values() {
+        let temp_db = TempDb::new("gringotts-dump-test.gdb");
+        let mut dbfile = temp_db.create();
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        dbfile.set_val(&String::from("a/"), String::from("2")).ok().unwrap();
+        dbfile.set_val(&String::from("a/\\/"), String::from("3")).ok().unwrap();
+
+        let mut out = Vec::new();
+        dump(&mut dbfile, DumpFormat::Nested, &mut out).unwrap();
+        let tree: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(tree, json!({ "a": { "/": "1", "": "2", "\\/": "3" } }));
+
+        let loaded_db = TempDb::new("gringotts-dump-load-test.gdb");
+        let mut loaded = loaded_db.create();
+        assert_eq!(load(&mut loaded, DumpFormat::Nested, out.as_slice()).ok(), Some(3));
+        assert_eq!(loaded.get_val(&String::from("a")), Some(String::from("1")));
+        assert_eq!(loaded.get_val(&String::from("a/")), Some(String::from("2")));
+        assert_eq!(loaded.get_val(&String::from("a/\\/")), Some(String::from("3")));
+    }
+
+    #[test]
+    fn importing_a_bad_line() {
+        let temp_db = TempDb::new("gringotts-import-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        let input = "{\"path\": \"a\", \"value\": \"1\"}\nnot json\n{\"path\": \"b\", \"value\": \"2\"}\n";
+        assert!(import_sorted(&mut dbfile, input.as_bytes()).is_err());
+        assert_eq!(dbfile.get_val(&String::from("a")), None);
+
+        // Nothing was loaded, so the database can still be bulk loaded.
+        let input = "{\"path\": \"a\", \"value\": \"1\"}\n{\"path\": \"b\", \"value\": \"2\"}\n";
+        assert_eq!(import_sorted(&mut dbfile, input.as_bytes()).ok(), Some(2));
+        assert_eq!(dbfile.get_val(&String::from("b")), Some(String::from("2")));
+    }
+}
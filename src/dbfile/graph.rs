@@ -0,0 +1,116 @@
+use std::io;
+use std::io::prelude::*;
+
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::inspect::*;
+
+/// Quotes a string for use as a DOT label.
+fn quote(label: &str) -> String {
+    return format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"));
+}
+
+fn get_node_label(description: &BlockDescription, block_size: usize) -> String {
+    let fill = (HEADER_SIZE + description.body_size as usize) * 100 / block_size;
+    let keys = match description.entries {
+        Ok(ref entries) if entries.is_empty() => String::from("(empty)"),
+        Ok(ref entries) => format!("{} .. {}", entries[0].key, entries[entries.len() - 1].key),
+        Err(_) => String::from("(unreadable)"),
+    };
+
+    return format!("{}: {}\n{}\n{}% full", description.block_number, description.block_type.get_name(), keys, fill);
+}
+
+/// Writes every block in the database to `out` as a Graphviz DOT graph.  Each block is labelled
+/// with its type, the range of keys it holds and how full it is.  Solid edges are the pointers from
+/// keys to the levels beneath them, labelled with the key, and dashed edges link siblings.  Free
+/// blocks aren't siblings of anything, so the free list is drawn with dotted edges instead.
+pub fn write_dot<W: Write>(dbfile: &mut Dbfile, out: &mut W) -> io::Result<()> {
+    let block_size = (dbfile.get_block_size() as usize) * 1024;
+    let number_of_blocks = dbfile.get_number_of_blocks();
+
+    writeln!(out, "digraph gringotts {{")?;
+    writeln!(out, "    node [shape=box];")?;
+
+    for n in 1..(number_of_blocks + 1) {
+        let description = match describe_block(dbfile, n) {
+            Ok(d) => d,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.get_message())),
+        };
+
+        writeln!(out, "    {} [label={}];", n, quote(&get_node_label(&description, block_size)))?;
+
+        match (description.next_block, &description.block_type) {
+            (Some(next), &BlockType::Free) => writeln!(out, "    {} -> {} [style=dotted, label=\"free\"];", n, next)?,
+            (Some(next), _) => writeln!(out, "    {} -> {} [style=dashed, label=\"next\"];", n, next)?,
+            (None, _) => {},
+        }
+
+        if let Ok(ref entries) = description.entries {
+            for entry in entries {
+                if let Some(block_ref) = entry.block_ref {
+                    writeln!(out, "    {} -> {} [label={}];", n, block_ref, quote(&entry.key))?;
+                }
+            }
+        }
+    }
+
+    writeln!(out, "}}")?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::batch::*;
+    use dbfile::testing::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn quoting_labels() {
+        assert_eq!(quote("a \"b\"\nc\\"), "\"a \\\"b\\\"\\nc\\\\\"");
+    }
+
+    #[test]
+    fn graphing_pointers_and_siblings() {
+        let temp_db = TempDb::new("gringotts-graph-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        dbfile.set_val(&String::from("a/b"), String::from("1")).ok().unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..300 {
+            batch.set(&format!("a/{:04}", i), "x".repeat(20));
+        }
+        dbfile.write_batch(batch).unwrap();
+
+        let mut out = Vec::new();
+        write_dot(&mut dbfile, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.starts_with("digraph gringotts {\n"));
+        assert!(dot.contains("    1 [label=\"1: Root\\na .. a\\n"));
+        assert!(dot.contains("    1 -> 2 [label=\"a\"];"));
+        assert!(dot.contains("[style=dashed, label=\"next\"];"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn graphing_the_free_list() {
+        let temp_db = TempDb::new("gringotts-graph-free-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        for i in 0..300 {
+            dbfile.set_with_ttl(&format!("a/{:04}", i), "x".repeat(20), Duration::from_millis(300)).ok().unwrap();
+        }
+        thread::sleep(Duration::from_millis(400));
+        dbfile.expire();
+
+        let mut out = Vec::new();
+        write_dot(&mut dbfile, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.contains("[style=dotted, label=\"free\"];"));
+        assert!(!dot.contains("[style=dashed, label=\"next\"];"));
+    }
+}
@@ -0,0 +1,175 @@
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::block::codec::*;
+use error::*;
+const BYTES_PER_LINE: usize = 16;
+
+/// One key in a block's `KVSet`, with everything that's stored for it.
+pub struct BlockEntry {
+    pub key: String,
+    pub value: Option<String>,
+    pub integer: Option<i64>,
+    pub expires: Option<u64>,
+    pub block_ref: Option<u64>,
+}
+
+/// A single block, decoded for debugging.
+pub struct BlockDescription {
+    pub block_number: u64,
+    pub body_size: u32,
+    /// The type code as it's written in the block, which may not be one we know.
+    pub type_code: u32,
+    pub block_type: BlockType,
+    pub next_block: Option<u64>,
+    pub format: u8,
+    pub flags: u8,
+    /// The block's entries, or why its body couldn't be read.
+    pub entries: Result<Vec<BlockEntry>, String>,
+}
+
+fn read_section<S: HasSectionAddress>(bytes: &Vec<u8>, section: S) -> &[u8] {
+    return &bytes[(section.get_start() as usize)..(section.get_end() as usize)];
+}
+
+fn check_block_number(dbfile: &mut Dbfile, block_number: u64) -> Result<(), InvalidInputError> {
+    let number_of_blocks = dbfile.get_number_of_blocks();
+    if (block_number < 1 || block_number > number_of_blocks) {
+        let message = format!("There is no block {}; blocks are numbered 1 to {}", block_number, number_of_blocks);
+        return Err(InvalidInputError::new(&message));
+    }
+
+    return Ok(());
+}
+
+/// Decodes the header and entries of the given block.
+pub fn describe_block(dbfile: &mut Dbfile, block_number: u64) -> Result<BlockDescription, InvalidInputError> {
+    check_block_number(dbfile, block_number)?;
+    let bytes = dbfile.get_block_bytes(block_number);
+
+    let type_code = decode_u32(read_section(&bytes, CommonSection::Type)).unwrap_or(0);
+    let next_block = match decode_u64(read_section(&bytes, CommonSection::NextBlock)) {
+        Some(0) | None => None,
+        Some(n) => Some(n),
+    };
+
+    let entries = match NodeBlock::from_bytes_with_key(block_number, bytes.clone(), dbfile.key.as_ref()) {
+        Ok(mut block) => {
+            let kvset = block.take_kvset();
+            Ok(kvset.get_keys().into_iter().map(|key| BlockEntry {
+                value: kvset.get(&key).map(String::from),
+                integer: kvset.get_integer(&key),
+                expires: kvset.get_expiry(&key),
+                block_ref: kvset.get_block_ref(&key),
+                key: key,
+            }).collect())
+        },
+        Err(e) => Err(String::from(e.get_message())),
+    };
+
+    return Ok(BlockDescription {
+        block_number: block_number,
+        body_size: decode_u32(read_section(&bytes, CommonSection::BodySize)).unwrap_or(0),
+        type_code: type_code,
+        block_type: BlockType::get_block_type(type_code),
+        next_block: next_block,
+        format: read_section(&bytes, CommonSection::Format)[0],
+        flags: read_section(&bytes, CommonSection::Flags)[0],
+        entries: entries,
+    });
+}
+
+fn hex_lines(bytes: &[u8], offset: usize) -> Vec<String> {
+    return bytes.chunks(BYTES_PER_LINE).enumerate().map(|(i, chunk)| {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = chunk.iter().map(|&b| if (b >= 0x20 && b < 0x7f) { b as char } else { '.' }).collect();
+        format!("  {:08x}  {:<47}  |{}|", offset + i * BYTES_PER_LINE, hex.join(" "), text)
+    }).collect();
+}
+
+/// A named run of bytes within a block.
+pub struct RawSection {
+    pub name: &'static str,
+    pub start: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// Splits the given block's bytes into its header sections, its body and the unused space after it.
+pub fn get_raw_sections(dbfile: &mut Dbfile, block_number: u64) -> Result<Vec<RawSection>, InvalidInputError> {
+    check_block_number(dbfile, block_number)?;
+    let bytes = dbfile.get_block_bytes(block_number);
+
+    let body_size = decode_u32(read_section(&bytes, CommonSection::BodySize)).unwrap_or(0) as usize;
+    let body_end = (HEADER_SIZE + body_size).min(bytes.len());
+
+    let ranges = vec!(
+        ("Magic", 0, 2),
+        ("BodySize", CommonSection::BodySize.get_start() as usize, CommonSection::BodySize.get_end() as usize),
+        ("Type", CommonSection::Type.get_start() as usize, CommonSection::Type.get_end() as usize),
+        ("NextBlock", CommonSection::NextBlock.get_start() as usize, CommonSection::NextBlock.get_end() as usize),
+        ("Format", CommonSection::Format.get_start() as usize, CommonSection::Format.get_end() as usize),
+        ("Flags", CommonSection::Flags.get_start() as usize, CommonSection::Flags.get_end() as usize),
+        ("Reserved", CommonSection::Flags.get_end() as usize, HEADER_SIZE),
+        ("Body", HEADER_SIZE, body_end),
+        ("Unused", body_end, bytes.len()),
+    );
+
+    return Ok(ranges.into_iter().map(|(name, start, end)| RawSection {
+        name: name,
+        start: start,
+        bytes: bytes[start..end].to_vec(),
+    }).collect());
+}
+
+/// Lays out the given block's bytes as hex, one section at a time.  Runs of zeros, like the unused
+/// end of the block, are summed up rather than printed.
+pub fn format_raw_block(dbfile: &mut Dbfile, block_number: u64) -> Result<Vec<String>, InvalidInputError> {
+    let mut lines = Vec::new();
+    for section in get_raw_sections(dbfile, block_number)? {
+        let end = section.start + section.bytes.len();
+        lines.push(format!("{} [{}..{}), {} bytes", section.name, section.start, end, section.bytes.len()));
+
+        if (section.bytes.len() > BYTES_PER_LINE && section.bytes.iter().all(|&b| b == 0)) {
+            lines.push(String::from("  (all zero)"));
+        }
+        else {
+            lines.append(&mut hex_lines(&section.bytes, section.start));
+        }
+    }
+
+    return Ok(lines);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+
+    #[test]
+    fn describing_a_block() {
+        let temp_db = TempDb::new("gringotts-inspect-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        dbfile.set_val(&String::from("a/b"), String::from("2")).ok().unwrap();
+        dbfile.increment(&String::from("n"), 5).ok();
+
+        let description = describe_block(&mut dbfile, 1).ok().unwrap();
+        assert_eq!(description.next_block, None);
+        assert!(description.body_size > 0);
+
+        let entries = description.entries.ok().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "a");
+        assert_eq!(entries[0].value, Some(String::from("1")));
+        assert_eq!(entries[0].block_ref, Some(2));
+        assert_eq!(entries[1].integer, Some(5));
+
+        assert!(describe_block(&mut dbfile, 0).is_err());
+        assert!(describe_block(&mut dbfile, 3).is_err());
+
+        let raw = format_raw_block(&mut dbfile, 2).ok().unwrap();
+        assert!(raw[0].starts_with("Magic [0..2)"));
+        assert_eq!(raw[1], "  00000000  42 4c                                            |BL|");
+        assert_eq!(raw.last().unwrap(), "  (all zero)");
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+use dbfile::block::codec::*;
+
+// Writes that have to land together are made through a rollback journal.  Before anything in the
+// database is overwritten, the bytes about to be replaced are saved to a journal beside it, along
+// with how long the file was:
+//
+//     [magic: 4 bytes][original length: u64]
+//     [position: u64][length: u32][original bytes]...
+//
+// Once the journal is safely on disk, the new bytes are written and synced, and the journal is
+// deleted.  If we're interrupted part way through, the journal is still there the next time the
+// database is opened, and putting the saved bytes back undoes the partial write.  A journal that
+// was cut short is safe to roll back too, since the database isn't touched until it's complete.
+
+const JOURNAL_MAGIC: &'static [u8] = b"GJNL";
+
+/// Returns the path of the rollback journal for the database at `db_path`.
+pub fn get_journal_path(db_path: &String) -> String {
+    return format!("{}.journal", db_path);
+}
+
+fn read_at(file: &mut File, position: u64, length: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0; length];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut buffer)?;
+    return Ok(buffer);
+}
+
+/// Writes each of the given byte ranges, keyed by their position in the file, so that either all
+/// of them end up in the file or none of them do.
+pub fn commit(file: &mut File, db_path: &String, writes: &BTreeMap<u64, Vec<u8>>) -> io::Result<()> {
+    let original_length = file.metadata()?.len();
+
+    let mut journal = JOURNAL_MAGIC.to_vec();
+    journal.append(&mut encode_u64(original_length));
+    for (&position, bytes) in writes.iter() {
+        if (position >= original_length) {
+            continue;
+        }
+
+        let length = bytes.len().min((original_length - position) as usize);
+        journal.append(&mut encode_u64(position));
+        journal.append(&mut encode_u32(length as u32));
+        journal.append(&mut read_at(file, position, length)?);
+    }
+
+    let journal_path = get_journal_path(db_path);
+    let mut journal_file = File::create(&journal_path)?;
+    journal_file.write_all(&journal)?;
+    journal_file.sync_all()?;
+
+    let mut result = Ok(());
+    for (&position, bytes) in writes.iter() {
+        result = file.seek(SeekFrom::Start(position)).and_then(|_| file.write_all(bytes));
+        if (result.is_err()) {
+            break;
+        }
+    }
+
+    match result.and_then(|_| file.sync_all()) {
+        Ok(_) => fs::remove_file(&journal_path)?,
+        Err(e) => {
+            roll_back(file, db_path)?;
+            return Err(e);
+        },
+    }
+
+    return Ok(());
+}
+
+/// Puts back everything saved in the database's journal, if it has one, and removes the journal.
+/// Returns whether there was anything to roll back.
+pub fn roll_back(file: &mut File, db_path: &String) -> io::Result<bool> {
+    let journal_path = get_journal_path(db_path);
+    if (!Path::new(&journal_path).exists()) {
+        return Ok(false);
+    }
+
+    let journal = fs::read(&journal_path)?;
+    let header_length = JOURNAL_MAGIC.len() + 8;
+    if (journal.len() >= header_length && journal.starts_with(JOURNAL_MAGIC)) {
+        let mut pos = header_length;
+        while let (Some(position), Some(length)) = (journal.get(pos..).and_then(decode_u64), journal.get((pos + 8)..).and_then(decode_u32)) {
+            let start = pos + 12;
+            let end = start + (length as usize);
+            if (end > journal.len()) {
+                break;
+            }
+
+            file.seek(SeekFrom::Start(position))?;
+            file.write_all(&journal[start..end])?;
+            pos = end;
+        }
+
+        if let Some(original_length) = decode_u64(&journal[JOURNAL_MAGIC.len()..]) {
+            file.set_len(original_length)?;
+        }
+        file.sync_all()?;
+    }
+
+    fs::remove_file(&journal_path)?;
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn rolling_back_an_interrupted_commit() {
+        let path = env::temp_dir().join("gringotts-journal-test.gdb");
+        let path = String::from(path.to_str().unwrap());
+        fs::write(&path, b"0123456789").unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+        let mut writes = BTreeMap::new();
+        writes.insert(2, b"ab".to_vec());
+        writes.insert(8, b"cdef".to_vec());
+        commit(&mut file, &path, &writes).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"01ab4567cdef".to_vec());
+        assert!(!Path::new(&get_journal_path(&path)).exists());
+
+        // Leave a journal behind, as though we'd stopped part way through writing over the start
+        // of the file and adding to the end of it.
+        let mut journal = JOURNAL_MAGIC.to_vec();
+        journal.append(&mut encode_u64(12));
+        journal.append(&mut encode_u64(0));
+        journal.append(&mut encode_u32(2));
+        journal.extend_from_slice(b"01");
+        fs::write(get_journal_path(&path), &journal).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"xx").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"trailing").unwrap();
+
+        assert_eq!(roll_back(&mut file, &path).unwrap(), true);
+        assert_eq!(fs::read(&path).unwrap(), b"01ab4567cdef".to_vec());
+        assert_eq!(roll_back(&mut file, &path).unwrap(), false);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
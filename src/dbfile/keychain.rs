@@ -31,6 +31,17 @@ impl KeyChain {
         };
     }
 
+    /// Builds a keychain string out of individual keys, escaping any slashes within them so that
+    /// `parse` will split it back into the same keys.
+    pub fn join(keys: &Vec<String>) -> String {
+        let mut escaped: Vec<String> = Vec::with_capacity(keys.len());
+        for key in keys {
+            escaped.push(key.replace("/", "\\/"));
+        }
+
+        return escaped.join("/");
+    }
+
     pub fn get_final_key(&self) -> String {
         return self.final_key.clone();
     }
@@ -73,6 +84,16 @@ mod tests {
         assert_eq!(keyref.get_final_key(), "c");
     }
 
+    #[test]
+    fn joining_with_slashes() {
+        let keys = vec!(String::from("a/z"), String::from("b"), String::from("c"));
+        let joined = KeyChain::join(&keys);
+        let keyref = KeyChain::parse(&joined);
+
+        assert_eq!(keyref.address, vec!("a/z", "b"));
+        assert_eq!(keyref.get_final_key(), "c");
+    }
+
     #[test]
     fn iterating_over_key() {
         let keychain = KeyChain::parse(&String::from("a/b/c"));
@@ -0,0 +1,200 @@
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+
+/// Returns the path of the file that writers to the database at `db_path` lock.  The lock is kept
+/// in a file of its own so that it still means the same thing when the database file is replaced
+/// by a rebuild.
+pub fn get_lock_path(db_path: &String) -> String {
+    return format!("{}.lock", db_path);
+}
+
+/// An exclusive lock on a database, held until it's dropped.  Processes writing to the same
+/// database take turns holding it.
+///
+/// The lock file only exists while someone holds the lock or is waiting for it.  Whoever holds it
+/// removes it just before letting go, so anyone who was waiting finds they've locked a file that's
+/// no longer there, and tries again with a fresh one.
+pub struct DbLock {
+    file: File,
+    path: String,
+}
+
+impl DbLock {
+    /// Blocks until the database's lock is free, then takes it.
+    pub fn acquire(db_path: &String) -> io::Result<DbLock> {
+        loop {
+            let (file, path) = open_lock_file(db_path)?;
+            file.lock()?;
+
+            if (is_current(&file, &path)?) {
+                return Ok(DbLock {
+                    file: file,
+                    path: path,
+                });
+            }
+        }
+    }
+
+    /// Takes the database's lock if it's free, or returns None straight away if someone else
+    /// holds it.
+    pub fn try_acquire(db_path: &String) -> io::Result<Option<DbLock>> {
+        loop {
+            let (file, path) = open_lock_file(db_path)?;
+
+            match file.try_lock() {
+                Ok(_) => {},
+                Err(TryLockError::WouldBlock) => return Ok(None),
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+
+            if (is_current(&file, &path)?) {
+                return Ok(Some(DbLock {
+                    file: file,
+                    path: path,
+                }));
+            }
+        }
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        if (cfg!(unix)) {
+            let _ = fs::remove_file(&self.path);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Takes a shared lock on an open database file, which lasts until the file is closed.  Every open
+/// `Dbfile` holds one, so that `destroy` can tell whether anyone is using the database.  Fails with
+/// `NotFound` if the database was destroyed while we waited for the lock.
+///
+/// Other platforms won't remove a file that's open, and their locks keep everyone else from
+/// writing to it, so this is only done on unix.
+pub fn lock_shared(file: &File, db_path: &String) -> io::Result<()> {
+    if (!cfg!(unix)) {
+        return Ok(());
+    }
+
+    file.lock_shared()?;
+    if (!is_current(file, db_path)?) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} was removed", db_path)));
+    }
+
+    return Ok(());
+}
+
+/// Takes an exclusive lock on a database file if nobody has it open, or returns false straight
+/// away if someone does.
+pub fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    if (!cfg!(unix)) {
+        return Ok(true);
+    }
+
+    return match file.try_lock() {
+        Ok(_) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(e),
+    };
+}
+
+fn open_lock_file(db_path: &String) -> io::Result<(File, String)> {
+    let path = get_lock_path(db_path);
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+    return Ok((file, path));
+}
+
+/// Whether a file we've locked is still the one at `path`, rather than one that was removed while
+/// we waited for it.
+#[cfg(unix)]
+fn is_current(file: &File, path: &String) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let locked = file.metadata()?;
+    return match fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    };
+}
+
+/// Other platforms won't remove a file that's open, so the lock file is never replaced.
+#[cfg(not(unix))]
+fn is_current(_: &File, _: &String) -> io::Result<bool> {
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::Path;
+    use std::thread;
+
+    #[test]
+    fn trying_a_held_lock() {
+        let path = env::temp_dir().join("gringotts-lock-test.gdb");
+        let path = String::from(path.to_str().unwrap());
+
+        let lock = DbLock::acquire(&path).unwrap();
+        assert!(DbLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(!Path::new(&get_lock_path(&path)).exists());
+
+        let lock = DbLock::try_acquire(&path).unwrap();
+        assert!(lock.is_some());
+        drop(lock);
+        assert!(!Path::new(&get_lock_path(&path)).exists());
+    }
+
+    #[test]
+    fn waiting_for_a_lock_that_is_removed() {
+        let path = env::temp_dir().join("gringotts-lock-wait-test.gdb");
+        let path = String::from(path.to_str().unwrap());
+
+        // The waiters lock the file the first holder removes, and have to find the new one.
+        let lock = DbLock::acquire(&path).unwrap();
+        let waiters: Vec<_> = (0..4).map(|_| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let lock = DbLock::acquire(&path).unwrap();
+                assert!(DbLock::try_acquire(&path).unwrap().is_none());
+                drop(lock);
+            })
+        }).collect();
+        drop(lock);
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert!(!Path::new(&get_lock_path(&path)).exists());
+    }
+
+    #[test]
+    fn sharing_a_database_file() {
+        let path = env::temp_dir().join("gringotts-lock-shared-test.gdb");
+        let path = String::from(path.to_str().unwrap());
+        fs::write(&path, b"").unwrap();
+
+        let reader = File::open(&path).unwrap();
+        let other_reader = File::open(&path).unwrap();
+        let destroyer = File::open(&path).unwrap();
+        lock_shared(&reader, &path).unwrap();
+        lock_shared(&other_reader, &path).unwrap();
+        assert!(!try_lock_exclusive(&destroyer).unwrap());
+
+        drop(reader);
+        drop(other_reader);
+        assert!(try_lock_exclusive(&destroyer).unwrap());
+
+        // Whoever opened the file before it was removed finds it gone once they have the lock.
+        let late_reader = File::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        drop(destroyer);
+        if (cfg!(unix)) {
+            assert_eq!(lock_shared(&late_reader, &path).unwrap_err().kind(), io::ErrorKind::NotFound);
+        }
+    }
+}
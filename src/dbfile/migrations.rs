@@ -0,0 +1,274 @@
+use std::fs;
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::dump;
+use dbfile::keychain::*;
+use error::*;
+use version::*;
+
+/// A single step that rewrites a database written in the `from` format so that it's in the `to`
+/// format.  Steps work on the file in place; data that can't be carried over that way can be
+/// moved into a fresh file with `dump` and `load` instead.
+pub struct Migration {
+    pub from: Version,
+    pub to: Version,
+    pub description: &'static str,
+    pub apply: fn(&mut Dbfile) -> Result<(), CorruptDataError>,
+}
+
+/// Returns every known migration, ordered from the oldest format to the newest.  The `to` version
+/// of each step should be the `from` version of the next one, and the last step should end at
+/// `CURRENT_DB_VERSION`.  A format can have more than one step out of it, for files that were
+/// written differently; whichever one's `from` matches the file is the one that runs.
+pub fn get_migrations() -> Vec<Migration> {
+    return vec!(
+        Migration {
+            from: Version::new(0, 0, 1),
+            to: Version::new(0, 1, 0),
+            description: "Integers written by a little-endian host are already little-endian",
+            apply: mark_only,
+        },
+        Migration {
+            from: written_big_endian(Version::new(0, 0, 1)),
+            to: Version::new(0, 1, 0),
+            description: "Store integers written by a big-endian host as little-endian",
+            apply: big_endian_to_little_endian,
+        },
+        Migration {
+            from: Version::new(0, 1, 0),
+            to: Version::new(0, 2, 0),
+            description: "Store block pointers as varints",
+            apply: rewrite_all_blocks,
+        },
+        Migration {
+            from: Version::new(0, 2, 0),
+            to: Version::new(0, 3, 0),
+            description: "Store KVSets as slotted, length-prefixed records",
+            apply: rebuild,
+        },
+        Migration {
+            from: Version::new(0, 3, 0),
+            to: Version::new(0, 4, 0),
+            description: "Add feature flags to the header",
+            apply: add_feature_flags,
+        },
+        Migration {
+            from: Version::new(0, 4, 0),
+            to: Version::new(0, 5, 0),
+            description: "Allow integer values, which older versions can't read",
+            apply: mark_only,
+        },
+        Migration {
+            from: Version::new(0, 5, 0),
+            to: Version::new(0, 6, 0),
+            description: "Allow values to expire, and keep a list of free blocks",
+            apply: mark_only,
+        },
+    );
+}
+
+/// Reads every block and writes it back out.  Blocks can be read in any format they were ever
+/// written in, but are always written in the current one, so this is all it takes to move a
+/// database onto a new block format.
+fn rewrite_all_blocks(dbfile: &mut Dbfile) -> Result<(), CorruptDataError> {
+    let number_of_blocks = dbfile.get_number_of_blocks();
+    for block_number in 1..(number_of_blocks + 1) {
+        let mut block = dbfile.get_block(block_number)?;
+        dbfile.write_block(&mut block);
+    }
+
+    return Ok(());
+}
+
+/// The feature flags sit in bytes that older headers always left as zeros, which already means
+/// "no features", so there's nothing to rewrite.
+fn add_feature_flags(_: &mut Dbfile) -> Result<(), CorruptDataError> {
+    return Ok(());
+}
+
+/// For formats that only add new kinds of records.  Existing blocks are already valid, but the
+/// version still has to change so that older versions stop reading the file.
+fn mark_only(_: &mut Dbfile) -> Result<(), CorruptDataError> {
+    return Ok(());
+}
+
+/// Copies every key into a fresh file and swaps it in for the old one.  This is for block formats
+/// that can take up more room than the ones before them, where rewriting a full block in place
+/// would spill over into the next one.
+fn rebuild(dbfile: &mut Dbfile) -> Result<(), CorruptDataError> {
+    let rebuild_path = format!("{}.rebuild", dbfile.get_path());
+    let _ = fs::remove_file(&rebuild_path);
+
+    let options = dbfile.get_options();
+    let mut rebuilt = match Dbfile::create_with_options(&rebuild_path, &options) {
+        Ok(file) => file,
+        Err(e) => return Err(CorruptDataError::new(&format!("Couldn't create {}: {}", rebuild_path, e))),
+    };
+
+    // Every value already fit in a block of the same size, but check anyway.
+    let mut failed = None;
+    dump::walk(dbfile, 1, &mut Vec::new(), &mut |path, value| {
+        if let Err(e) = rebuilt.set_val(&KeyChain::join(path), value) {
+            failed = Some(e);
+        }
+    });
+    if let Some(e) = failed {
+        return Err(CorruptDataError::new(&format!("Couldn't copy a key into {}: {}", rebuild_path, e.get_message())));
+    }
+
+    return match dbfile.replace_with(&rebuild_path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(CorruptDataError::new(&format!("Couldn't replace {}: {}", dbfile.get_path(), e))),
+    };
+}
+
+/// Before 0.1.0, integers were written in whatever byte order the host used, the version
+/// included.  Read as little-endian, as it is now, a version written by a big-endian host has
+/// each of its numbers byte-swapped, which is how those files are told apart.
+fn written_big_endian(version: Version) -> Version {
+    return Version::new(version.major.swap_bytes(), version.minor.swap_bytes(), version.build.swap_bytes());
+}
+
+/// Little-endian hosts already wrote the new layout, so only files from big-endian hosts need
+/// their integers flipped around.
+fn big_endian_to_little_endian(dbfile: &mut Dbfile) -> Result<(), CorruptDataError> {
+    let mut header_block = dbfile.get_header_block();
+    let number_of_blocks = header_block.get_number_of_blocks().swap_bytes();
+    header_block.set_number_of_blocks(number_of_blocks);
+    dbfile.write_header_block(&mut header_block);
+
+    for block_number in 1..(number_of_blocks + 1) {
+        let mut bytes = dbfile.get_block_bytes(block_number);
+        for section in vec!(CommonSection::BodySize, CommonSection::Type, CommonSection::NextBlock) {
+            let range = section.get_start_and_end();
+            bytes[(range[0] as usize)..(range[1] as usize)].reverse();
+        }
+
+        let mut block = NodeBlock::from_bytes(block_number, bytes)?;
+        for key in block.get_keys() {
+            if let Some(n) = block.get_block_ref(&key) {
+                if let Err(e) = block.set_block_ref(&key, n.swap_bytes()) {
+                    return Err(CorruptDataError::new(&e.get_message()));
+                }
+            }
+        }
+
+        dbfile.write_block(&mut block);
+    }
+
+    return Ok(());
+}
+
+/// Returns the migrations that still need to run on a database at the given version.
+pub fn get_pending_migrations(version: &Version) -> Vec<Migration> {
+    return get_migrations().into_iter().filter(|m| m.from >= *version).collect();
+}
+
+/// Runs each of the given migrations that applies to the database, in order, recording the new
+/// version in the header after every step so that an interrupted upgrade can pick up where it
+/// left off.  Returns the migrations that were run.
+pub fn run_migrations(dbfile: &mut Dbfile, migrations: Vec<Migration>) -> Result<Vec<Migration>, CorruptDataError> {
+    let mut applied = Vec::new();
+
+    for migration in migrations {
+        let version = dbfile.get_version();
+        if (migration.from != version) {
+            // Either we've already been through this step, or it's for a format we're not in.
+            if (migration.from < version) {
+                continue;
+            }
+
+            let message = format!("No migration from version {} to {}", version, migration.from);
+            return Err(CorruptDataError::new(&message));
+        }
+
+        debug!("Migrating from {} to {}: {}", migration.from, migration.to, migration.description);
+
+        (migration.apply)(dbfile)?;
+        dbfile.set_version(&migration.to);
+        applied.push(migration);
+    }
+
+    return Ok(applied);
+}
+
+/// Brings the database up to `CURRENT_DB_VERSION`, returning the migrations that were run.
+pub fn upgrade(dbfile: &mut Dbfile) -> Result<Vec<Migration>, CorruptDataError> {
+    let version = dbfile.get_version();
+    return run_migrations(dbfile, get_pending_migrations(&version));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+
+    fn mark_migrated(dbfile: &mut Dbfile) -> Result<(), CorruptDataError> {
+        dbfile.set_val(&String::from("migrated"), String::from("yes")).ok().unwrap();
+        return Ok(());
+    }
+
+    #[test]
+    fn running_migrations_in_order() {
+        let temp_db = TempDb::new("gringotts-migrations-test.gdb");
+
+        let mut dbfile = temp_db.create();
+        dbfile.set_version(&Version::new(0, 0, 0));
+
+        let migrations = vec!(
+            Migration {
+                from: Version::new(0, 0, 0),
+                to: CURRENT_DB_VERSION,
+                description: "Test migration",
+                apply: mark_migrated,
+            },
+        );
+
+        let applied = match run_migrations(&mut dbfile, migrations) {
+            Ok(applied) => applied,
+            Err(e) => panic!("{}", e.get_message()),
+        };
+
+        assert_eq!(applied.len(), 1);
+        assert_eq!(dbfile.get_version(), CURRENT_DB_VERSION);
+        assert_eq!(dbfile.get_val(&String::from("migrated")), Some(String::from("yes")));
+    }
+
+    #[test]
+    fn choosing_a_step_by_byte_order() {
+        let from_little_endian = get_pending_migrations(&Version::new(0, 0, 1));
+        assert_eq!(from_little_endian[0].description, "Integers written by a little-endian host are already little-endian");
+
+        let big_endian = written_big_endian(Version::new(0, 0, 1));
+        assert_eq!(big_endian, Version::from_bytes(vec!(0, 0, 0, 0, 0, 1)).ok().unwrap());
+        let from_big_endian = get_pending_migrations(&big_endian);
+        assert_eq!(from_big_endian[0].description, "Store integers written by a big-endian host as little-endian");
+        assert_eq!(from_big_endian[1].from, Version::new(0, 1, 0));
+    }
+
+    #[test]
+    fn flipping_big_endian_integers() {
+        let temp_db = TempDb::new("gringotts-big-endian-test.gdb");
+
+        // Make the file look as though a big-endian host wrote its integers.
+        let mut dbfile = temp_db.create();
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        let mut header_block = dbfile.get_header_block();
+        let number_of_blocks = header_block.get_number_of_blocks();
+        header_block.set_number_of_blocks(number_of_blocks.swap_bytes());
+        dbfile.write_header_block(&mut header_block);
+        let mut bytes = dbfile.get_block_bytes(1);
+        for section in vec!(CommonSection::BodySize, CommonSection::Type, CommonSection::NextBlock) {
+            let range = section.get_start_and_end();
+            bytes[(range[0] as usize)..(range[1] as usize)].reverse();
+        }
+        dbfile.write_block_bytes(1, &bytes);
+        dbfile.set_version(&written_big_endian(Version::new(0, 0, 1)));
+
+        let steps = get_migrations().into_iter().take(2).collect();
+        assert_eq!(run_migrations(&mut dbfile, steps).ok().unwrap().len(), 1);
+        assert_eq!(dbfile.get_version(), Version::new(0, 1, 0));
+        assert_eq!(dbfile.get_number_of_blocks(), number_of_blocks);
+        assert_eq!(dbfile.get_val(&String::from("a")), Some(String::from("1")));
+    }
+}
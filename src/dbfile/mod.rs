@@ -1,46 +1,131 @@
-use abomonation::{encode, decode};
 use std::error::Error;
 use std::io;
 use std::io::prelude::*;
 use std::io::SeekFrom;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::mem;
 use std::path::Path;
+use std::time::Duration;
 use version::*;
 
+pub mod batch;
 pub mod block;
+mod bulk;
 use dbfile::block::*;
+use dbfile::block::encryption::*;
+use dbfile::block::kvset::now_millis;
+use error::*;
 
-mod keychain;
+pub mod dump;
+pub mod graph;
+pub mod inspect;
+pub mod journal;
+pub mod keychain;
+pub mod lock;
+pub mod migrations;
+pub mod options;
+pub mod stats;
+pub mod testing;
+pub mod verify;
+pub mod watch;
+pub use dbfile::options::*;
 use dbfile::keychain::*;
+use dbfile::lock::*;
+use dbfile::watch::*;
+use dbfile::batch::*;
+use std::collections::BTreeMap;
 
 pub struct Dbfile {
     file: File,
-    string_path: String
+    string_path: String,
+    key: Option<BlockKey>,
+    watchers: Watchers,
+    /// The header's settings, which are fixed when the database is created, so that reading a
+    /// block doesn't have to read the header as well.
+    options: DbOptions,
+    block_size: u8,
+    /// While a batch is being applied, the bytes it would write, keyed by their position in the
+    /// file.  Reads see these in place of what's on disk.
+    pending: Option<BTreeMap<u64, Vec<u8>>>
+}
+
+/// Returns the paths of the files that can be left beside the database at `string_path`: its
+/// lock and journal, and anything left behind by an unfinished rebuild or rekey.
+pub fn get_sidecar_paths(string_path: &String) -> Vec<String> {
+    let rebuild_path = format!("{}.rebuild", string_path);
+    let rekey_path = format!("{}.rekey", string_path);
+    return vec!(
+        journal::get_journal_path(string_path),
+        get_lock_path(&rebuild_path),
+        rebuild_path,
+        get_lock_path(&rekey_path),
+        rekey_path,
+        get_lock_path(string_path),
+    );
 }
 
 impl Dbfile {
+    /// Creates an empty database.  A file that's already at the path is left alone, and
+    /// `AlreadyExists` returned.
     pub fn create(string_path: &String) -> io::Result<Dbfile> {
+        return Dbfile::create_with_options(string_path, &DbOptions::new());
+    }
+
+    pub fn create_with_options(string_path: &String, options: &DbOptions) -> io::Result<Dbfile> {
+        if (options.encryption) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Encrypted databases need a passphrase"));
+        }
+
+        return Dbfile::create_inner(string_path, options, None);
+    }
+
+    /// Creates a database whose blocks are encrypted with a key derived from the passphrase.
+    pub fn create_with_passphrase(string_path: &String, options: &DbOptions, passphrase: &str) -> io::Result<Dbfile> {
+        let salt = new_salt();
+        let key = match BlockKey::derive(passphrase, &salt) {
+            Ok(k) => k,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e.get_message())),
+        };
+
+        return Dbfile::create_inner(string_path, options, Some((salt, key)));
+    }
+
+    fn create_inner(string_path: &String, options: &DbOptions, encryption: Option<(Vec<u8>, BlockKey)>) -> io::Result<Dbfile> {
         // Create a path to the desired file
 	    let path = Path::new(&string_path);
 	    let display = path.display();
 
-        let mut file: File = match OpenOptions::new().read(true).write(true).create(true).open(string_path) {
+        let mut file: File = match OpenOptions::new().read(true).write(true).create_new(true).open(string_path) {
             Ok(file) => file,
             Err(why) => {
                 return Err(why);
             },
         };
+        lock_shared(&file, string_path)?;
 
         let mut dbfile = Dbfile {
             file: file,
-            string_path: string_path.clone()
+            string_path: string_path.clone(),
+            key: None,
+            watchers: Watchers::new(),
+            options: DbOptions::new(),
+            block_size: 0,
+            pending: None
         };
 
+        let mut features = options.get_features() & !FEATURE_ENCRYPTION;
         let mut header_block = HeaderBlock::new();
+        if let Some((salt, key)) = encryption {
+            features |= FEATURE_ENCRYPTION;
+            header_block.set_salt(salt);
+            header_block.set_key_check(key.key_check());
+            dbfile.key = Some(key);
+        }
+        header_block.set_features(features);
         debug!("Header block serialized: {:?}", header_block.serialize());
         dbfile.write_header_block(&mut header_block);
+        dbfile.read_settings();
 
         // Initialize the first block
         let mut block = dbfile.new_block();
@@ -50,37 +135,227 @@ impl Dbfile {
         return Ok(dbfile);
     }
 
-    pub fn open(string_path: &String) -> Dbfile {
+    /// Opens an existing database.  Files that aren't Gringotts databases are refused with
+    /// `InvalidData`, and ones written by a newer, incompatible version with `Unsupported`.
+    /// Encrypted databases are refused with `PermissionDenied`, since none of their blocks can be
+    /// read without the key; they're opened with `open_with_passphrase` instead.
+    pub fn open(string_path: &String) -> io::Result<Dbfile> {
+        let dbfile = Dbfile::open_locked(string_path)?;
+        if (dbfile.options.encryption) {
+            let message = format!("{} is encrypted, and needs its passphrase to be opened", Path::new(string_path).display());
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+        }
+
+        return Ok(dbfile);
+    }
+
+    /// Opens an existing database, deriving the key its blocks are encrypted with from the
+    /// passphrase.  A passphrase that doesn't match is refused with `PermissionDenied`.  Databases
+    /// that aren't encrypted don't need one, and are opened the same as with `open`.
+    pub fn open_with_passphrase(string_path: &String, passphrase: &str) -> io::Result<Dbfile> {
+        let mut dbfile = Dbfile::open_locked(string_path)?;
+        if (dbfile.options.encryption) {
+            if let Err(e) = dbfile.unlock(passphrase) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, e.get_message()));
+            }
+        }
+
+        return Ok(dbfile);
+    }
+
+    /// Reads an existing database's header, which isn't encrypted, so no passphrase is needed.
+    pub fn read_header(string_path: &String) -> io::Result<HeaderBlock> {
+        return Ok(Dbfile::open_locked(string_path)?.get_header_block());
+    }
+
+    /// Opens a database without its key, so only its header can be read until it's unlocked.
+    fn open_locked(string_path: &String) -> io::Result<Dbfile> {
 	    // Create a path to the desired file
 	    let path = Path::new(&string_path);
 	    let display = path.display();
-        let invalid_file = || panic!("{} is not a valid Gringotts database.", display);
+        let invalid_file = || io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid Gringotts database", display));
+
+        // Open the file, and keep it from being destroyed while it's open.
+        let mut file = OpenOptions::new().read(true).write(true).open(string_path)?;
+        lock_shared(&file, string_path)?;
 
-        // Open the file
-        let mut file = OpenOptions::new().read(true).write(true).open(string_path).unwrap();
+        // Undo any batch that was interrupted part way through being written.
+        {
+            let _lock = DbLock::acquire(string_path)?;
+            match journal::roll_back(&mut file, string_path) {
+                Ok(true) => warn!("Rolled back an unfinished write to {}", display),
+                Ok(false) => {},
+                Err(why) => return Err(io::Error::new(why.kind(), format!("Couldn't roll back the journal for {}: {}", display, why))),
+            }
+        }
 
         // Check the Magic String
         let mut buffer = vec![0; MAGIC_STRING.len()];
-        match file.read(&mut buffer) {
-            Err(why) => panic!("Couldn't read {}: {}", display, Error::description(&why)),
-            Ok(size) if size < MAGIC_STRING.len() => invalid_file(),
+        match file.read_exact(&mut buffer) {
+            Err(ref why) if why.kind() == io::ErrorKind::UnexpectedEof => return Err(invalid_file()),
+            Err(why) => return Err(why),
             Ok(_) => {
-                if (String::from_utf8(buffer).unwrap() != MAGIC_STRING) {
-                    invalid_file();
+                if (buffer != MAGIC_STRING.as_bytes()) {
+                    return Err(invalid_file());
                 }
             },
         }
 
-        // Return a new Dbfile object
-        Dbfile {
+        let mut dbfile = Dbfile {
             file: file,
-            string_path: string_path.clone()
+            string_path: string_path.clone(),
+            key: None,
+            watchers: Watchers::new(),
+            options: DbOptions::new(),
+            block_size: 0,
+            pending: None
+        };
+
+        // Refuse anything written with a format we can't promise to understand.  Older files are
+        // still opened, so that they can be upgraded.
+        let version = match dbfile.get_header_block().get_version() {
+            Ok(version) => version,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has an unreadable version: {}", display, e.get_message()))),
+        };
+        if (!version.is_readable_by(&CURRENT_DB_VERSION)) {
+            let message = format!("{} was written by a newer version of Gringotts ({}) and can't be opened", display, version);
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+        }
+        else if (version.needs_upgrade_to(&CURRENT_DB_VERSION)) {
+            warn!("{} is at version {} and should be upgraded.", display, version);
+        }
+
+        let features = dbfile.get_header_block().get_features();
+        if (features & !KNOWN_FEATURES != 0) {
+            let message = format!("{} uses features that this version of Gringotts doesn't support", display);
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message));
         }
+
+        dbfile.read_settings();
+        return Ok(dbfile);
     }
 
-    pub fn get_block_size(&mut self) -> u8 {
+    /// Derives the key for an encrypted database from its passphrase, which has to be done before
+    /// any of its blocks can be read.
+    fn unlock(&mut self, passphrase: &str) -> Result<(), InvalidInputError> {
+        let header_block = self.get_header_block();
+        if (header_block.get_features() & FEATURE_ENCRYPTION == 0) {
+            return Err(InvalidInputError::new("The database isn't encrypted"));
+        }
+
+        let key = BlockKey::derive(passphrase, &header_block.get_salt())?;
+        if (!key.matches_key_check(&header_block.get_key_check())) {
+            return Err(InvalidInputError::new("Incorrect passphrase"));
+        }
+
+        self.key = Some(key);
+        return Ok(());
+    }
+
+    /// Re-encrypts the database with a key derived from a new passphrase.  Every value is copied,
+    /// along with its type and expiry time, into a fresh file under the new key.  That file is
+    /// then swapped in for this one, so the old passphrase keeps working until the new one has
+    /// completely taken over.
+    pub fn change_passphrase(&mut self, passphrase: &str) -> io::Result<()> {
+        let options = self.get_options();
+        if (!options.encryption) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The database isn't encrypted"));
+        }
+
+        // Keep writers out until the new file has taken over, so that nothing is lost.
+        let _lock = self.lock();
+
+        let rekey_path = format!("{}.rekey", self.string_path);
+        let _ = fs::remove_file(&rekey_path);
+
+        let mut rekeyed = Dbfile::create_with_passphrase(&rekey_path, &options, passphrase)?;
+        rekeyed.set_version(&self.get_version());
+        if let Err(e) = self.copy_level(1, &mut rekeyed, &mut Vec::new()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e.get_message()));
+        }
+
+        self.replace_with(&rekey_path)?;
+        self.key = rekeyed.key;
+        return Ok(());
+    }
+
+    /// Copies every value in the level starting at `block_number`, and in the levels beneath it,
+    /// into `target` as it is, rather than as the string `get_val` would return.
+    fn copy_level(&mut self, block_number: u64, target: &mut Dbfile, path: &mut Vec<String>) -> Result<(), InvalidInputError> {
+        for mut block in self.get_level_blocks(block_number) {
+            for key in block.get_keys() {
+                path.push(key.clone());
+
+                let saved = block.save_value(&key);
+                if (saved.has_value()) {
+                    target.write_entry(&KeyChain::join(path), |b, k| b.restore_value(k, saved.clone()))?;
+                }
+
+                if let Some(child) = block.get_block_ref(&key) {
+                    self.copy_level(child, target, path)?;
+                }
+
+                path.pop();
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Deletes the database at `string_path`, along with its lock, journal and anything left
+    /// behind by an unfinished rebuild or rekey.  Files that aren't Gringotts databases are left
+    /// alone, and so are databases that anyone has open.
+    pub fn destroy(string_path: &String) -> io::Result<()> {
+        let mut buffer = vec![0; MAGIC_STRING.len()];
+        let mut file = File::open(string_path)?;
+        if (file.read_exact(&mut buffer).is_err() || buffer != MAGIC_STRING.as_bytes()) {
+            let message = format!("{} is not a valid Gringotts database", string_path);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        // Everyone with the database open holds a shared lock on it, so this only succeeds if
+        // nobody does.  Anyone who opens it after this finds it gone once they get their lock.
+        if (!try_lock_exclusive(&file)?) {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "The database is in use"));
+        }
+
+        fs::remove_file(string_path)?;
+
+        for sidecar in get_sidecar_paths(string_path) {
+            match fs::remove_file(&sidecar) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => return Err(io::Error::new(e.kind(), format!("Couldn't remove {}: {}", sidecar, e))),
+                _ => {},
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn get_path(&self) -> String {
+        return self.string_path.clone();
+    }
+
+    /// Moves the database at `other_path` over this one, and carries on working on it instead.
+    pub fn replace_with(&mut self, other_path: &String) -> io::Result<()> {
+        fs::rename(other_path, &self.string_path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.string_path)?;
+        lock_shared(&self.file, &self.string_path)?;
+        self.read_settings();
+        return Ok(());
+    }
+
+    fn read_settings(&mut self) {
         let mut header_block = self.get_header_block();
-        return header_block.get_block_size();
+        self.options = DbOptions::from_features(header_block.get_features());
+        self.block_size = header_block.get_block_size();
+    }
+
+    pub fn get_block_size(&mut self) -> u8 {
+        return self.block_size;
+    }
+
+    pub fn get_options(&mut self) -> DbOptions {
+        return self.options.clone();
     }
 
     pub fn get_number_of_blocks(&mut self) -> u64 {
@@ -89,13 +364,16 @@ impl Dbfile {
     }
 
     pub fn get_header_block(&mut self) -> HeaderBlock {
+        if let Some(bytes) = self.pending.as_ref().and_then(|p| p.get(&0)) {
+            return HeaderBlock::from_bytes(bytes.clone());
+        }
+
         match self.file.seek(SeekFrom::Start(0)) {
 	        Err(why) => panic!("couldn't seek on: {}", Error::description(&why)),
 	        Ok(_) => debug!("Successfully seeked to pos: {}", 0),
 	    }
 
-        let mut buffer = Vec::with_capacity(256 as usize);
-        unsafe{ buffer.set_len(256 as usize) };
+        let mut buffer = vec![0; HEADER_BLOCK_SIZE as usize];
 
 	    match self.file.read(&mut buffer) {
 	        Err(why) => panic!("couldn't read: {}", Error::description(&why)),
@@ -106,6 +384,11 @@ impl Dbfile {
     }
 
     pub fn write_header_block(&mut self, block: &mut HeaderBlock) {
+        if let Some(ref mut pending) = self.pending {
+            pending.insert(0, block.serialize());
+            return;
+        }
+
         let path = Path::new(&self.string_path);
 	    let display = path.display();
 
@@ -120,9 +403,21 @@ impl Dbfile {
         }
     }
 
-    pub fn get_block(&mut self, block_number: u64) -> NodeBlock {
+    fn get_block_position(&mut self, block_number: u64) -> u64 {
         let block_size_in_bytes = (self.get_block_size() as u64) * 1024;
-        let start_pos = ((block_number - 1) * block_size_in_bytes) + self::block::types::header::HEADER_BLOCK_SIZE;;
+        return ((block_number - 1) * block_size_in_bytes) + HEADER_BLOCK_SIZE;
+    }
+
+    /// Reads the raw bytes of a block, without interpreting them.
+    pub fn get_block_bytes(&mut self, block_number: u64) -> Vec<u8> {
+        let block_size_in_bytes = (self.get_block_size() as u64) * 1024;
+        let start_pos = self.get_block_position(block_number);
+
+        if let Some(bytes) = self.pending.as_ref().and_then(|p| p.get(&start_pos)) {
+            let mut buffer = bytes.clone();
+            buffer.resize(block_size_in_bytes as usize, 0);
+            return buffer;
+        }
 
         let path = Path::new(&self.string_path);
 	    let display = path.display();
@@ -132,23 +427,51 @@ impl Dbfile {
 	        Ok(_) => debug!("Successfully seeked to pos: {}", start_pos.to_string()),
 	    }
 
-        let mut buffer = Vec::with_capacity(block_size_in_bytes as usize);
-        unsafe{ buffer.set_len(block_size_in_bytes as usize) };
+        let mut buffer = vec![0; block_size_in_bytes as usize];
 
 	    match self.file.read(&mut buffer) {
 	        Err(why) => panic!("couldn't read {}: {}", display, Error::description(&why)),
 	        Ok(_) => debug!("Successfully read block: {}", block_number),
 	    }
 
-        return match NodeBlock::from_bytes(block_number, buffer) {
+        return buffer;
+    }
+
+    /// Reads a block, decrypting and decompressing it as needed.  Fails if the block is corrupt.
+    pub fn get_block(&mut self, block_number: u64) -> Result<NodeBlock, CorruptDataError> {
+        let buffer = self.get_block_bytes(block_number);
+        let options = self.get_options();
+
+        let mut block = NodeBlock::from_bytes_with_key(block_number, buffer, self.key.as_ref())?;
+        block.set_kvset_format(options.get_kvset_format());
+        block.set_compression(options.block_compression);
+        block.set_key(self.key.clone());
+        return Ok(block);
+    }
+
+    /// Reads a block that the database's own pointers lead to.  An open database always has the
+    /// key its blocks need, so one that can't be read means the file is corrupt, which, like a
+    /// failed read, there's no recovering from here.
+    fn read_block(&mut self, block_number: u64) -> NodeBlock {
+        return match self.get_block(block_number) {
             Ok(block) => block,
-            Err(_) => panic!("Couldn't get block"),
+            Err(e) => panic!("Block {} of {} is corrupt: {}", block_number, self.string_path, e.get_message()),
         };
     }
 
     pub fn write_block<T: SerializeableBlock>(&mut self, block: &mut T) {
-        let block_size_in_bytes = (self.get_block_size() as u64) * 1024;
-        let start_pos = ((block.get_block_number() - 1) * block_size_in_bytes) + self::block::types::header::HEADER_BLOCK_SIZE;
+        let block_number = block.get_block_number();
+        self.write_block_bytes(block_number, &block.serialize());
+    }
+
+    /// Writes raw bytes at the start of a block, without interpreting them.
+    pub fn write_block_bytes(&mut self, block_number: u64, bytes: &Vec<u8>) {
+        let start_pos = self.get_block_position(block_number);
+
+        if let Some(ref mut pending) = self.pending {
+            pending.insert(start_pos, bytes.clone());
+            return;
+        }
 
         let path = Path::new(&self.string_path);
 	    let display = path.display();
@@ -158,70 +481,463 @@ impl Dbfile {
 	        Ok(_) => debug!("Successfully seeked to pos: {}", start_pos.to_string()),
 	    }
 
-        match self.file.write(&mut block.serialize()) {
+        match self.file.write(bytes) {
         	Err(why) => panic!("couldn't write {}: {}", display, Error::description(&why)),
-	        Ok(_) => debug!("Successfully wrote block: {}", block.get_block_number()),
+	        Ok(_) => debug!("Successfully wrote block: {}", block_number),
         }
     }
 
     fn new_block(&mut self) -> NodeBlock {
-        let bytes = Vec::with_capacity(self.get_block_size() as usize);
+        // Use a block from the free list if there is one, and only grow the file when there isn't.
+        let mut header_block = self.get_header_block();
+        let new_block_number = match header_block.get_free_list() {
+            Some(n) => {
+                let next_free = self.read_block(n).get_right_block();
+                header_block.set_free_list(next_free);
+                n
+            },
+            None => {
+                let n = header_block.get_number_of_blocks() + 1;
+                header_block.set_number_of_blocks(n);
+                n
+            },
+        };
+        self.write_header_block(&mut header_block);
 
-        let old_num_blocks = self.get_number_of_blocks();
-        let new_num_blocks = old_num_blocks + 1;
+        let mut block = self.empty_block(new_block_number);
+        self.write_block(&mut block);
 
-        // Create the new block
-        let mut block = match NodeBlock::from_bytes(new_num_blocks, bytes) {
+        return block;
+    }
+
+    /// Makes an empty block with the database's settings, without writing it anywhere.
+    fn empty_block(&mut self, block_number: u64) -> NodeBlock {
+        // Size the block to a full page so that it knows how much room it has.
+        let bytes = vec![0; (self.get_block_size() as usize) * 1024];
+        let mut block = match NodeBlock::from_bytes(block_number, bytes) {
             Ok(b) => b,
             Err(_) => panic!("Error creating new block"),
         };
 
-        self.set_number_of_blocks(new_num_blocks);
-        self.write_block(&mut block);
-
+        let options = self.get_options();
+        block.set_kvset_format(options.get_kvset_format());
+        block.set_compression(options.block_compression);
+        block.set_key(self.key.clone());
         return block;
     }
 
-    fn set_number_of_blocks(&mut self, number: u64) {
+    /// Puts a block that's no longer in use onto the free list, so that `new_block` can use it
+    /// again.
+    fn free_block(&mut self, block_number: u64) {
+        let bytes = vec![0; (self.get_block_size() as usize) * 1024];
+        let mut block = match NodeBlock::from_bytes(block_number, bytes) {
+            Ok(b) => b,
+            Err(_) => panic!("Error freeing block {}", block_number),
+        };
+        block.set_block_type(BlockType::Free);
+
         let mut header_block = self.get_header_block();
-        header_block.set_number_of_blocks(number);
+        if let Some(next_free) = header_block.get_free_list() {
+            block.set_right_block(next_free);
+        }
+        self.write_block(&mut block);
+
+        header_block.set_free_list(Some(block_number));
         self.write_header_block(&mut header_block);
     }
 
-    fn set_block_size(&mut self, size: usize) {
-        let mut header_block = self.get_header_block();
-        return header_block.set_block_size(size);
+    /// Takes the database's write lock, which is held until the returned guard is dropped.
+    fn lock(&self) -> DbLock {
+        return match DbLock::acquire(&self.string_path) {
+            Ok(lock) => lock,
+            Err(why) => panic!("couldn't lock {}: {}", self.string_path, why),
+        };
     }
 
-    pub fn set_val(&mut self, key: &String, val: String) {
-        let keychain = KeyChain::parse(&key);
-        let key = keychain.get_final_key();
-        let mut block = self.get_block_from_ref(keychain, true).unwrap();
+    /// Sets the key's value.  Fails, leaving the database as it was, if the key and value are too
+    /// big to fit in a block.
+    pub fn set_val(&mut self, key: &String, val: String) -> Result<(), InvalidInputError> {
+        let _lock = self.lock();
+        return self.write_val(key, val);
+    }
+
+    /// Sets the key's value, returning the value it had before.
+    pub fn swap_val(&mut self, key: &String, val: String) -> Result<Option<String>, InvalidInputError> {
+        let _lock = self.lock();
+
+        let old = self.get_val(key);
+        self.write_val(key, val)?;
+        return Ok(old);
+    }
+
+    /// Removes a key's value, returning it.  Any keys beneath it are left alone.
+    pub fn delete_val(&mut self, key: &String) -> Option<String> {
+        let _lock = self.lock();
+        return self.remove_val(key);
+    }
+
+    /// Sets the key to `new` if its current value is `expected`, where None means the key has no
+    /// value.  Otherwise the key is left alone and its current value is returned.  The outer
+    /// error is for a value that can't be stored at all.
+    pub fn compare_and_swap(&mut self, key: &String, expected: Option<&str>, new: String) -> Result<Result<(), Option<String>>, InvalidInputError> {
+        let _lock = self.lock();
+
+        let current = self.get_val(key);
+        if (current.as_ref().map(|s| s.as_str()) != expected) {
+            return Ok(Err(current));
+        }
+
+        self.write_val(key, new)?;
+        return Ok(Ok(()));
+    }
+
+    /// Like `compare_and_swap`, but the new value expires once `ttl` has passed.
+    pub fn compare_and_swap_with_ttl(&mut self, key: &String, expected: Option<&str>, new: String, ttl: Duration) -> Result<Result<(), Option<String>>, InvalidInputError> {
+        let _lock = self.lock();
+        let expires = get_expiry_time(key, ttl)?;
+
+        let current = self.get_val(key);
+        if (current.as_ref().map(|s| s.as_str()) != expected) {
+            return Ok(Err(current));
+        }
 
-        match block.set(&key, val.clone()) {
+        self.write_entry(key, |block, k| block.set_with_expiry(k, new.clone(), expires).map(|_| ()))?;
+        self.watchers.notify(key, current, Some(new));
+        return Ok(Ok(()));
+    }
+
+    /// Sets the key only if it doesn't have a value yet, returning the value it has otherwise.
+    pub fn set_if_absent(&mut self, key: &String, new: String) -> Result<Result<(), Option<String>>, InvalidInputError> {
+        return self.compare_and_swap(key, None, new);
+    }
+
+    /// Removes the key's value only if it's `expected`, returning the current value otherwise.
+    pub fn delete_if_equals(&mut self, key: &String, expected: &str) -> Result<(), Option<String>> {
+        let _lock = self.lock();
+
+        let current = self.get_val(key);
+        if (current.as_ref().map(|s| s.as_str()) != Some(expected)) {
+            return Err(current);
+        }
+
+        self.remove_val(key);
+        return Ok(());
+    }
+
+    /// Subscribes to changes made through this handle to any key whose path starts with `prefix`.
+    /// Each change is sent once it's been written, and nothing is sent for values that expire.
+    /// Dropping the receiver unsubscribes.
+    pub fn watch(&mut self, prefix: &str) -> WatchReceiver {
+        return self.watchers.add(prefix);
+    }
+
+    /// Looks up the key's current value if anyone's watching it, so that they can be told what it
+    /// changed from.
+    fn get_watched_val(&mut self, key: &String) -> Option<Option<String>> {
+        if (!self.watchers.is_watched(key)) {
+            return None;
+        }
+
+        return Some(self.get_val(key));
+    }
+
+    /// Applies every change in the batch at once: either all of them end up in the file, or, if
+    /// writing is interrupted, none of them do.  Each dirty block is only written once.
+    pub fn write_batch(&mut self, batch: WriteBatch) -> io::Result<()> {
+        let _lock = self.lock();
+
+        self.pending = Some(BTreeMap::new());
+        self.watchers.hold();
+
+        let mut level: Option<(Vec<String>, Option<u64>)> = None;
+        for change in batch.into_changes() {
+            let known_level = match level {
+                Some((ref address, n)) if *address == change.address => n,
+                _ => self.find_level(&change.address, false),
+            };
+
+            // Only sets create the keys leading down to their level.
+            let level_block = match (known_level, &change.value) {
+                (None, &Some(_)) => self.find_level(&change.address, true),
+                (n, _) => n,
+            };
+
+            let written = match (level_block, change.value) {
+                (Some(n), Some(val)) => self.set_in_level(n, &change.key, &change.final_key, val),
+                (Some(n), None) => { self.remove_in_level(n, &change.key, &change.final_key); Ok(()) },
+                (None, Some(_)) => Err(InvalidInputError::too_big(&format!("{} is too big to fit in a block", change.key))),
+                (None, None) => Ok(()),
+            };
+
+            // Nothing has been written yet, so dropping the pending blocks undoes the batch.
+            if let Err(e) = written {
+                self.pending = None;
+                self.watchers.discard();
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, e.get_message()));
+            }
+
+            level = Some((change.address, level_block));
+        }
+
+        let writes = self.pending.take().unwrap();
+        return match journal::commit(&mut self.file, &self.string_path, &writes) {
+            Ok(_) => {
+                self.watchers.release();
+                Ok(())
+            },
             Err(e) => {
-                let mut new_block = self.split_block(&mut block);
+                self.watchers.discard();
+                Err(e)
+            },
+        };
+    }
 
-                match block.get_last_key() {
-                    Some(k) => {
-                        if (key > k) {
-                            new_block.set(&key, val);
-                            self.write_block(&mut new_block);
-                        }
-                        else {
-                            block.set(&key, val);
-                        }
-                    },
-                    _ => {
-                        block.set(&key, val);
-                        ();
-                    },
+    /// Finds the first block of the level of the keychain that the given keys lead to, creating
+    /// the keys along the way if `create_path` is set.
+    fn find_level(&mut self, address: &Vec<String>, create_path: bool) -> Option<u64> {
+        let mut keys = address.clone();
+        keys.reverse();
+        return self.get_block_inner(&mut keys, 1, create_path).map(|b| b.get_block_number());
+    }
+
+    fn get_in_level(&mut self, level: u64, final_key: &String) -> Option<String> {
+        let first_block = self.read_block(level);
+        return self.navigate_block_level(first_block, final_key).get(final_key);
+    }
+
+    fn remove_val(&mut self, key: &String) -> Option<String> {
+        let keychain = KeyChain::parse(key);
+        return match self.find_level(&keychain.as_vec(), false) {
+            Some(level) => self.remove_in_level(level, key, &keychain.get_final_key()),
+            None => None,
+        };
+    }
+
+    fn remove_in_level(&mut self, level: u64, key: &String, final_key: &String) -> Option<String> {
+        let first_block = self.read_block(level);
+        let mut block = self.navigate_block_level(first_block, final_key);
+
+        let removed = block.delete(final_key);
+        if (removed.is_some()) {
+            self.write_block(&mut block);
+            self.watchers.notify(key, removed.clone(), None);
+        }
+
+        return removed;
+    }
+
+    /// Sets a value that expires once `ttl` has passed, after which it reads as though it had never
+    /// been set.  A TTL too long to work out the expiry time of is refused.
+    pub fn set_with_ttl(&mut self, key: &String, val: String, ttl: Duration) -> Result<(), InvalidInputError> {
+        let _lock = self.lock();
+        let expires = get_expiry_time(key, ttl)?;
+
+        let old = self.get_watched_val(key);
+        self.write_entry(key, |block, k| block.set_with_expiry(k, val.clone(), expires).map(|_| ()))?;
+        if let Some(old) = old {
+            self.watchers.notify(key, old, Some(val));
+        }
+        return Ok(());
+    }
+
+    /// Changes the key's value to expire once `ttl` has passed, keeping the value as it is, integer
+    /// or string.  Returns false if the key has no value.
+    pub fn set_ttl(&mut self, key: &String, ttl: Duration) -> Result<bool, InvalidInputError> {
+        let _lock = self.lock();
+        let expires = get_expiry_time(key, ttl)?;
+
+        let keychain = KeyChain::parse(key);
+        let final_key = keychain.get_final_key();
+        let saved = match self.get_block_from_ref(keychain, false) {
+            Some(mut block) if block.get(&final_key).is_some() => block.save_value(&final_key),
+            _ => return Ok(false),
+        };
+
+        // The expiry time takes up room of its own, so the block may need splitting.
+        let saved = saved.expiring_at(expires);
+        self.write_entry(key, |block, k| block.restore_value(k, saved.clone()))?;
+        return Ok(true);
+    }
+
+    /// Removes every expired value in the database, freeing any blocks that are left empty.
+    /// Returns the number of values that were removed.
+    pub fn expire(&mut self) -> u64 {
+        let _lock = self.lock();
+        let (removed, _) = self.expire_level(1, now_millis());
+        return removed;
+    }
+
+    /// Removes the expired values from one level of the keychain and everything beneath it.
+    /// Returns how many were removed, and whether the level was left with nothing in it.
+    fn expire_level(&mut self, first_block: u64, now: u64) -> (u64, bool) {
+        let mut removed = 0;
+        let mut kept: Vec<NodeBlock> = Vec::new();
+
+        for mut block in self.get_level_blocks(first_block) {
+            let mut changed = false;
+
+            let expired = block.remove_expired(now);
+            if (expired > 0) {
+                removed += expired;
+                changed = true;
+            }
+
+            for key in block.get_keys() {
+                if let Some(child) = block.get_block_ref(&key) {
+                    let (child_removed, child_is_empty) = self.expire_level(child, now);
+                    removed += child_removed;
+
+                    if (child_is_empty) {
+                        block.delete_block_ref(&key);
+                        self.free_block(child);
+                        changed = true;
+                    }
                 }
-            },
-            Ok(_) => {}
+            }
+
+            // Unlink empty blocks from the block to their left, which is the last one we kept.
+            if (block.is_empty() && kept.len() > 0) {
+                let right_block = block.get_right_block().unwrap_or(0);
+                let left_block = kept.last_mut().unwrap();
+                left_block.set_right_block(right_block);
+                self.write_block(left_block);
+                self.free_block(block.get_block_number());
+                continue;
+            }
+
+            if (changed) {
+                self.write_block(&mut block);
+            }
+            kept.push(block);
+        }
+
+        // The first block is what the level above points to, so rather than unlinking it when
+        // it's empty, move the next block's keys into it.
+        if (kept.len() > 1 && kept[0].is_empty()) {
+            let mut next_block = kept.remove(1);
+            let right_block = next_block.get_right_block().unwrap_or(0);
+            kept[0].set_kvset(next_block.take_kvset());
+            kept[0].set_right_block(right_block);
+            self.write_block(&mut kept[0]);
+            self.free_block(next_block.get_block_number());
+        }
+
+        return (removed, kept.len() == 1 && kept[0].is_empty());
+    }
+
+    fn write_val(&mut self, key: &String, val: String) -> Result<(), InvalidInputError> {
+        let keychain = KeyChain::parse(key);
+        let level = self.create_level(key, &keychain)?;
+        return self.set_in_level(level, key, &keychain.get_final_key(), val);
+    }
+
+    fn set_in_level(&mut self, level: u64, key: &String, final_key: &String, val: String) -> Result<(), InvalidInputError> {
+        let old = match self.watchers.is_watched(key) {
+            true  => Some(self.get_in_level(level, final_key)),
+            false => None,
+        };
+
+        self.write_entry_at(level, final_key, |block, k| block.set(k, val.clone()).map(|_| ()))?;
+        if let Some(old) = old {
+            self.watchers.notify(key, old, Some(val));
+        }
+        return Ok(());
+    }
+
+    /// Finds the level the key belongs in, creating the keys leading down to it.  That only fails
+    /// if one of them is too big to fit in a block.
+    fn create_level(&mut self, key: &String, keychain: &KeyChain) -> Result<u64, InvalidInputError> {
+        return match self.find_level(&keychain.as_vec(), true) {
+            Some(n) => Ok(n),
+            None => Err(InvalidInputError::too_big(&format!("{} is too big to fit in a block", key))),
+        };
+    }
+
+    /// Stores something for the key with `put`, splitting the block it belongs in if there's no
+    /// room left.
+    fn write_entry<F>(&mut self, key: &String, put: F) -> Result<(), InvalidInputError> where F: Fn(&mut NodeBlock, &String) -> Result<(), NoRoomError> {
+        let keychain = KeyChain::parse(&key);
+        let level = self.create_level(key, &keychain)?;
+        return self.write_entry_at(level, &keychain.get_final_key(), put);
+    }
+
+    fn write_entry_at<F>(&mut self, level: u64, key: &String, put: F) -> Result<(), InvalidInputError> where F: Fn(&mut NodeBlock, &String) -> Result<(), NoRoomError> {
+        let first_block = self.read_block(level);
+        let mut block = self.navigate_block_level(first_block, key);
+
+        // Make room by clearing out anything that has expired while we're here.
+        block.remove_expired(now_millis());
+
+        if (put(&mut block, key).is_ok()) {
+            self.write_block(&mut block);
+            return Ok(());
+        }
+
+        // No amount of splitting helps an entry that doesn't fit in a block of its own, which
+        // would also keep any subtree the key already points to.
+        let mut alone = self.empty_block(0);
+        if let Some(n) = block.get_block_ref(key) {
+            let _ = alone.set_block_ref(key, n);
+        }
+        if (put(&mut alone, key).is_err()) {
+            return Err(InvalidInputError::too_big(&format!("The value for {} is too big to fit in a block", key)));
+        }
+
+        let keys = block.get_keys();
+        if (keys.len() > 1) {
+            // Either half can still be too full, so look for the key's block again.
+            self.split_block(&mut block);
+            return self.write_entry_at(level, key, put);
+        }
+
+        // There's only one other entry, so the two get a block each, in order.
+        let mut new_block = self.new_block();
+        if let Some(n) = block.get_right_block() {
+            new_block.set_right_block(n);
+        }
+        block.set_right_block(new_block.get_block_number());
+
+        if (keys.len() == 1 && *key < keys[0]) {
+            new_block.set_kvset(block.take_kvset());
+            let _ = put(&mut block, key);
+        }
+        else {
+            let _ = put(&mut new_block, key);
         }
 
+        self.write_block(&mut new_block);
         self.write_block(&mut block);
+        return Ok(());
+    }
+
+    /// Adds `delta` to the integer stored at the key and returns the result, all while holding the
+    /// write lock.  A key without a value starts from zero, and one holding a string is converted
+    /// if the string is a number.
+    pub fn increment(&mut self, key: &String, delta: i64) -> Result<i64, InvalidInputError> {
+        let _lock = self.lock();
+
+        let keychain = KeyChain::parse(key);
+        let final_key = keychain.get_final_key();
+        let old = match self.get_block_from_ref(keychain, false) {
+            Some(block) => block.get(&final_key),
+            None => None,
+        };
+        let current = match old {
+            Some(ref s) => match s.parse::<i64>() {
+                Ok(n) => n,
+                Err(_) => return Err(InvalidInputError::new(&format!("The value of {} isn't an integer", key))),
+            },
+            None => 0,
+        };
+
+        let value = match current.checked_add(delta) {
+            Some(n) => n,
+            None => return Err(InvalidInputError::new(&format!("Incrementing {} would overflow", key))),
+        };
+
+        self.write_entry(key, |block, k| block.set_integer(k, value).map(|_| ()))?;
+        self.watchers.notify(key, old, Some(value.to_string()));
+        return Ok(value);
     }
 
     fn navigate_block_level(&mut self, block: NodeBlock, key: &String) -> NodeBlock {
@@ -236,7 +952,7 @@ impl Dbfile {
                     if (key > &k) {
                         match next_block.get_right_block() {
                             Some(n) => {
-                                next_block = self.get_block(n);
+                                next_block = self.read_block(n);
                             },
                             None => break 'toTheRight
                         }
@@ -252,7 +968,7 @@ impl Dbfile {
     }
 
     fn get_block_inner(&mut self, keys: &mut Vec<String>, blocknum: u64, create_path: bool) -> Option<NodeBlock> {
-        let mut block = self.get_block(blocknum);
+        let mut block = self.read_block(blocknum);
         let key = match keys.pop() {
             Some(s) => s,
             None => return Some(block)
@@ -264,12 +980,18 @@ impl Dbfile {
         return match block.get_block_ref(&key) {
             Some(b) => self.get_block_inner(keys, b, create_path),
             None if create_path => {
-                let new_block = self.new_block();
-                block.set_block_type(BlockType::Root);
-                self.write_block(&mut block);
-                block.set_block_ref(&key, new_block.get_block_number());
-                self.write_block(&mut block);
-                return self.get_block_inner(keys, new_block.get_block_number(), create_path);
+                let new_block_number = self.new_block().get_block_number();
+
+                // The level may be full, so this goes through the same path as values do.
+                let written = self.write_entry_at(blocknum, &key, |b, k| {
+                    b.set_block_type(BlockType::Root);
+                    return b.set_block_ref(k, new_block_number).map(|_| ());
+                });
+                if (written.is_err()) {
+                    self.free_block(new_block_number);
+                    return None;
+                }
+                return self.get_block_inner(keys, new_block_number, create_path);
             },
             None => None
         };
@@ -289,6 +1011,116 @@ impl Dbfile {
         };
     }
 
+    /// Returns the given block along with every block to its right, which together hold all of
+    /// the keys for one level of the keychain.
+    pub fn get_level_blocks(&mut self, block_number: u64) -> Vec<NodeBlock> {
+        let mut blocks = Vec::new();
+        let mut next = Some(block_number);
+
+        while let Some(n) = next {
+            let mut block = self.read_block(n);
+            next = block.get_right_block();
+            blocks.push(block);
+        }
+
+        return blocks;
+    }
+
+    /// Lists the keys in the level beneath the keys in `address`, each with its value and whether
+    /// it has keys beneath it.  Returns None if there's no such level.
+    pub fn list_level(&mut self, address: &Vec<String>) -> Option<Vec<(String, Option<String>, bool)>> {
+        let level = match self.find_level(address, false) {
+            Some(n) => n,
+            None => return None,
+        };
+
+        let mut keys = Vec::new();
+        for block in self.get_level_blocks(level) {
+            for key in block.get_keys() {
+                keys.push((key.clone(), block.get(&key), block.get_block_ref(&key).is_some()));
+            }
+        }
+
+        return Some(keys);
+    }
+
+    /// Returns the full path and value of every key beneath `prefix`, in order.  An empty prefix
+    /// returns every key in the database.
+    pub fn scan(&mut self, prefix: &String) -> Vec<(String, String)> {
+        let mut address = Vec::new();
+        if (!prefix.is_empty()) {
+            let keychain = KeyChain::parse(prefix);
+            address = keychain.as_vec();
+            address.push(keychain.get_final_key());
+        }
+
+        let level = match self.find_level(&address, false) {
+            Some(n) => n,
+            None => return Vec::new(),
+        };
+
+        let mut found = Vec::new();
+        dump::walk(self, level, &mut address, &mut |path, value| found.push((KeyChain::join(path), value)));
+        return found;
+    }
+
+    /// Lists the paths of up to `limit` keys with values, in the order `scan` returns them, starting
+    /// after the key `after`.  Nothing that comes before `after` is read, other than the blocks
+    /// leading down to it, so a long scan can be done a piece at a time.
+    pub fn scan_keys(&mut self, after: Option<&String>, limit: usize) -> Vec<String> {
+        let after = after.map(|key| {
+            let keychain = KeyChain::parse(key);
+            let mut address = keychain.as_vec();
+            address.push(keychain.get_final_key());
+            address
+        });
+
+        let mut found = Vec::new();
+        self.scan_keys_in_level(1, &mut Vec::new(), after.as_ref().map(|a| a.as_slice()), limit, &mut found);
+        return found;
+    }
+
+    /// Adds the keys from one level, and the levels beneath it, to `found` until it holds `limit`
+    /// of them.  `after` is what's left of the starting key's address beneath this level, if the
+    /// level leads to it.
+    fn scan_keys_in_level(&mut self, first_block: u64, path: &mut Vec<String>, after: Option<&[String]>, limit: usize, found: &mut Vec<String>) {
+        let mut block = self.read_block(first_block);
+        if let Some(after) = after {
+            block = self.navigate_block_level(block, &after[0]);
+        }
+
+        loop {
+            for key in block.get_keys() {
+                if (found.len() >= limit) {
+                    return;
+                }
+
+                // Keys before the starting key's, and everything beneath them, have been scanned
+                // already, and so has the value of a key leading down to it.
+                let rest = match after {
+                    Some(after) if key < after[0] => continue,
+                    Some(after) if key == after[0] => Some(&after[1..]),
+                    _ => None,
+                };
+
+                path.push(key.clone());
+                if (rest.is_none() && block.get(&key).is_some()) {
+                    found.push(KeyChain::join(path));
+                }
+                if let Some(child) = block.get_block_ref(&key) {
+                    let child_after = rest.filter(|r| !r.is_empty());
+                    self.scan_keys_in_level(child, path, child_after, limit, found);
+                }
+                path.pop();
+            }
+
+            match block.get_right_block() {
+                Some(n) => block = self.read_block(n),
+                None => return,
+            }
+        }
+    }
+
     pub fn get_val(&mut self, keystring: &String) -> Option<String> {
         let keychain = KeyChain::parse(keystring);
         let key = keychain.get_final_key();
@@ -307,6 +1139,11 @@ impl Dbfile {
         let kvset = block.split();
         let mut new_block = self.new_block();
 
+        // Keep the rest of the level reachable from the new block.
+        if let Some(n) = block.get_right_block() {
+            new_block.set_right_block(n);
+        }
+
         block.set_right_block(new_block.get_block_number());
         new_block.set_kvset(kvset);
 
@@ -317,6 +1154,328 @@ impl Dbfile {
     }
 
     pub fn get_version(&mut self) -> Version {
-        return self.get_header_block().get_version();
+        return match self.get_header_block().get_version() {
+            Ok(version) => version,
+            Err(e) => panic!("Couldn't read version: {}", e.get_message()),
+        };
+    }
+
+    pub fn set_version(&mut self, version: &Version) {
+        let mut header_block = self.get_header_block();
+        header_block.set_version(version);
+        self.write_header_block(&mut header_block);
+    }
+}
+
+/// When a value given `ttl` now expires, in milliseconds since the Unix epoch.  A TTL that would
+/// take that past what a u64 can hold is refused.
+fn get_expiry_time(key: &String, ttl: Duration) -> Result<u64, InvalidInputError> {
+    let expires = ttl.as_secs().checked_mul(1000)
+        .and_then(|ms| ms.checked_add(ttl.subsec_millis() as u64))
+        .and_then(|ms| ms.checked_add(now_millis()));
+
+    return match expires {
+        Some(t) => Ok(t),
+        None => Err(InvalidInputError::new(&format!("The TTL for {} is too long", key))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+    use std::env;
+    use std::thread;
+
+    #[test]
+    fn conditional_writes() {
+        let temp_db = TempDb::new("gringotts-conditional-writes-test.gdb");
+        let mut dbfile = temp_db.create();
+        let key = String::from("services/leader");
+
+        assert_eq!(dbfile.set_if_absent(&key, String::from("a")).ok(), Some(Ok(())));
+        assert_eq!(dbfile.set_if_absent(&key, String::from("b")).ok(), Some(Err(Some(String::from("a")))));
+
+        assert_eq!(dbfile.compare_and_swap(&key, Some("b"), String::from("c")).ok(), Some(Err(Some(String::from("a")))));
+        assert_eq!(dbfile.compare_and_swap(&key, Some("a"), String::from("c")).ok(), Some(Ok(())));
+        assert_eq!(dbfile.get_val(&key), Some(String::from("c")));
+
+        assert_eq!(dbfile.delete_if_equals(&key, "a"), Err(Some(String::from("c"))));
+        assert_eq!(dbfile.delete_if_equals(&key, "c"), Ok(()));
+        assert_eq!(dbfile.get_val(&key), None);
+        assert_eq!(dbfile.delete_if_equals(&key, "c"), Err(None));
+        assert_eq!(dbfile.compare_and_swap(&key, None, String::from("d")).ok(), Some(Ok(())));
+        assert_eq!(dbfile.swap_val(&key, String::from("e")).ok(), Some(Some(String::from("d"))));
+        assert_eq!(dbfile.get_val(&key), Some(String::from("e")));
+    }
+
+    #[test]
+    fn values_too_big_for_a_block() {
+        let temp_db = TempDb::new("gringotts-oversized-value-test.gdb");
+        let mut dbfile = temp_db.create();
+        let x = String::from("x");
+        dbfile.set_val(&x, String::from("a")).ok().unwrap();
+
+        assert!(dbfile.set_val(&String::from("big"), "v".repeat(5000)).is_err());
+        assert!(dbfile.set_val(&x, "v".repeat(5000)).is_err());
+        assert!(dbfile.increment(&String::from("big"), 1).is_ok());
+        assert_eq!(dbfile.get_val(&x), Some(String::from("a")));
+        assert_eq!(dbfile.get_val(&String::from("big")), Some(String::from("1")));
+
+        // Values that only fit in a block of their own end up in order on either side of x.
+        for key in vec!("m", "z", "b") {
+            dbfile.set_val(&String::from(key), key.repeat(3000)).ok().unwrap();
+        }
+        for key in vec!("m", "z", "b") {
+            assert_eq!(dbfile.get_val(&String::from(key)), Some(key.repeat(3000)));
+        }
+        assert_eq!(dbfile.get_val(&x), Some(String::from("a")));
+        assert_eq!(dbfile.get_val(&String::from("big")), Some(String::from("1")));
+    }
+
+    #[test]
+    fn changing_the_passphrase() {
+        let temp_db = TempDb::new("gringotts-rekey-test.gdb");
+        let path = temp_db.get_path();
+        let mut options = DbOptions::new();
+        options.encryption = true;
+        let mut dbfile = Dbfile::create_with_passphrase(path, &options, "old").unwrap();
+
+        dbfile.increment(&String::from("counter"), 41).ok().unwrap();
+        dbfile.set_val(&String::from("name"), String::from("vault")).ok().unwrap();
+        dbfile.set_with_ttl(&String::from("session"), String::from("token"), Duration::from_secs(3600)).ok().unwrap();
+        let expires = inspect::describe_block(&mut dbfile, 1).ok().unwrap().entries.ok().unwrap()[2].expires;
+
+        dbfile.change_passphrase("new").unwrap();
+        drop(dbfile);
+
+        assert_eq!(Dbfile::open(path).err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(Dbfile::open_with_passphrase(path, "old").err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+        let mut dbfile = Dbfile::open_with_passphrase(path, "new").unwrap();
+
+        // The counter is still stored as an integer, and the session still expires when it did.
+        let entries = inspect::describe_block(&mut dbfile, 1).ok().unwrap().entries.ok().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].integer, Some(41));
+        assert_eq!(entries[1].value, Some(String::from("vault")));
+        assert_eq!(entries[2].value, Some(String::from("token")));
+        assert!(expires.is_some());
+        assert_eq!(entries[2].expires, expires);
+    }
+
+    #[test]
+    fn incrementing() {
+        let temp_db = TempDb::new("gringotts-increment-test.gdb");
+        let mut dbfile = temp_db.create();
+        let key = String::from("counters/requests");
+
+        assert_eq!(dbfile.increment(&key, 1).ok(), Some(1));
+        assert_eq!(dbfile.increment(&key, -5).ok(), Some(-4));
+        assert_eq!(dbfile.get_val(&key), Some(String::from("-4")));
+
+        dbfile.set_val(&key, String::from("not a number")).ok().unwrap();
+        assert!(dbfile.increment(&key, 1).is_err());
+
+        dbfile.set_val(&key, String::from(i64::max_value().to_string())).ok().unwrap();
+        assert!(dbfile.increment(&key, 1).is_err());
+    }
+
+    #[test]
+    fn expiring_values() {
+        let temp_db = TempDb::new("gringotts-expiry-test.gdb");
+        let mut dbfile = temp_db.create();
+        let value = String::from("a session token that takes up some room");
+
+        dbfile.set_val(&String::from("kept"), String::from("yes")).ok().unwrap();
+        dbfile.set_with_ttl(&String::from("later"), String::from("still here"), Duration::from_secs(3600)).ok().unwrap();
+        for i in 0..300 {
+            dbfile.set_with_ttl(&format!("sessions/{:03}", i), value.clone(), Duration::from_millis(500)).ok().unwrap();
+        }
+
+        let number_of_blocks = dbfile.get_number_of_blocks();
+        assert!(number_of_blocks > 2);
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(dbfile.get_val(&String::from("sessions/000")), None);
+        assert_eq!(dbfile.get_val(&String::from("later")), Some(String::from("still here")));
+
+        assert!(dbfile.set_with_ttl(&String::from("kept"), String::from("no"), Duration::from_secs(u64::max_value())).is_err());
+        assert_eq!(dbfile.expire(), 300);
+        assert_eq!(dbfile.expire(), 0);
+        assert_eq!(dbfile.get_val(&String::from("kept")), Some(String::from("yes")));
+        assert_eq!(dbfile.get_val(&String::from("later")), Some(String::from("still here")));
+
+        // The blocks that held the sessions are reused rather than growing the file.
+        for i in 0..300 {
+            dbfile.set_val(&format!("users/{:03}", i), value.clone()).ok().unwrap();
+        }
+        assert_eq!(dbfile.get_number_of_blocks(), number_of_blocks);
+        assert_eq!(dbfile.get_val(&String::from("users/299")), Some(value));
+    }
+
+    #[test]
+    fn watching_a_subtree() {
+        let temp_db = TempDb::new("gringotts-watch-test.gdb");
+        let mut dbfile = temp_db.create();
+        let events = dbfile.watch("config/service-a/");
+
+        dbfile.set_val(&String::from("config/service-a/port"), String::from("80")).ok().unwrap();
+        dbfile.set_val(&String::from("config/service-b/port"), String::from("81")).ok().unwrap();
+        assert_eq!(dbfile.compare_and_swap(&String::from("config/service-a/port"), Some("80"), String::from("8080")).ok(), Some(Ok(())));
+        assert_eq!(dbfile.increment(&String::from("config/service-a/restarts"), 1).ok(), Some(1));
+        dbfile.delete_val(&String::from("config/service-a/port"));
+
+        let received: Vec<WatchEvent> = events.try_iter().collect();
+        let expected = vec!(
+            ("config/service-a/port", None, Some("80")),
+            ("config/service-a/port", Some("80"), Some("8080")),
+            ("config/service-a/restarts", None, Some("1")),
+            ("config/service-a/port", Some("8080"), None),
+        );
+
+        assert_eq!(received.len(), expected.len());
+        for (event, (path, old, new)) in received.iter().zip(expected) {
+            assert_eq!(event.path, path);
+            assert_eq!(event.old, old.map(String::from));
+            assert_eq!(event.new, new.map(String::from));
+        }
+    }
+
+    #[test]
+    fn writing_a_batch() {
+        let temp_db = TempDb::new("gringotts-batch-test.gdb");
+        let path = temp_db.get_path();
+        let mut dbfile = temp_db.create();
+        dbfile.set_val(&String::from("users/old"), String::from("gone soon")).ok().unwrap();
+        let events = dbfile.watch("users/");
+
+        let mut batch = WriteBatch::new();
+        for i in 0..500 {
+            batch.set(&format!("users/{:03}/name", i), format!("user {}", i));
+            batch.set(&format!("groups/{}", i % 7), format!("group {}", i % 7));
+        }
+        batch.delete(&String::from("users/old"));
+        batch.delete(&String::from("missing/key"));
+        dbfile.write_batch(batch).unwrap();
+
+        assert_eq!(dbfile.get_val(&String::from("users/000/name")), Some(String::from("user 0")));
+        assert_eq!(dbfile.get_val(&String::from("users/499/name")), Some(String::from("user 499")));
+        assert_eq!(dbfile.get_val(&String::from("groups/6")), Some(String::from("group 6")));
+        assert_eq!(dbfile.get_val(&String::from("users/old")), None);
+        assert!(!Path::new(&journal::get_journal_path(&path)).exists());
+        assert_eq!(events.try_iter().count(), 501);
+
+        // A reopened handle sees the same thing.
+        let mut reopened = Dbfile::open(&path).unwrap();
+        assert_eq!(reopened.get_val(&String::from("users/250/name")), Some(String::from("user 250")));
+    }
+
+    #[test]
+    fn listing_a_level() {
+        let temp_db = TempDb::new("gringotts-list-level-test.gdb");
+        let mut dbfile = temp_db.create();
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        dbfile.set_val(&String::from("a/b"), String::from("2")).ok().unwrap();
+        dbfile.set_val(&String::from("c/d"), String::from("3")).ok().unwrap();
+
+        let root = dbfile.list_level(&Vec::new()).unwrap();
+        assert_eq!(root, vec!((String::from("a"), Some(String::from("1")), true), (String::from("c"), None, true)));
+        assert_eq!(dbfile.list_level(&vec!(String::from("c"))).unwrap(), vec!((String::from("d"), Some(String::from("3")), false)));
+        assert_eq!(dbfile.list_level(&vec!(String::from("x"))), None);
+    }
+
+    #[test]
+    fn scanning_a_prefix() {
+        let temp_db = TempDb::new("gringotts-scan-test.gdb");
+        let mut dbfile = temp_db.create();
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        dbfile.set_val(&String::from("a/b"), String::from("2")).ok().unwrap();
+        dbfile.set_val(&String::from("a/b/c"), String::from("3")).ok().unwrap();
+        dbfile.set_val(&String::from("d"), String::from("4")).ok().unwrap();
+
+        let pair = |k: &str, v: &str| (String::from(k), String::from(v));
+        assert_eq!(dbfile.scan(&String::from("a")), vec!(pair("a/b", "2"), pair("a/b/c", "3")));
+        assert_eq!(dbfile.scan(&String::from("")).len(), 4);
+        assert!(dbfile.scan(&String::from("x")).is_empty());
+    }
+
+    #[test]
+    fn destroying_a_database() {
+        let temp_db = TempDb::new("gringotts-destroy-test.gdb");
+        let path = temp_db.get_path();
+        let dbfile = temp_db.create();
+        fs::write(journal::get_journal_path(&path), b"").unwrap();
+
+        // Nobody has to be writing for the database to be in use.
+        assert_eq!(Dbfile::destroy(&path).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        let reader = Dbfile::open(&path).unwrap();
+        drop(dbfile);
+        assert_eq!(Dbfile::destroy(&path).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        drop(reader);
+
+        Dbfile::destroy(&path).unwrap();
+        assert!(!Path::new(&path).exists());
+        assert!(!Path::new(&get_lock_path(&path)).exists());
+        assert!(!Path::new(&journal::get_journal_path(&path)).exists());
+
+        let not_a_db = env::temp_dir().join("gringotts-destroy-not-a-db.txt");
+        let not_a_db = String::from(not_a_db.to_str().unwrap());
+        fs::write(&not_a_db, b"important notes").unwrap();
+        assert_eq!(Dbfile::destroy(&not_a_db).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Path::new(&not_a_db).exists());
+        fs::remove_file(&not_a_db).unwrap();
+    }
+
+    #[test]
+    fn refusing_to_open() {
+        let missing = String::from(env::temp_dir().join("gringotts-open-missing.gdb").to_str().unwrap());
+        assert_eq!(Dbfile::open(&missing).err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        let not_a_db = String::from(env::temp_dir().join("gringotts-open-not-a-db.txt").to_str().unwrap());
+        fs::write(&not_a_db, b"notes").unwrap();
+        assert_eq!(Dbfile::open(&not_a_db).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&not_a_db).unwrap();
+        let _ = fs::remove_file(get_lock_path(&not_a_db));
+
+        let temp_db = TempDb::new("gringotts-open-newer-test.gdb");
+        let path = temp_db.get_path();
+        let mut dbfile = temp_db.create();
+        let mut newer = CURRENT_DB_VERSION;
+        newer.major += 1;
+        dbfile.set_version(&newer);
+        drop(dbfile);
+        assert_eq!(Dbfile::open(&path).err().unwrap().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn racing_compare_and_swaps() {
+        let temp_db = TempDb::new("gringotts-racing-cas-test.gdb");
+        let path = temp_db.get_path();
+        let mut dbfile = temp_db.create();
+        let key = String::from("counter");
+        dbfile.set_val(&key, String::from("0")).ok().unwrap();
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let path = path.clone();
+            let key = key.clone();
+            thread::spawn(move || {
+                let mut dbfile = Dbfile::open(&path).unwrap();
+                for _ in 0..25 {
+                    let mut current = dbfile.get_val(&key);
+                    loop {
+                        let next = (current.clone().unwrap().parse::<u64>().unwrap() + 1).to_string();
+                        match dbfile.compare_and_swap(&key, current.as_ref().map(|s| s.as_str()), next).ok().unwrap() {
+                            Ok(_) => break,
+                            Err(actual) => current = actual,
+                        }
+                    }
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(dbfile.get_val(&key), Some(String::from("100")));
     }
 }
@@ -0,0 +1,55 @@
+use dbfile::block::*;
+use dbfile::block::kvset::*;
+
+/// The choices that are made once, when a database is created.
+#[derive(Clone)]
+pub struct DbOptions {
+    /// Store the keys in each block with their shared prefixes compressed.
+    pub prefix_compression: bool,
+    /// Compress the body of each block before writing it.
+    pub block_compression: bool,
+    /// Encrypt the body of each block.  Databases can only be created with this set through
+    /// `Dbfile::create_with_passphrase`.
+    pub encryption: bool,
+}
+
+impl DbOptions {
+    pub fn new() -> DbOptions {
+        return DbOptions {
+            prefix_compression: false,
+            block_compression: false,
+            encryption: false,
+        };
+    }
+
+    pub fn from_features(features: u32) -> DbOptions {
+        return DbOptions {
+            prefix_compression: features & FEATURE_PREFIX_COMPRESSION != 0,
+            block_compression: features & FEATURE_BLOCK_COMPRESSION != 0,
+            encryption: features & FEATURE_ENCRYPTION != 0,
+        };
+    }
+
+    pub fn get_features(&self) -> u32 {
+        let mut features = 0;
+        if (self.prefix_compression) {
+            features |= FEATURE_PREFIX_COMPRESSION;
+        }
+        if (self.block_compression) {
+            features |= FEATURE_BLOCK_COMPRESSION;
+        }
+        if (self.encryption) {
+            features |= FEATURE_ENCRYPTION;
+        }
+
+        return features;
+    }
+
+    /// The format that blocks in the database are written in.
+    pub fn get_kvset_format(&self) -> KVSetFormat {
+        return match self.prefix_compression {
+            true  => KVSetFormat::SlottedPrefix,
+            false => KVSetFormat::Slotted,
+        };
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use dbfile::Dbfile;
+use dbfile::block::*;
+
+/// Values are counted by size into buckets that end just below each of these sizes, in bytes, with
+/// one more bucket for anything larger.
+pub const VALUE_SIZE_LIMITS: [usize; 5] = [16, 64, 256, 1024, 4096];
+
+/// Node blocks are counted by how full they are, in buckets of 10%.
+pub const FILL_BUCKETS: usize = 10;
+
+/// The shape of a database's keychain and how well its blocks are used.
+pub struct DbStats {
+    /// Keys that hold a value.
+    pub keys: u64,
+    /// Keys that lead to a level beneath them.
+    pub subtrees: u64,
+    /// How many levels there are at each depth, with the root level at depth 0.
+    pub levels_by_depth: BTreeMap<usize, u64>,
+    /// How many keys, with or without values, there are at each depth.
+    pub keys_by_depth: BTreeMap<usize, u64>,
+    /// How many levels span each number of blocks.  Looking up a key scans along its level's
+    /// chain, so long chains mean slow lookups.
+    pub chain_lengths: BTreeMap<usize, u64>,
+    pub node_blocks: u64,
+    pub fill_histogram: [u64; FILL_BUCKETS],
+    pub value_sizes: [u64; VALUE_SIZE_LIMITS.len() + 1],
+    pub value_bytes: u64,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    /// Blocks that are neither reachable from the root nor on the free list.
+    pub orphaned_blocks: Vec<u64>,
+}
+
+impl DbStats {
+    fn new() -> DbStats {
+        return DbStats {
+            keys: 0,
+            subtrees: 0,
+            levels_by_depth: BTreeMap::new(),
+            keys_by_depth: BTreeMap::new(),
+            chain_lengths: BTreeMap::new(),
+            node_blocks: 0,
+            fill_histogram: [0; FILL_BUCKETS],
+            value_sizes: [0; VALUE_SIZE_LIMITS.len() + 1],
+            value_bytes: 0,
+            total_blocks: 0,
+            free_blocks: 0,
+            orphaned_blocks: Vec::new(),
+        };
+    }
+
+    /// The deepest level's depth, with the root level at depth 0.
+    pub fn get_max_depth(&self) -> usize {
+        return self.levels_by_depth.keys().next_back().cloned().unwrap_or(0);
+    }
+
+    pub fn get_max_chain_length(&self) -> usize {
+        return self.chain_lengths.keys().next_back().cloned().unwrap_or(0);
+    }
+}
+
+fn get_value_size_bucket(size: usize) -> usize {
+    return VALUE_SIZE_LIMITS.iter().position(|&limit| size < limit).unwrap_or(VALUE_SIZE_LIMITS.len());
+}
+
+fn collect_level(dbfile: &mut Dbfile, first_block: u64, depth: usize, block_size: usize, seen: &mut BTreeSet<u64>, stats: &mut DbStats) {
+    *stats.levels_by_depth.entry(depth).or_insert(0) += 1;
+
+    let mut chain_length = 0;
+    let mut children = Vec::new();
+    let mut next = Some(first_block);
+
+    // A block we've already seen means a corrupt chain, so stop rather than going round forever.
+    while let Some(n) = next {
+        if (!seen.insert(n)) {
+            break;
+        }
+
+        // Corrupt blocks are left for verify to report.
+        let mut block = match dbfile.get_block(n) {
+            Ok(b) => b,
+            Err(_) => break,
+        };
+        next = block.get_right_block();
+        chain_length += 1;
+
+        stats.node_blocks += 1;
+        let used = block.serialize().len();
+        let bucket = (used * FILL_BUCKETS / block_size).min(FILL_BUCKETS - 1);
+        stats.fill_histogram[bucket] += 1;
+
+        for key in block.get_keys() {
+            *stats.keys_by_depth.entry(depth).or_insert(0) += 1;
+
+            if let Some(value) = block.get(&key) {
+                stats.keys += 1;
+                stats.value_bytes += value.len() as u64;
+                stats.value_sizes[get_value_size_bucket(value.len())] += 1;
+            }
+
+            if let Some(child) = block.get_block_ref(&key) {
+                stats.subtrees += 1;
+                children.push(child);
+            }
+        }
+    }
+
+    *stats.chain_lengths.entry(chain_length).or_insert(0) += 1;
+
+    for child in children {
+        if (!seen.contains(&child)) {
+            collect_level(dbfile, child, depth + 1, block_size, seen, stats);
+        }
+    }
+}
+
+/// Walks the whole database, gathering statistics about its keys and blocks.
+pub fn collect(dbfile: &mut Dbfile) -> DbStats {
+    let mut stats = DbStats::new();
+    let block_size = (dbfile.get_block_size() as usize) * 1024;
+    let header = dbfile.get_header_block();
+    stats.total_blocks = header.get_number_of_blocks();
+
+    let mut seen = BTreeSet::new();
+    collect_level(dbfile, 1, 0, block_size, &mut seen, &mut stats);
+
+    let mut next_free = header.get_free_list();
+    while let Some(n) = next_free {
+        if (n > stats.total_blocks || !seen.insert(n)) {
+            break;
+        }
+
+        stats.free_blocks += 1;
+        next_free = match dbfile.get_block(n) {
+            Ok(mut block) => block.get_right_block(),
+            Err(_) => None,
+        };
+    }
+
+    stats.orphaned_blocks = (1..(stats.total_blocks + 1)).filter(|n| !seen.contains(n)).collect();
+    return stats;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+
+    #[test]
+    fn counting_keys_and_levels() {
+        let temp_db = TempDb::new("gringotts-stats-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        dbfile.set_val(&String::from("a"), String::from("1")).ok().unwrap();
+        dbfile.set_val(&String::from("a/b"), String::from("22")).ok().unwrap();
+        dbfile.set_val(&String::from("c/d/e"), "x".repeat(100)).ok().unwrap();
+
+        let stats = collect(&mut dbfile);
+        assert_eq!(stats.keys, 3);
+        assert_eq!(stats.subtrees, 3);
+        assert_eq!(stats.get_max_depth(), 2);
+        assert_eq!(stats.levels_by_depth.get(&1), Some(&2));
+        assert_eq!(stats.keys_by_depth.get(&0), Some(&2));
+        assert_eq!(stats.value_sizes, [2, 0, 1, 0, 0, 0]);
+        assert_eq!(stats.value_bytes, 103);
+        assert_eq!(stats.node_blocks, stats.total_blocks);
+        assert_eq!(stats.fill_histogram[0], stats.node_blocks);
+        assert_eq!(stats.free_blocks, 0);
+        assert!(stats.orphaned_blocks.is_empty());
+    }
+
+    #[test]
+    fn value_size_buckets() {
+        assert_eq!(get_value_size_bucket(0), 0);
+        assert_eq!(get_value_size_bucket(16), 1);
+        assert_eq!(get_value_size_bucket(4095), 4);
+        assert_eq!(get_value_size_bucket(1 << 20), 5);
+    }
+}
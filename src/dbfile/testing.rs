@@ -0,0 +1,48 @@
+use std::env;
+use std::fs;
+use dbfile::*;
+
+/// A database path in the temp directory, for tests.  Whatever is at the path, and anything left
+/// beside it, is removed when this is created and again when it's dropped, so a failed test
+/// doesn't leave files behind for the next run.  Tests run in parallel, so each needs a name of
+/// its own.
+pub struct TempDb {
+    path: String,
+}
+
+impl TempDb {
+    pub fn new(name: &str) -> TempDb {
+        let path = env::temp_dir().join(name);
+        let temp_db = TempDb {
+            path: String::from(path.to_string_lossy()),
+        };
+
+        temp_db.remove_files();
+        return temp_db;
+    }
+
+    pub fn get_path(&self) -> &String {
+        return &self.path;
+    }
+
+    /// Creates an empty database at the path.
+    pub fn create(&self) -> Dbfile {
+        return match Dbfile::create(&self.path) {
+            Ok(dbfile) => dbfile,
+            Err(e) => panic!("Couldn't create {}: {}", self.path, e),
+        };
+    }
+
+    fn remove_files(&self) {
+        let _ = fs::remove_file(&self.path);
+        for sidecar in get_sidecar_paths(&self.path) {
+            let _ = fs::remove_file(&sidecar);
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
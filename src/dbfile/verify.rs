@@ -0,0 +1,175 @@
+use std::collections::BTreeSet;
+
+use dbfile::Dbfile;
+use dbfile::block::*;
+use dbfile::inspect::*;
+
+/// Something wrong with one block.
+pub struct Problem {
+    pub block: u64,
+    pub message: String,
+}
+
+pub struct VerifyReport {
+    /// How many blocks were reached, from the root or the free list.
+    pub blocks_checked: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        return self.problems.is_empty();
+    }
+
+    fn add_problem(&mut self, block: u64, message: String) {
+        self.problems.push(Problem {
+            block: block,
+            message: message,
+        });
+    }
+}
+
+/// Follows a level's sibling chain, checking each block and that the keys run in order from one
+/// block to the next, then checks the levels beneath it.
+fn verify_level(dbfile: &mut Dbfile, first_block: u64, number_of_blocks: u64, seen: &mut BTreeSet<u64>, report: &mut VerifyReport) {
+    let mut children = Vec::new();
+    let mut previous_key: Option<String> = None;
+    let mut next = Some(first_block);
+    let mut from = first_block;
+
+    while let Some(n) = next {
+        if (n < 1 || n > number_of_blocks) {
+            report.add_problem(from, format!("points to block {}, which doesn't exist", n));
+            break;
+        }
+        if (!seen.insert(n)) {
+            report.add_problem(from, format!("points to block {}, which is already in use", n));
+            break;
+        }
+
+        report.blocks_checked += 1;
+        let description = match describe_block(dbfile, n) {
+            Ok(d) => d,
+            Err(e) => {
+                report.add_problem(n, String::from(e.get_message()));
+                break;
+            },
+        };
+
+        if let BlockType::Free = description.block_type {
+            report.add_problem(n, String::from("is in use but marked as free"));
+        }
+
+        match description.entries {
+            Ok(ref entries) => {
+                if let (Some(previous), Some(first)) = (previous_key.as_ref(), entries.first()) {
+                    if (&first.key <= previous) {
+                        report.add_problem(n, format!("starts with {:?}, which isn't after {:?} in the block before it", first.key, previous));
+                    }
+                }
+
+                if let Some(last) = entries.last() {
+                    previous_key = Some(last.key.clone());
+                }
+
+                for entry in entries {
+                    if let Some(child) = entry.block_ref {
+                        children.push((n, child));
+                    }
+                }
+            },
+            Err(ref message) => report.add_problem(n, message.clone()),
+        }
+
+        from = n;
+        next = description.next_block;
+    }
+
+    for (parent, child) in children {
+        if (child < 1 || child > number_of_blocks) {
+            report.add_problem(parent, format!("points to block {}, which doesn't exist", child));
+        }
+        else if (seen.contains(&child)) {
+            report.add_problem(parent, format!("points to block {}, which is already in use", child));
+        }
+        else {
+            verify_level(dbfile, child, number_of_blocks, seen, report);
+        }
+    }
+}
+
+/// Checks that every block can be read, that each level's keys are in order and that every block
+/// is either in use or on the free list, exactly once.
+pub fn verify(dbfile: &mut Dbfile) -> VerifyReport {
+    let mut report = VerifyReport {
+        blocks_checked: 0,
+        problems: Vec::new(),
+    };
+
+    let header = dbfile.get_header_block();
+    let number_of_blocks = header.get_number_of_blocks();
+    let mut seen = BTreeSet::new();
+    verify_level(dbfile, 1, number_of_blocks, &mut seen, &mut report);
+
+    let mut next_free = header.get_free_list();
+    while let Some(n) = next_free {
+        if (n < 1 || n > number_of_blocks || !seen.insert(n)) {
+            report.add_problem(n, String::from("is on the free list but is out of range or already in use"));
+            break;
+        }
+
+        report.blocks_checked += 1;
+        next_free = match describe_block(dbfile, n) {
+            Ok(ref d) => {
+                match d.block_type {
+                    BlockType::Free => {},
+                    _ => report.add_problem(n, String::from("is on the free list but isn't marked as free")),
+                }
+                d.next_block
+            },
+            Err(e) => {
+                report.add_problem(n, String::from(e.get_message()));
+                None
+            },
+        };
+    }
+
+    for n in 1..(number_of_blocks + 1) {
+        if (!seen.contains(&n)) {
+            report.add_problem(n, String::from("is neither in use nor on the free list"));
+        }
+    }
+
+    return report;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+
+    #[test]
+    fn finding_problems() {
+        let temp_db = TempDb::new("gringotts-verify-test.gdb");
+        let mut dbfile = temp_db.create();
+
+        for i in 0..300 {
+            dbfile.set_val(&format!("a/{:04}", i), "x".repeat(20)).ok().unwrap();
+        }
+
+        let report = verify(&mut dbfile);
+        assert!(report.is_ok());
+        assert_eq!(report.blocks_checked, dbfile.get_number_of_blocks());
+
+        // Point the root at a block that doesn't exist, leaving the subtree it pointed to behind.
+        let mut root = dbfile.get_block(1).ok().unwrap();
+        root.set_block_ref(&String::from("a"), 99).ok();
+        dbfile.write_block(&mut root);
+
+        let report = verify(&mut dbfile);
+        assert!(!report.is_ok());
+        assert_eq!(report.problems[0].block, 1);
+        assert_eq!(report.problems[0].message, "points to block 99, which doesn't exist");
+        assert_eq!(report.problems[1].message, "is neither in use nor on the free list");
+    }
+}
@@ -0,0 +1,167 @@
+use std::ops::Deref;
+use std::sync::{Arc, Weak};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// A change to a key's value.  `old` is None if the key had no value before, and `new` is None if
+/// the value was removed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchEvent {
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Receives the changes a watcher was subscribed to.  Dropping it unsubscribes.
+pub struct WatchReceiver {
+    receiver: Receiver<WatchEvent>,
+    _subscribed: Arc<()>,
+}
+
+impl Deref for WatchReceiver {
+    type Target = Receiver<WatchEvent>;
+
+    fn deref(&self) -> &Receiver<WatchEvent> {
+        return &self.receiver;
+    }
+}
+
+struct Watcher {
+    prefix: String,
+    sender: Sender<WatchEvent>,
+    /// Gone once the watcher's receiver has been dropped, which a sender can't tell until it
+    /// sends something.
+    subscribed: Weak<()>,
+}
+
+/// The watchers subscribed to changes made through one `Dbfile`.  Watchers are dropped once their
+/// receiver goes away, so ones that never see a change don't pile up.
+pub struct Watchers {
+    watchers: Vec<Watcher>,
+    held: Option<Vec<WatchEvent>>,
+}
+
+impl Watchers {
+    pub fn new() -> Watchers {
+        return Watchers {
+            watchers: Vec::new(),
+            held: None,
+        };
+    }
+
+    /// Subscribes to changes to every key whose path starts with `prefix`.
+    pub fn add(&mut self, prefix: &str) -> WatchReceiver {
+        self.prune();
+
+        let (sender, receiver) = channel();
+        let subscribed = Arc::new(());
+        self.watchers.push(Watcher {
+            prefix: String::from(prefix),
+            sender: sender,
+            subscribed: Arc::downgrade(&subscribed),
+        });
+
+        return WatchReceiver {
+            receiver: receiver,
+            _subscribed: subscribed,
+        };
+    }
+
+    /// Whether anyone is watching the path, so that callers only look up old values when they'll
+    /// be needed.
+    pub fn is_watched(&mut self, path: &str) -> bool {
+        self.prune();
+        return self.watchers.iter().any(|w| path.starts_with(&w.prefix));
+    }
+
+    /// Forgets the watchers whose receivers have been dropped.
+    fn prune(&mut self) {
+        self.watchers.retain(|w| w.subscribed.strong_count() > 0);
+    }
+
+    pub fn notify(&mut self, path: &str, old: Option<String>, new: Option<String>) {
+        if (old == new) {
+            return;
+        }
+
+        let event = WatchEvent {
+            path: String::from(path),
+            old: old,
+            new: new,
+        };
+
+        if let Some(ref mut held) = self.held {
+            if (self.watchers.iter().any(|w| path.starts_with(&w.prefix))) {
+                held.push(event);
+            }
+            return;
+        }
+
+        self.send(event);
+    }
+
+    fn send(&mut self, event: WatchEvent) {
+        let path = event.path.clone();
+        self.watchers.retain(|w| !path.starts_with(&w.prefix) || w.sender.send(event.clone()).is_ok());
+    }
+
+    /// Holds on to events rather than sending them, until they're released once the changes they
+    /// describe have been committed.
+    pub fn hold(&mut self) {
+        self.held = Some(Vec::new());
+    }
+
+    pub fn release(&mut self) {
+        if let Some(held) = self.held.take() {
+            for event in held {
+                self.send(event);
+            }
+        }
+    }
+
+    /// Drops the held events, for changes that never made it to disk.
+    pub fn discard(&mut self) {
+        self.held = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_and_dropped_receivers() {
+        let mut watchers = Watchers::new();
+        let config = watchers.add("config/service-a/");
+        let everything = watchers.add("");
+        drop(watchers.add("config/"));
+
+        assert!(watchers.is_watched("users/1"));
+        watchers.notify("config/service-a/port", None, Some(String::from("80")));
+        watchers.notify("users/1", Some(String::from("a")), None);
+        watchers.notify("users/2", Some(String::from("b")), Some(String::from("b")));
+
+        let event = config.try_recv().unwrap();
+        assert_eq!(event.path, "config/service-a/port");
+        assert_eq!(event.new, Some(String::from("80")));
+        assert!(config.try_recv().is_err());
+
+        assert_eq!(everything.try_iter().count(), 2);
+        assert_eq!(watchers.watchers.len(), 2);
+    }
+
+    #[test]
+    fn watchers_that_never_see_a_change() {
+        let mut watchers = Watchers::new();
+        for _ in 0..10 {
+            drop(watchers.add("config/"));
+        }
+
+        let kept = watchers.add("users/");
+        assert_eq!(watchers.watchers.len(), 1);
+        assert!(!watchers.is_watched("config/port"));
+
+        drop(kept);
+        assert!(!watchers.is_watched("users/1"));
+        assert!(watchers.watchers.is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::any::Any;
+
 pub struct CorruptDataError {
     message: String
 }
@@ -29,3 +31,45 @@ impl NoRoomError {
         return self.message.clone();
     }
 }
+
+pub struct InvalidInputError {
+    message: String,
+    too_big: bool
+}
+
+impl InvalidInputError {
+    pub fn new(message: &str) -> InvalidInputError {
+        return InvalidInputError {
+            message: String::from(message),
+            too_big: false
+        };
+    }
+
+    /// For a key or value that's fine in itself, but too big to fit in a block.
+    pub fn too_big(message: &str) -> InvalidInputError {
+        return InvalidInputError {
+            message: String::from(message),
+            too_big: true
+        };
+    }
+
+    pub fn get_message(&self) -> String {
+        return self.message.clone();
+    }
+
+    pub fn is_too_big(&self) -> bool {
+        return self.too_big;
+    }
+}
+
+/// Gets the message out of a panic's payload, for reporting a panic from inside the database as
+/// an ordinary error.
+pub fn describe_panic(payload: Box<dyn Any + Send>) -> String {
+    return match payload.downcast_ref::<String>() {
+        Some(s) => s.clone(),
+        None => match payload.downcast_ref::<&str>() {
+            Some(s) => String::from(*s),
+            None => String::from("Unknown error"),
+        },
+    };
+}
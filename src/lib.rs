@@ -3,10 +3,15 @@
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-extern crate abomonation;
+#[macro_use]
+extern crate serde_json;
+extern crate lz4_flex;
+extern crate argon2;
+extern crate chacha20poly1305;
 
 pub mod dbfile;
 pub mod error;
+pub mod net;
 pub mod version;
 
 #[test]
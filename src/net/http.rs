@@ -0,0 +1,690 @@
+use serde_json::{Map, Value};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::panic;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use dbfile::dump::{self, OWN_VALUE_KEY};
+use dbfile::keychain::KeyChain;
+use error::*;
+use net::protocol::MAX_FRAME_SIZE;
+use net::server::*;
+
+// A small HTTP/1.1 server for reading and writing keys with curl or fetch.  URL paths beneath /kv
+// are keychain paths, so /kv/a/b is the key b beneath a, and a %2F within a segment is a slash
+// within a key.
+//
+//   GET /kv/a/b               {"key": "a/b", "value": ...}, with an ETag
+//   PUT /kv/a/b               sets the value to the request body
+//   DELETE /kv/a/b            removes the value
+//   GET /kv/a/?list           the keys directly beneath a, as dbctl ls prints them
+//   GET /kv/a/?recursive      everything beneath a, as a nested dump
+//   GET /kv/a/b?watch         waits for a change to a/b or anything beneath it
+//
+// PUT and DELETE honour If-Match, and PUT honours If-None-Match: *, so that a client can update a
+// value only if nobody has changed it since the client read it.
+
+/// Request lines and headers can be at most this long.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+/// How long a watch waits when the request doesn't say, and the longest it can ask for.
+const DEFAULT_WATCH_SECONDS: u64 = 30;
+const MAX_WATCH_SECONDS: u64 = 300;
+const KV_PREFIX: &'static str = "/kv";
+
+// Error codes, named like those dbctl prints with --format json.
+const NOT_FOUND: &'static str = "not_found";
+const INVALID_INPUT: &'static str = "invalid_input";
+const PRECONDITION_FAILED: &'static str = "precondition_failed";
+const METHOD_NOT_ALLOWED: &'static str = "method_not_allowed";
+const INTERNAL: &'static str = "internal";
+
+pub struct HttpRequest {
+    pub method: String,
+    /// The keys named by the path beneath /kv, or None for any other path.
+    pub keys: Option<Vec<String>>,
+    /// The query's parameters, which may not have values, like `?list`.
+    pub query: Vec<(String, Option<String>)>,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Whether the client wants the connection closed after the response.
+    pub close: bool,
+}
+
+impl HttpRequest {
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.as_str());
+    }
+
+    pub fn has_param(&self, name: &str) -> bool {
+        return self.query.iter().any(|&(ref n, _)| n == name);
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        return self.query.iter().find(|&&(ref n, _)| n == name).and_then(|&(_, ref v)| v.as_ref().map(|v| v.as_str()));
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json(status: u16, body: Value) -> HttpResponse {
+        return HttpResponse {
+            status: status,
+            headers: vec!((String::from("Content-Type"), String::from("application/json"))),
+            body: body.to_string() + "\n",
+        };
+    }
+
+    pub fn error(status: u16, code: &str, message: &str) -> HttpResponse {
+        return HttpResponse::json(status, json!({ "error": { "code": code, "message": message } }));
+    }
+
+    pub fn empty(status: u16) -> HttpResponse {
+        return HttpResponse {
+            status: status,
+            headers: Vec::new(),
+            body: String::new(),
+        };
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((String::from(name), String::from(value)));
+        return self;
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.as_str());
+    }
+
+    fn get_reason(&self) -> &'static str {
+        return match self.status {
+            200 => "OK",
+            201 => "Created",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            412 => "Precondition Failed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            _ => "",
+        };
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W, close: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.get_reason());
+        for &(ref name, ref value) in self.headers.iter() {
+            head += &format!("{}: {}\r\n", name, value);
+        }
+        head += &format!("Content-Length: {}\r\n", self.body.len());
+        if (close) {
+            head += "Connection: close\r\n";
+        }
+        head += "\r\n";
+
+        out.write_all(head.as_bytes())?;
+        out.write_all(self.body.as_bytes())?;
+        return out.flush();
+    }
+}
+
+fn bad_request(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, String::from(message));
+}
+
+/// Reads one line, without its line ending.  Returns None at the end of the input.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    input.by_ref().take(MAX_LINE_LENGTH).read_until(b'\n', &mut line)?;
+
+    if (line.is_empty()) {
+        return Ok(None);
+    }
+    if (!line.ends_with(b"\n")) {
+        return Err(bad_request("A line of the request is too long or unfinished"));
+    }
+
+    let line = String::from_utf8(line).map_err(|_| bad_request("The request's head isn't valid UTF-8"))?;
+    return Ok(Some(String::from(line.trim_end_matches(|c| c == '\r' || c == '\n'))));
+}
+
+/// Decodes %XX escapes, returning None if one is malformed or the result isn't UTF-8.  Plus
+/// signs are left alone, so keys can contain them.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if (bytes[i] == b'%') {
+            let hex = match s.get((i + 1)..(i + 3)) {
+                Some(h) => h,
+                None => return None,
+            };
+            match u8::from_str_radix(hex, 16) {
+                Ok(b) => decoded.push(b),
+                Err(_) => return None,
+            }
+            i += 3;
+        }
+        else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    return String::from_utf8(decoded).ok();
+}
+
+/// Splits a request target into the keys beneath /kv, if it's there, and the query.
+pub fn parse_target(target: &str) -> Option<(Option<Vec<String>>, Vec<(String, Option<String>)>)> {
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[(i + 1)..]),
+        None => (target, ""),
+    };
+
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let name = percent_decode(parts.next().unwrap_or(""))?;
+        let value = match parts.next() {
+            Some(v) => Some(percent_decode(v)?),
+            None => None,
+        };
+        params.push((name, value));
+    }
+
+    let keys = match path {
+        KV_PREFIX => Some(Vec::new()),
+        p if p.starts_with("/kv/") => {
+            let mut keys = Vec::new();
+            for segment in p[KV_PREFIX.len()..].split('/').filter(|s| !s.is_empty()) {
+                keys.push(percent_decode(segment)?);
+            }
+            Some(keys)
+        },
+        _ => None,
+    };
+
+    return Some((keys, params));
+}
+
+/// Reads one request.  Returns None if the client closed the connection between requests.
+pub fn read_request<R: BufRead>(input: &mut R) -> io::Result<Option<HttpRequest>> {
+    let request_line = match read_line(input)? {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+
+    let parts: Vec<&str> = request_line.split(' ').collect();
+    if (parts.len() != 3 || !parts[2].starts_with("HTTP/1.")) {
+        return Err(bad_request("The request line isn't HTTP/1.x"));
+    }
+
+    let (keys, query) = match parse_target(parts[1]) {
+        Some(t) => t,
+        None => return Err(bad_request("The URL has a malformed escape")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = match read_line(input)? {
+            Some(l) => l,
+            None => return Err(bad_request("The request ended part way through its headers")),
+        };
+        if (line.is_empty()) {
+            break;
+        }
+        if (headers.len() == MAX_HEADERS) {
+            return Err(bad_request("The request has too many headers"));
+        }
+
+        match line.find(':') {
+            Some(i) => headers.push((line[..i].trim().to_lowercase(), String::from(line[(i + 1)..].trim()))),
+            None => return Err(bad_request("A header has no colon")),
+        }
+    }
+
+    let mut request = HttpRequest {
+        method: String::from(parts[0]),
+        keys: keys,
+        query: query,
+        headers: headers,
+        body: Vec::new(),
+        close: false,
+    };
+
+    // HTTP/1.1 keeps the connection open unless asked not to, and HTTP/1.0 closes it unless asked
+    // to keep it.
+    let connection = request.get_header("connection").map(|c| c.to_lowercase());
+    request.close = match parts[2] {
+        "HTTP/1.0" => connection != Some(String::from("keep-alive")),
+        _ => connection == Some(String::from("close")),
+    };
+
+    if (request.get_header("transfer-encoding").is_some()) {
+        return Err(bad_request("Chunked requests aren't supported; send a Content-Length"));
+    }
+    let length = match request.get_header("content-length").map(|l| l.parse::<u64>()) {
+        None => 0,
+        Some(Ok(n)) if n <= MAX_FRAME_SIZE as u64 => n,
+        Some(_) => return Err(bad_request("The Content-Length is invalid or too large")),
+    };
+
+    request.body = vec![0; length as usize];
+    input.read_exact(&mut request.body)?;
+    return Ok(Some(request));
+}
+
+/// An ETag for a value: a quoted FNV-1a hash of it, so that it's the same whatever server or
+/// version computes it.
+pub fn get_etag(value: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in value.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    return format!("\"{:016x}\"", hash);
+}
+
+/// Whether an If-Match or If-None-Match header lists the value's ETag.  `*` matches any value,
+/// but not a missing one.
+fn etag_matches(header: &str, value: &Option<String>) -> bool {
+    let value = match *value {
+        Some(ref v) => v,
+        None => return false,
+    };
+
+    let etag = get_etag(value);
+    return header.split(',').map(|t| t.trim()).any(|t| t == "*" || t == etag || t.trim_start_matches("W/") == etag);
+}
+
+fn has_preconditions(request: &HttpRequest) -> bool {
+    return request.get_header("if-match").is_some() || request.get_header("if-none-match").is_some();
+}
+
+/// Checks If-Match and If-None-Match against the key's current value.
+fn check_preconditions(request: &HttpRequest, current: &Option<String>) -> Result<(), HttpResponse> {
+    if let Some(header) = request.get_header("if-match") {
+        if (!etag_matches(header, current)) {
+            return Err(HttpResponse::error(412, PRECONDITION_FAILED, "The value has changed"));
+        }
+    }
+    if let Some(header) = request.get_header("if-none-match") {
+        if (etag_matches(header, current)) {
+            return Err(HttpResponse::error(412, PRECONDITION_FAILED, "The value already exists"));
+        }
+    }
+
+    return Ok(());
+}
+
+/// A value too big to fit in a block is refused with 413.  Anything else that stops a write is
+/// the server's problem rather than the client's.
+fn write_error(e: InvalidInputError) -> HttpResponse {
+    if (e.is_too_big()) {
+        return HttpResponse::error(413, INVALID_INPUT, &e.get_message());
+    }
+
+    return HttpResponse::error(500, INTERNAL, &e.get_message());
+}
+
+fn value_response(status: u16, key: &str, value: String) -> HttpResponse {
+    let etag = get_etag(&value);
+    return HttpResponse::json(status, json!({ "key": key, "value": value })).with_header("ETag", &etag);
+}
+
+/// Answers one request.
+pub fn handle(dbfile: &SharedDbfile, request: &HttpRequest) -> HttpResponse {
+    let keys = match request.keys {
+        Some(ref k) => k.clone(),
+        None => return HttpResponse::error(404, NOT_FOUND, "Keys are beneath /kv/"),
+    };
+    let key = KeyChain::join(&keys);
+
+    if (request.method == "GET" && request.has_param("list")) {
+        return match lock_dbfile(dbfile).list_level(&keys) {
+            Some(listing) => {
+                let entries: Vec<_> = listing.into_iter()
+                    .map(|(key, value, has_subtree)| json!({ "key": key, "value": value, "has_subtree": has_subtree }))
+                    .collect();
+                HttpResponse::json(200, json!({ "path": key, "keys": entries }))
+            },
+            None => HttpResponse::error(404, NOT_FOUND, &format!("Nothing beneath /{}", key)),
+        };
+    }
+
+    if (request.method == "GET" && request.has_param("recursive")) {
+        let mut dbfile = lock_dbfile(dbfile);
+        let mut tree = match dump::to_nested(&mut dbfile, &keys) {
+            Some(t) => t,
+            None => return HttpResponse::error(404, NOT_FOUND, &format!("Nothing beneath /{}", key)),
+        };
+
+        // As in a dump, a key's own value sits beside its subtree.
+        if let Some(value) = dbfile.get_val(&key).filter(|_| !keys.is_empty()) {
+            let mut with_value = Map::new();
+            with_value.insert(String::from(OWN_VALUE_KEY), Value::String(value));
+            with_value.extend(tree);
+            tree = with_value;
+        }
+
+        return HttpResponse::json(200, json!({ "path": key, "tree": tree }));
+    }
+
+    if (keys.is_empty()) {
+        return HttpResponse::error(400, INVALID_INPUT, "A key is needed, or ?list or ?recursive");
+    }
+
+    return match request.method.as_ref() {
+        "GET" if request.has_param("watch") => watch(dbfile, request, &key),
+        "GET" => {
+            let value = match lock_dbfile(dbfile).get_val(&key) {
+                Some(v) => v,
+                None => return HttpResponse::error(404, NOT_FOUND, &format!("{} has no value", key)),
+            };
+
+            match request.get_header("if-none-match") {
+                Some(header) if etag_matches(header, &Some(value.clone())) => {
+                    HttpResponse::empty(304).with_header("ETag", &get_etag(&value))
+                },
+                _ => value_response(200, &key, value),
+            }
+        },
+        "PUT" => {
+            let value = match String::from_utf8(request.body.clone()) {
+                Ok(v) => v,
+                Err(_) => return HttpResponse::error(400, INVALID_INPUT, "Values must be UTF-8"),
+            };
+
+            let mut dbfile = lock_dbfile(dbfile);
+            if (!has_preconditions(request)) {
+                return match dbfile.swap_val(&key, value.clone()) {
+                    Ok(old) => value_response(if (old.is_none()) { 201 } else { 200 }, &key, value),
+                    Err(e) => write_error(e),
+                };
+            }
+
+            // The preconditions are checked against the value read here, and the write only
+            // happens if it's still the same, so that not even another process writing to the
+            // file can change it in between.
+            let current = dbfile.get_val(&key);
+            if let Err(response) = check_preconditions(request, &current) {
+                return response;
+            }
+
+            let written = match current {
+                Some(ref c) => dbfile.compare_and_swap(&key, Some(c), value.clone()),
+                None => dbfile.set_if_absent(&key, value.clone()),
+            };
+            match written {
+                Ok(Ok(_)) => value_response(if (current.is_none()) { 201 } else { 200 }, &key, value),
+                Ok(Err(_)) => HttpResponse::error(412, PRECONDITION_FAILED, "The value has changed"),
+                Err(e) => write_error(e),
+            }
+        },
+        "DELETE" => {
+            let mut dbfile = lock_dbfile(dbfile);
+            if (!has_preconditions(request)) {
+                return match dbfile.delete_val(&key) {
+                    Some(old) => HttpResponse::json(200, json!({ "key": key, "value": old })),
+                    None => HttpResponse::error(404, NOT_FOUND, &format!("{} has no value", key)),
+                };
+            }
+
+            // As with PUT, the value is only removed if it's still the one that was checked.
+            let current = match dbfile.get_val(&key) {
+                Some(v) => v,
+                None => return HttpResponse::error(404, NOT_FOUND, &format!("{} has no value", key)),
+            };
+            if let Err(response) = check_preconditions(request, &Some(current.clone())) {
+                return response;
+            }
+
+            match dbfile.delete_if_equals(&key, &current) {
+                Ok(_) => HttpResponse::json(200, json!({ "key": key, "value": current })),
+                Err(_) => HttpResponse::error(412, PRECONDITION_FAILED, "The value has changed"),
+            }
+        },
+        _ => HttpResponse::error(405, METHOD_NOT_ALLOWED, &format!("{} isn't supported", request.method)).with_header("Allow", "GET, PUT, DELETE"),
+    };
+}
+
+/// Long-polls for a change to the key or anything beneath it.  If the request's If-None-Match
+/// doesn't match the key's current value, the client has already missed a change, so the value
+/// is returned straight away.  Otherwise the first change is returned as `{"key", "old", "new"}`,
+/// or 304 if nothing changes before the timeout.
+fn watch(dbfile: &SharedDbfile, request: &HttpRequest, key: &String) -> HttpResponse {
+    let seconds = match request.get_param("timeout").map(|t| t.parse::<u64>()) {
+        None => DEFAULT_WATCH_SECONDS,
+        Some(Ok(n)) => n.min(MAX_WATCH_SECONDS),
+        Some(Err(_)) => return HttpResponse::error(400, INVALID_INPUT, "timeout must be a whole number of seconds"),
+    };
+
+    // Subscribe while holding the database, so that no change can slip in after the check.
+    let receiver = {
+        let mut dbfile = lock_dbfile(dbfile);
+        if let Some(header) = request.get_header("if-none-match") {
+            let current = dbfile.get_val(key);
+            if (!etag_matches(header, &current)) {
+                return match current {
+                    Some(value) => value_response(200, key, value),
+                    None => HttpResponse::error(404, NOT_FOUND, &format!("{} has no value", key)),
+                };
+            }
+        }
+
+        dbfile.watch(key)
+    };
+
+    let subtree = format!("{}/", key);
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let event = match receiver.recv_timeout(remaining) {
+            Ok(e) => e,
+            Err(RecvTimeoutError::Timeout) => return HttpResponse::empty(304),
+            Err(RecvTimeoutError::Disconnected) => return HttpResponse::error(500, INTERNAL, "The database stopped sending changes"),
+        };
+
+        // Watching a/b also sees a/bc, which isn't beneath it.
+        if (event.path != *key && !event.path.starts_with(&subtree)) {
+            continue;
+        }
+
+        let response = HttpResponse::json(200, json!({ "key": event.path, "old": event.old, "new": event.new }));
+        return match event.new {
+            Some(ref value) if event.path == *key => response.with_header("ETag", &get_etag(value)),
+            _ => response,
+        };
+    }
+}
+
+/// Answers requests on the connection until the client closes it.  A request that can't be
+/// parsed is answered with 400, and then the connection is closed.
+pub fn serve_connection(stream: TcpStream, dbfile: SharedDbfile) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let request = match read_request(&mut reader) {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                let status = if (err.kind() == io::ErrorKind::InvalidData) { 400 } else { 500 };
+                HttpResponse::error(status, INVALID_INPUT, &err.to_string()).write_to(&mut writer, true)?;
+                return Err(err);
+            },
+        };
+
+        let response = match panic::catch_unwind(panic::AssertUnwindSafe(|| handle(&dbfile, &request))) {
+            Ok(r) => r,
+            Err(payload) => HttpResponse::error(500, INTERNAL, &describe_panic(payload)),
+        };
+
+        response.write_to(&mut writer, request.close)?;
+        if (request.close) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+    use std::thread;
+
+    fn shared_db(name: &str) -> (TempDb, SharedDbfile) {
+        let temp_db = TempDb::new(name);
+        let dbfile = share(temp_db.create());
+        return (temp_db, dbfile);
+    }
+
+    fn request(method: &str, target: &str, headers: &[(&str, &str)], body: &str) -> HttpRequest {
+        let (keys, query) = parse_target(target).unwrap();
+        return HttpRequest {
+            method: String::from(method),
+            keys: keys,
+            query: query,
+            headers: headers.iter().map(|&(n, v)| (String::from(n), String::from(v))).collect(),
+            body: body.as_bytes().to_vec(),
+            close: false,
+        };
+    }
+
+    fn body(response: &HttpResponse) -> Value {
+        return ::serde_json::from_str(&response.body).unwrap();
+    }
+
+    #[test]
+    fn parsing_targets() {
+        let (keys, query) = parse_target("/kv/a/b%2Fc/?list&timeout=5").unwrap();
+        assert_eq!(keys, Some(vec!(String::from("a"), String::from("b/c"))));
+        assert_eq!(query, vec!((String::from("list"), None), (String::from("timeout"), Some(String::from("5")))));
+
+        assert_eq!(parse_target("/kv").unwrap().0, Some(Vec::new()));
+        assert_eq!(parse_target("/kvx").unwrap().0, None);
+        assert!(parse_target("/kv/%zz").is_none());
+        assert_eq!(percent_decode("caf%C3%A9+1"), Some(String::from("café+1")));
+    }
+
+    #[test]
+    fn reading_requests() {
+        let mut input: &[u8] = b"PUT /kv/a HTTP/1.1\r\nContent-Length: 3\r\nIf-Match: *\r\n\r\nabcGET /kv/a HTTP/1.0\r\n\r\n";
+
+        let first = read_request(&mut input).unwrap().unwrap();
+        assert_eq!(first.method, "PUT");
+        assert_eq!(first.get_header("if-match"), Some("*"));
+        assert_eq!(first.body, b"abc".to_vec());
+        assert!(!first.close);
+
+        let second = read_request(&mut input).unwrap().unwrap();
+        assert_eq!(second.keys, Some(vec!(String::from("a"))));
+        assert!(second.close);
+        assert!(read_request(&mut input).unwrap().is_none());
+
+        let mut bad: &[u8] = b"GET /kv/a\r\n\r\n";
+        assert!(read_request(&mut bad).is_err());
+    }
+
+    #[test]
+    fn reading_and_writing_keys() {
+        let (_temp_db, dbfile) = shared_db("gringotts-http-keys-test.gdb");
+
+        let created = handle(&dbfile, &request("PUT", "/kv/a/b", &[], "1"));
+        assert_eq!(created.status, 201);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a/c", &[], "2")).status, 201);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a", &[], "top")).status, 201);
+
+        let read = handle(&dbfile, &request("GET", "/kv/a/b", &[], ""));
+        assert_eq!(read.status, 200);
+        assert_eq!(body(&read), json!({ "key": "a/b", "value": "1" }));
+        assert_eq!(read.get_header("ETag"), created.get_header("ETag"));
+        assert_eq!(handle(&dbfile, &request("GET", "/kv/a/x", &[], "")).status, 404);
+
+        let listing = handle(&dbfile, &request("GET", "/kv/a/?list", &[], ""));
+        assert_eq!(body(&listing)["keys"][1], json!({ "key": "c", "value": "2", "has_subtree": false }));
+
+        let tree = handle(&dbfile, &request("GET", "/kv/a?recursive", &[], ""));
+        assert_eq!(body(&tree), json!({ "path": "a", "tree": { "/": "top", "b": "1", "c": "2" } }));
+
+        let deleted = handle(&dbfile, &request("DELETE", "/kv/a/c", &[], ""));
+        assert_eq!(body(&deleted), json!({ "key": "a/c", "value": "2" }));
+        assert_eq!(handle(&dbfile, &request("DELETE", "/kv/a/c", &[], "")).status, 404);
+        assert_eq!(handle(&dbfile, &request("POST", "/kv/a", &[], "")).status, 405);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/big", &[], &"x".repeat(100000))).status, 413);
+        assert_eq!(handle(&dbfile, &request("GET", "/other", &[], "")).status, 404);
+    }
+
+    #[test]
+    fn optimistic_concurrency() {
+        let (_temp_db, dbfile) = shared_db("gringotts-http-etags-test.gdb");
+
+        let etag = get_etag("1");
+        let only_new = [("if-none-match", "*")];
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a", &only_new, "1")).status, 201);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a", &only_new, "1")).status, 412);
+
+        assert_eq!(handle(&dbfile, &request("GET", "/kv/a", &[("if-none-match", &etag)], "")).status, 304);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a", &[("if-match", &etag)], "2")).status, 200);
+        assert_eq!(handle(&dbfile, &request("PUT", "/kv/a", &[("if-match", &etag)], "3")).status, 412);
+        assert_eq!(handle(&dbfile, &request("DELETE", "/kv/a", &[("if-match", &etag)], "")).status, 412);
+        assert_eq!(handle(&dbfile, &request("DELETE", "/kv/a", &[("if-match", &get_etag("2"))], "")).status, 200);
+    }
+
+    #[test]
+    fn watching_for_changes() {
+        let (_temp_db, dbfile) = shared_db("gringotts-http-watch-test.gdb");
+        handle(&dbfile, &request("PUT", "/kv/a", &[], "1"));
+
+        // The client's copy is out of date, so there's no need to wait.
+        let stale = handle(&dbfile, &request("GET", "/kv/a?watch", &[("if-none-match", "\"old\"")], ""));
+        assert_eq!(body(&stale), json!({ "key": "a", "value": "1" }));
+
+        assert_eq!(handle(&dbfile, &request("GET", "/kv/a?watch&timeout=0", &[], "")).status, 304);
+
+        let watcher = dbfile.clone();
+        let waiting = thread::spawn(move || handle(&watcher, &request("GET", "/kv/a?watch&timeout=10", &[], "")));
+        // Keep writing until the watcher has subscribed and seen a change.  Changes to keys beside
+        // a aren't seen, even when a is a prefix of their names.
+        let mut i = 0;
+        while !waiting.is_finished() {
+            handle(&dbfile, &request("PUT", "/kv/ab", &[], "x"));
+            handle(&dbfile, &request("PUT", "/kv/a/b", &[], &i.to_string()));
+            thread::sleep(Duration::from_millis(10));
+            i += 1;
+        }
+
+        let change = waiting.join().unwrap();
+        assert_eq!(change.status, 200);
+        assert_eq!(body(&change)["key"], json!("a/b"));
+    }
+
+    #[test]
+    fn serving_http_clients() {
+        let (_temp_db, dbfile) = shared_db("gringotts-http-server-test.gdb");
+        let server = Server::bind_shared("127.0.0.1:0", dbfile, Protocol::Http).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"PUT /kv/a HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET /kv/a HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut responses = String::new();
+        stream.read_to_string(&mut responses).unwrap();
+
+        assert!(responses.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(responses.contains("HTTP/1.1 200 OK\r\n"));
+        assert!(responses.ends_with("Connection: close\r\n\r\n{\"key\":\"a\",\"value\":\"hi\"}\n"));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod http;
+pub mod protocol;
+pub mod resp;
+pub mod server;
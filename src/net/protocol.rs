@@ -0,0 +1,298 @@
+use std::io;
+use std::io::prelude::*;
+
+// The wire protocol.  Every message is a frame: a u32 length, then that many bytes of payload.
+// A payload starts with an opcode, followed by its fields.  Integers are little endian, like
+// those in the database file, and strings are a u32 length followed by UTF-8 bytes.
+
+/// Frames longer than this are refused, so that a bad length can't make either side try to
+/// allocate without limit.
+pub const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
+
+// Request opcodes.
+const GET: u8 = 1;
+const SET: u8 = 2;
+const DELETE: u8 = 3;
+const SCAN: u8 = 4;
+const BEGIN: u8 = 5;
+const COMMIT: u8 = 6;
+const ROLLBACK: u8 = 7;
+
+// Response opcodes.
+const OK: u8 = 128;
+const VALUE: u8 = 129;
+const ENTRIES: u8 = 130;
+const ERROR: u8 = 255;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Get(String),
+    Set(String, String),
+    Delete(String),
+    /// Every key beneath a prefix; an empty prefix is the whole database.
+    Scan(String),
+    /// Holds on to the connection's sets and deletes until they're committed.
+    Begin,
+    Commit,
+    Rollback,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    Ok,
+    Value(Option<String>),
+    Entries(Vec<(String, String)>),
+    Error(String),
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+fn put_u32(bytes: &mut Vec<u8>, n: u32) {
+    bytes.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_string(bytes: &mut Vec<u8>, s: &str) {
+    put_u32(bytes, s.len() as u32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Reads the fields of a payload in order.
+struct PayloadReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn new(bytes: &'a [u8]) -> PayloadReader<'a> {
+        return PayloadReader {
+            bytes: bytes,
+            position: 0,
+        };
+    }
+
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if (self.bytes.len() - self.position < length) {
+            return Err(invalid_data("The message ended part way through a field"));
+        }
+
+        let taken = &self.bytes[self.position..(self.position + length)];
+        self.position += length;
+        return Ok(taken);
+    }
+
+    fn get_u8(&mut self) -> io::Result<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn get_u32(&mut self) -> io::Result<u32> {
+        let mut buffer = [0; 4];
+        buffer.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_le_bytes(buffer));
+    }
+
+    fn get_string(&mut self) -> io::Result<String> {
+        let length = self.get_u32()? as usize;
+        return String::from_utf8(self.take(length)?.to_vec()).map_err(|_| invalid_data("A string isn't valid UTF-8"));
+    }
+
+    fn finish<T>(&self, message: T) -> io::Result<T> {
+        if (self.position != self.bytes.len()) {
+            return Err(invalid_data("The message has bytes left over"));
+        }
+
+        return Ok(message);
+    }
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match *self {
+            Request::Get(ref key) => {
+                bytes.push(GET);
+                put_string(&mut bytes, key);
+            },
+            Request::Set(ref key, ref value) => {
+                bytes.push(SET);
+                put_string(&mut bytes, key);
+                put_string(&mut bytes, value);
+            },
+            Request::Delete(ref key) => {
+                bytes.push(DELETE);
+                put_string(&mut bytes, key);
+            },
+            Request::Scan(ref prefix) => {
+                bytes.push(SCAN);
+                put_string(&mut bytes, prefix);
+            },
+            Request::Begin => bytes.push(BEGIN),
+            Request::Commit => bytes.push(COMMIT),
+            Request::Rollback => bytes.push(ROLLBACK),
+        }
+
+        return bytes;
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Request> {
+        let mut reader = PayloadReader::new(bytes);
+
+        let request = match reader.get_u8()? {
+            GET => Request::Get(reader.get_string()?),
+            SET => Request::Set(reader.get_string()?, reader.get_string()?),
+            DELETE => Request::Delete(reader.get_string()?),
+            SCAN => Request::Scan(reader.get_string()?),
+            BEGIN => Request::Begin,
+            COMMIT => Request::Commit,
+            ROLLBACK => Request::Rollback,
+            other => return Err(invalid_data(&format!("{} is not a request opcode", other))),
+        };
+
+        return reader.finish(request);
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match *self {
+            Response::Ok => bytes.push(OK),
+            Response::Value(ref value) => {
+                bytes.push(VALUE);
+                match *value {
+                    Some(ref v) => {
+                        bytes.push(1);
+                        put_string(&mut bytes, v);
+                    },
+                    None => bytes.push(0),
+                }
+            },
+            Response::Entries(ref entries) => {
+                bytes.push(ENTRIES);
+                put_u32(&mut bytes, entries.len() as u32);
+                for &(ref key, ref value) in entries {
+                    put_string(&mut bytes, key);
+                    put_string(&mut bytes, value);
+                }
+            },
+            Response::Error(ref message) => {
+                bytes.push(ERROR);
+                put_string(&mut bytes, message);
+            },
+        }
+
+        return bytes;
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Response> {
+        let mut reader = PayloadReader::new(bytes);
+
+        let response = match reader.get_u8()? {
+            OK => Response::Ok,
+            VALUE => match reader.get_u8()? {
+                0 => Response::Value(None),
+                1 => Response::Value(Some(reader.get_string()?)),
+                _ => return Err(invalid_data("A value is neither present nor absent")),
+            },
+            ENTRIES => {
+                let count = reader.get_u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push((reader.get_string()?, reader.get_string()?));
+                }
+                Response::Entries(entries)
+            },
+            ERROR => Response::Error(reader.get_string()?),
+            other => return Err(invalid_data(&format!("{} is not a response opcode", other))),
+        };
+
+        return reader.finish(response);
+    }
+}
+
+/// Reads one frame's payload.  Returns None if the other side closed the connection between
+/// frames.
+pub fn read_frame<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match input.read_exact(&mut length) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let length = u32::from_le_bytes(length);
+    if (length > MAX_FRAME_SIZE) {
+        return Err(invalid_data(&format!("A {} byte message is larger than the limit of {}", length, MAX_FRAME_SIZE)));
+    }
+
+    let mut payload = vec![0; length as usize];
+    input.read_exact(&mut payload)?;
+    return Ok(Some(payload));
+}
+
+pub fn write_frame<W: Write>(output: &mut W, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    put_u32(&mut frame, payload.len() as u32);
+    frame.extend_from_slice(payload);
+
+    output.write_all(&frame)?;
+    return output.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let requests = vec!(
+            Request::Get(String::from("a/b")),
+            Request::Set(String::from("a/b"), String::from("é")),
+            Request::Delete(String::from("")),
+            Request::Scan(String::from("a")),
+            Request::Begin,
+            Request::Commit,
+            Request::Rollback,
+        );
+        for request in requests {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+
+        let responses = vec!(
+            Response::Ok,
+            Response::Value(None),
+            Response::Value(Some(String::from("1"))),
+            Response::Entries(vec!((String::from("a"), String::from("1")), (String::from("b"), String::from("")))),
+            Response::Error(String::from("No transaction is under way")),
+        );
+        for response in responses {
+            assert_eq!(Response::decode(&response.encode()).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn refusing_bad_messages() {
+        assert!(Request::decode(&[]).is_err());
+        assert!(Request::decode(&[42]).is_err());
+        assert!(Request::decode(&[GET, 5, 0, 0, 0, b'a']).is_err());
+        assert!(Request::decode(&[BEGIN, 0]).is_err());
+
+        let mut oversized: &[u8] = &[255, 255, 255, 255];
+        assert!(read_frame(&mut oversized).is_err());
+    }
+
+    #[test]
+    fn framing() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"first").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+
+        let mut input: &[u8] = &stream;
+        assert_eq!(read_frame(&mut input).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_frame(&mut input).unwrap(), Some(Vec::new()));
+        assert_eq!(read_frame(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,790 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::panic;
+use std::time::Duration;
+
+use dbfile::Dbfile;
+use error::*;
+use net::protocol::MAX_FRAME_SIZE;
+use net::server::*;
+
+// A front-end that speaks the Redis protocol, RESP2 by default and RESP3 after `HELLO 3`, so that
+// redis-cli and Redis client libraries can use a database.  Redis keys are keychain paths, so
+// `SET a/b 1` sets the key b beneath a, and `KEYS a/*` lists everything beneath a.  SCAN cursors
+// are the last key returned, after a slash, rather than a number.
+
+/// Inline commands, typed without RESP framing as with telnet, can be at most this long.
+const MAX_INLINE_LENGTH: u64 = 64 * 1024;
+/// A command can have at most this many arguments.
+const MAX_ARGUMENTS: i64 = 1024 * 1024;
+/// How many keys SCAN looks at when COUNT isn't given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// The commands that use the database, and so can be queued between MULTI and EXEC.
+const DATA_COMMANDS: [&'static str; 11] = ["GET", "SET", "DEL", "EXISTS", "INCR", "DECR", "INCRBY", "DECRBY", "EXPIRE", "KEYS", "SCAN"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    /// The message starts with an error code, such as ERR.
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Null,
+    Array(Vec<Reply>),
+    /// A map in RESP3, and a flat array of keys and values in RESP2.
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    pub fn encode(&self, version: u8, out: &mut Vec<u8>) {
+        match *self {
+            Reply::Simple(ref s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            Reply::Error(ref s) => out.extend_from_slice(format!("-{}\r\n", s).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(ref s) => {
+                out.extend_from_slice(format!("${}\r\n", s.len()).as_bytes());
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            },
+            Reply::Null => match version {
+                3 => out.extend_from_slice(b"_\r\n"),
+                _ => out.extend_from_slice(b"$-1\r\n"),
+            },
+            Reply::Array(ref replies) => {
+                out.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.encode(version, out);
+                }
+            },
+            Reply::Map(ref pairs) => {
+                match version {
+                    3 => out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes()),
+                    _ => out.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes()),
+                }
+                for &(ref key, ref value) in pairs {
+                    key.encode(version, out);
+                    value.encode(version, out);
+                }
+            },
+        }
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {}", message));
+}
+
+/// Reads one line, without its line ending.  Returns None at the end of the input.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    input.by_ref().take(MAX_INLINE_LENGTH).read_until(b'\n', &mut line)?;
+
+    if (line.is_empty()) {
+        return Ok(None);
+    }
+    if (!line.ends_with(b"\n")) {
+        return Err(protocol_error("the line is too long or unfinished"));
+    }
+
+    line.pop();
+    if (line.ends_with(b"\r")) {
+        line.pop();
+    }
+    return Ok(Some(line));
+}
+
+fn parse_length(line: &[u8]) -> io::Result<i64> {
+    return String::from_utf8_lossy(line).parse::<i64>().map_err(|_| protocol_error("invalid length"));
+}
+
+/// Reads one command: an array of bulk strings, or an inline command split on whitespace.
+/// Returns None at the end of the input, and an empty command for a blank line.
+pub fn read_command<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let line = match read_line(input)? {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+
+    if (!line.starts_with(b"*")) {
+        let words = line.split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_vec())
+            .collect();
+        return Ok(Some(words));
+    }
+
+    let count = parse_length(&line[1..])?;
+    if (count > MAX_ARGUMENTS) {
+        return Err(protocol_error("too many arguments"));
+    }
+
+    let mut arguments = Vec::new();
+    for _ in 0..count {
+        let header = match read_line(input)? {
+            Some(ref h) if h.starts_with(b"$") => h.clone(),
+            _ => return Err(protocol_error("expected a bulk string")),
+        };
+
+        let length = parse_length(&header[1..])?;
+        if (length < 0 || length > MAX_FRAME_SIZE as i64) {
+            return Err(protocol_error("invalid bulk length"));
+        }
+
+        let mut argument = vec![0; length as usize + 2];
+        input.read_exact(&mut argument)?;
+        if (!argument.ends_with(b"\r\n")) {
+            return Err(protocol_error("a bulk string isn't followed by CRLF"));
+        }
+
+        argument.truncate(length as usize);
+        arguments.push(argument);
+    }
+
+    return Ok(Some(arguments));
+}
+
+/// Matches Redis glob patterns: `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // When what follows a `*` stops matching, the `*` takes one more character and matching
+    // starts again just after it.  Only the last `*` ever needs to, since anything an earlier
+    // one could take, the last one can take instead, so no pattern takes more than
+    // pattern length times text length steps.
+    let mut p = 0;
+    let mut t = 0;
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if (p < pattern.len() && pattern[p] == '*') {
+            p += 1;
+            last_star = Some((p, t));
+            continue;
+        }
+
+        if let Some(length) = match_one(&pattern[p..], text[t]) {
+            p += length;
+            t += 1;
+            continue;
+        }
+
+        match last_star {
+            Some((after_star, taken)) => {
+                p = after_star;
+                t = taken + 1;
+                last_star = Some((after_star, t));
+            },
+            None => return false,
+        }
+    }
+
+    // Once the text runs out, only `*`s are left to match nothing.
+    return pattern[p..].iter().all(|&c| c == '*');
+}
+
+/// Matches a character against the first element of the pattern, returning how long that
+/// element is if it matched.
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    if (pattern.is_empty()) {
+        return None;
+    }
+
+    match pattern[0] {
+        '?' => return Some(1),
+        '[' => {
+            if let Some((matched, length)) = match_class(pattern, c) {
+                return if (matched) { Some(length) } else { None };
+            }
+        },
+        '\\' if pattern.len() > 1 => {
+            return if (pattern[1] == c) { Some(2) } else { None };
+        },
+        _ => {},
+    }
+
+    // Anything else, including a [ that's never closed, matches itself.
+    return if (pattern[0] == c) { Some(1) } else { None };
+}
+
+/// Matches a character against the class at the start of the pattern.  Returns whether it
+/// matched and how long the class is, or None if the class is never closed.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let negate = pattern.get(1) == Some(&'^');
+    let mut i = if (negate) { 2 } else { 1 };
+    let mut matched = false;
+
+    while i < pattern.len() && pattern[i] != ']' {
+        if (pattern[i] == '\\' && i + 1 < pattern.len()) {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        }
+        else if (i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']') {
+            matched |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        }
+        else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    if (i >= pattern.len()) {
+        return None;
+    }
+
+    return Some((matched != negate, i + 1));
+}
+
+fn wrong_arity(name: &str) -> Reply {
+    return Reply::Error(format!("ERR wrong number of arguments for '{}' command", name.to_lowercase()));
+}
+
+fn syntax_error() -> Reply {
+    return Reply::Error(String::from("ERR syntax error"));
+}
+
+fn out_of_range() -> Reply {
+    return Reply::Error(String::from("ERR value is not an integer or out of range"));
+}
+
+fn parse_integer(s: &str) -> Result<i64, Reply> {
+    return s.parse::<i64>().map_err(|_| out_of_range());
+}
+
+fn to_bulk_array(keys: Vec<String>) -> Reply {
+    return Reply::Array(keys.into_iter().map(Reply::Bulk).collect());
+}
+
+/// Runs a command that uses the database.
+fn execute(dbfile: &mut Dbfile, args: &[String]) -> Reply {
+    let name = args[0].to_uppercase();
+
+    let result = match (name.as_ref(), args.len()) {
+        ("GET", 2) => Ok(dbfile.get_val(&args[1]).map_or(Reply::Null, Reply::Bulk)),
+        ("SET", n) if n >= 3 => set(dbfile, args),
+        ("DEL", n) if n >= 2 => {
+            let removed = args[1..].iter().filter(|key| dbfile.delete_val(key).is_some()).count();
+            Ok(Reply::Integer(removed as i64))
+        },
+        ("EXISTS", n) if n >= 2 => {
+            let found = args[1..].iter().filter(|key| dbfile.get_val(key).is_some()).count();
+            Ok(Reply::Integer(found as i64))
+        },
+        ("INCR", 2) => increment(dbfile, &args[1], 1),
+        ("DECR", 2) => increment(dbfile, &args[1], -1),
+        ("INCRBY", 3) => parse_integer(&args[2]).and_then(|delta| increment(dbfile, &args[1], delta)),
+        ("DECRBY", 3) => parse_integer(&args[2])
+            .and_then(|delta| delta.checked_neg().ok_or_else(out_of_range))
+            .and_then(|delta| increment(dbfile, &args[1], delta)),
+        ("EXPIRE", 3) => expire(dbfile, &args[1], &args[2]),
+        ("KEYS", 2) => {
+            // Only the keys beneath the part of the pattern without wildcards can match.
+            let keys = dbfile.scan(&get_literal_parent(&args[1])).into_iter()
+                .map(|(key, _)| key)
+                .filter(|key| glob_match(&args[1], key))
+                .collect();
+            Ok(to_bulk_array(keys))
+        },
+        ("SCAN", n) if n >= 2 => scan(dbfile, args),
+        _ => Err(wrong_arity(&name)),
+    };
+
+    return match result {
+        Ok(reply) => reply,
+        Err(error) => error,
+    };
+}
+
+/// SET key value [EX seconds | PX milliseconds] [NX | XX]
+fn set(dbfile: &mut Dbfile, args: &[String]) -> Result<Reply, Reply> {
+    let mut ttl = None;
+    let mut only_if_absent = false;
+    let mut only_if_present = false;
+
+    let mut i = 3;
+    while i < args.len() {
+        match (args[i].to_uppercase().as_ref(), args.get(i + 1)) {
+            ("EX", Some(n)) | ("PX", Some(n)) => {
+                let n = parse_integer(n)?;
+                if (n <= 0) {
+                    return Err(Reply::Error(String::from("ERR invalid expire time in 'set' command")));
+                }
+
+                ttl = match args[i].to_uppercase().as_ref() {
+                    "EX" => Some(Duration::from_secs(n as u64)),
+                    _ => Some(Duration::from_millis(n as u64)),
+                };
+                i += 2;
+            },
+            ("NX", _) => {
+                only_if_absent = true;
+                i += 1;
+            },
+            ("XX", _) => {
+                only_if_present = true;
+                i += 1;
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    if (only_if_absent && only_if_present) {
+        return Err(syntax_error());
+    }
+
+    // The conditions are checked by compare-and-swap, so that they still hold when another
+    // process is writing to the file too.
+    let written = if (only_if_absent) {
+        swap_if(dbfile, &args[1], None, &args[2], ttl)
+    }
+    else if (only_if_present) {
+        // Whatever the value is, it's replaced, so if it changes first, try again with the new one.
+        let mut current = dbfile.get_val(&args[1]);
+        loop {
+            let expected = match current {
+                Some(ref value) => value.clone(),
+                None => break Ok(Err(None)),
+            };
+
+            match swap_if(dbfile, &args[1], Some(&expected), &args[2], ttl) {
+                Ok(Err(value)) => current = value,
+                written => break written,
+            }
+        }
+    }
+    else {
+        let written = match ttl {
+            Some(duration) => dbfile.set_with_ttl(&args[1], args[2].clone(), duration),
+            None => dbfile.set_val(&args[1], args[2].clone()),
+        };
+        written.map(|_| Ok(()))
+    };
+
+    return match written {
+        Ok(Ok(_)) => Ok(Reply::Simple(String::from("OK"))),
+        Ok(Err(_)) => Ok(Reply::Null),
+        Err(e) => Err(Reply::Error(format!("ERR {}", e.get_message()))),
+    };
+}
+
+/// Sets the key to `value` if its current value is `expected`, giving it the TTL if there is one.
+fn swap_if(dbfile: &mut Dbfile, key: &String, expected: Option<&str>, value: &String, ttl: Option<Duration>) -> Result<Result<(), Option<String>>, InvalidInputError> {
+    return match ttl {
+        Some(duration) => dbfile.compare_and_swap_with_ttl(key, expected, value.clone(), duration),
+        None => dbfile.compare_and_swap(key, expected, value.clone()),
+    };
+}
+
+fn increment(dbfile: &mut Dbfile, key: &String, delta: i64) -> Result<Reply, Reply> {
+    return dbfile.increment(key, delta)
+        .map(Reply::Integer)
+        .map_err(|_| out_of_range());
+}
+
+/// Gives the key's value a TTL, leaving the value as it is.  A TTL that isn't positive removes it
+/// now.
+fn expire(dbfile: &mut Dbfile, key: &String, seconds: &str) -> Result<Reply, Reply> {
+    let seconds = parse_integer(seconds)?;
+
+    let expired = match seconds {
+        n if n <= 0 => Ok(dbfile.delete_val(key).is_some()),
+        n => dbfile.set_ttl(key, Duration::from_secs(n as u64)),
+    };
+
+    return match expired {
+        Ok(found) => Ok(Reply::Integer(found as i64)),
+        Err(e) => Err(Reply::Error(format!("ERR {}", e.get_message()))),
+    };
+}
+
+/// The path of the deepest key that everything matching the pattern is beneath, which is empty
+/// if the pattern starts with a wildcard.
+fn get_literal_parent(pattern: &str) -> String {
+    let literal: String = pattern.chars().take_while(|c| !['*', '?', '[', '\\'].contains(c)).collect();
+    return match literal.rfind('/') {
+        Some(i) => String::from(&literal[..i]),
+        None => String::new(),
+    };
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count].  Cursors other than 0 are the last key returned
+/// after a slash, and the scan carries on from just after it, so keys added or removed during a
+/// scan don't shift it.
+fn scan(dbfile: &mut Dbfile, args: &[String]) -> Result<Reply, Reply> {
+    let cursor = match args[1].as_ref() {
+        "0" => None,
+        c if c.starts_with('/') => Some(String::from(&c[1..])),
+        _ => return Err(Reply::Error(String::from("ERR invalid cursor"))),
+    };
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+
+    let mut i = 2;
+    while i < args.len() {
+        match (args[i].to_uppercase().as_ref(), args.get(i + 1)) {
+            ("MATCH", Some(p)) => pattern = Some(p.clone()),
+            ("COUNT", Some(n)) => {
+                count = match parse_integer(n)? {
+                    n if n >= 1 => n as usize,
+                    _ => return Err(syntax_error()),
+                };
+            },
+            _ => return Err(syntax_error()),
+        }
+        i += 2;
+    }
+
+    let keys = dbfile.scan_keys(cursor.as_ref(), count);
+    let next = match keys.last() {
+        Some(key) if keys.len() == count => format!("/{}", key),
+        _ => String::from("0"),
+    };
+
+    let found = keys.into_iter()
+        .filter(|key| pattern.as_ref().map_or(true, |p| glob_match(p, key)))
+        .collect();
+
+    return Ok(Reply::Array(vec!(Reply::Bulk(next), to_bulk_array(found))));
+}
+
+/// One client's connection: the protocol version it asked for, and the commands it has queued
+/// since MULTI.
+struct RespSession {
+    dbfile: SharedDbfile,
+    version: u8,
+    queued: Option<Vec<Vec<String>>>,
+    /// Whether a command couldn't be queued, in which case EXEC throws the transaction away.
+    queue_failed: bool,
+}
+
+impl RespSession {
+    fn new(dbfile: SharedDbfile) -> RespSession {
+        return RespSession {
+            dbfile: dbfile,
+            version: 2,
+            queued: None,
+            queue_failed: false,
+        };
+    }
+
+    /// Runs one command, returning its reply and whether to close the connection afterwards.
+    fn handle(&mut self, args: Vec<Vec<u8>>) -> (Reply, bool) {
+        let args: Vec<String> = match args.into_iter().map(String::from_utf8).collect() {
+            Ok(a) => a,
+            Err(_) => return (Reply::Error(String::from("ERR gringotts only stores UTF-8 strings")), false),
+        };
+
+        let name = args[0].to_uppercase();
+        if (self.queued.is_some() && !["MULTI", "EXEC", "DISCARD", "QUIT"].contains(&name.as_ref())) {
+            if (!DATA_COMMANDS.contains(&name.as_ref())) {
+                self.queue_failed = true;
+                return (Reply::Error(format!("ERR '{}' can't be used in a transaction", args[0])), false);
+            }
+
+            self.queued.as_mut().unwrap().push(args);
+            return (Reply::Simple(String::from("QUEUED")), false);
+        }
+
+        let reply = match (name.as_ref(), args.len()) {
+            ("PING", 1) => Reply::Simple(String::from("PONG")),
+            ("PING", 2) | ("ECHO", 2) => Reply::Bulk(args[1].clone()),
+            ("QUIT", _) => return (Reply::Simple(String::from("OK")), true),
+            ("HELLO", _) => self.hello(&args),
+            ("SELECT", 2) => match args[1].as_ref() {
+                "0" => Reply::Simple(String::from("OK")),
+                _ => Reply::Error(String::from("ERR DB index is out of range")),
+            },
+            // Client libraries name themselves when they connect, and redis-cli asks for the
+            // documentation of every command; neither needs an answer.
+            ("CLIENT", n) if n >= 2 => Reply::Simple(String::from("OK")),
+            ("COMMAND", _) => Reply::Array(Vec::new()),
+            ("MULTI", 1) => {
+                if (self.queued.is_some()) {
+                    return (Reply::Error(String::from("ERR MULTI calls can not be nested")), false);
+                }
+
+                self.queued = Some(Vec::new());
+                self.queue_failed = false;
+                Reply::Simple(String::from("OK"))
+            },
+            ("EXEC", 1) => {
+                let queued = match self.queued.take() {
+                    Some(q) => q,
+                    None => return (Reply::Error(String::from("ERR EXEC without MULTI")), false),
+                };
+                if (self.queue_failed) {
+                    return (Reply::Error(String::from("EXECABORT Transaction discarded because of previous errors.")), false);
+                }
+
+                // Holding the database for the whole transaction keeps other connections out of
+                // the middle of it.
+                let mut dbfile = lock_dbfile(&self.dbfile);
+                Reply::Array(queued.iter().map(|args| execute(&mut dbfile, args)).collect())
+            },
+            ("DISCARD", 1) => match self.queued.take() {
+                Some(_) => Reply::Simple(String::from("OK")),
+                None => Reply::Error(String::from("ERR DISCARD without MULTI")),
+            },
+            (name, _) if DATA_COMMANDS.contains(&name) => execute(&mut lock_dbfile(&self.dbfile), &args),
+            ("PING", _) | ("ECHO", _) | ("SELECT", _) | ("CLIENT", _) | ("MULTI", _) | ("EXEC", _) | ("DISCARD", _) => wrong_arity(&name),
+            _ => Reply::Error(format!("ERR unknown command '{}'", args[0])),
+        };
+
+        return (reply, false);
+    }
+
+    /// HELLO [protover], which switches to RESP3 and describes the server.  Authentication isn't
+    /// supported, so any other arguments are ignored.
+    fn hello(&mut self, args: &[String]) -> Reply {
+        match args.get(1).map(|v| v.as_str()) {
+            None => {},
+            Some("2") => self.version = 2,
+            Some("3") => self.version = 3,
+            Some(_) => return Reply::Error(String::from("NOPROTO unsupported protocol version")),
+        }
+
+        let field = |name: &str, value: Reply| (Reply::Bulk(String::from(name)), value);
+        return Reply::Map(vec!(
+            field("server", Reply::Bulk(String::from("gringotts"))),
+            field("version", Reply::Bulk(String::from(env!("CARGO_PKG_VERSION")))),
+            field("proto", Reply::Integer(self.version as i64)),
+            field("id", Reply::Integer(0)),
+            field("mode", Reply::Bulk(String::from("standalone"))),
+            field("role", Reply::Bulk(String::from("master"))),
+            field("modules", Reply::Array(Vec::new())),
+        ));
+    }
+}
+
+/// Answers commands on the connection until the client closes it or sends QUIT.  Input that
+/// isn't RESP is answered with an error, and then the connection is closed.
+pub fn serve_connection(stream: TcpStream, dbfile: SharedDbfile) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut session = RespSession::new(dbfile);
+
+    loop {
+        let mut out = Vec::new();
+
+        let args = match read_command(&mut reader) {
+            Ok(Some(a)) => a,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                Reply::Error(format!("ERR {}", err)).encode(session.version, &mut out);
+                writer.write_all(&out)?;
+                return Err(err);
+            },
+        };
+        if (args.is_empty()) {
+            continue;
+        }
+
+        let (reply, quit) = match panic::catch_unwind(panic::AssertUnwindSafe(|| session.handle(args))) {
+            Ok(r) => r,
+            Err(payload) => (Reply::Error(format!("ERR {}", describe_panic(payload))), false),
+        };
+
+        reply.encode(session.version, &mut out);
+        writer.write_all(&out)?;
+        if (quit) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbfile::testing::*;
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn temp_session(name: &str) -> (TempDb, RespSession) {
+        let temp_db = TempDb::new(name);
+        let session = RespSession::new(share(temp_db.create()));
+        return (temp_db, session);
+    }
+
+    fn run(session: &mut RespSession, command: &str) -> Reply {
+        let args = command.split(' ').map(|word| word.as_bytes().to_vec()).collect();
+        return session.handle(args).0;
+    }
+
+    fn bulk(s: &str) -> Reply {
+        return Reply::Bulk(String::from(s));
+    }
+
+    #[test]
+    fn matching_globs() {
+        assert!(glob_match("*", "a/b"));
+        assert!(glob_match("a/*", "a/b/c"));
+        assert!(!glob_match("a/*", "b/a"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("h[a-c]llo", "hbllo"));
+        assert!(glob_match("h\\*llo", "h*llo"));
+        assert!(!glob_match("h\\*llo", "hello"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("*[0-9]", "key9"));
+    }
+
+    #[test]
+    fn matching_globs_with_many_stars() {
+        // Trying every way of sharing the text out between the stars would take far too long.
+        let text = "a".repeat(10000);
+        assert!(!glob_match("*a*a*a*a*a*a*a*a*a*a*b", &text));
+        assert!(glob_match("*a*a*a*a*a*a*a*a*a*a*", &text));
+    }
+
+    #[test]
+    fn reading_commands() {
+        let mut input: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\na/b\r\nPING  hi\r\n\r\n";
+        assert_eq!(read_command(&mut input).unwrap(), Some(vec!(b"GET".to_vec(), b"a/b".to_vec())));
+        assert_eq!(read_command(&mut input).unwrap(), Some(vec!(b"PING".to_vec(), b"hi".to_vec())));
+        assert_eq!(read_command(&mut input).unwrap(), Some(Vec::new()));
+        assert_eq!(read_command(&mut input).unwrap(), None);
+
+        let mut bad: &[u8] = b"*1\r\n+GET\r\n";
+        assert!(read_command(&mut bad).is_err());
+        let mut short: &[u8] = b"*1\r\n$10\r\nGET\r\n";
+        assert!(read_command(&mut short).is_err());
+    }
+
+    #[test]
+    fn encoding_replies() {
+        let encode = |reply: Reply, version: u8| {
+            let mut out = Vec::new();
+            reply.encode(version, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(encode(Reply::Null, 2), "$-1\r\n");
+        assert_eq!(encode(Reply::Null, 3), "_\r\n");
+        assert_eq!(encode(Reply::Array(vec!(bulk("é"), Reply::Integer(-1))), 2), "*2\r\n$2\r\né\r\n:-1\r\n");
+        assert_eq!(encode(Reply::Map(vec!((bulk("a"), Reply::Simple(String::from("OK"))))), 2), "*2\r\n$1\r\na\r\n+OK\r\n");
+        assert_eq!(encode(Reply::Map(vec!((bulk("a"), Reply::Simple(String::from("OK"))))), 3), "%1\r\n$1\r\na\r\n+OK\r\n");
+    }
+
+    #[test]
+    fn running_commands() {
+        let (_temp_db, mut session) = temp_session("gringotts-resp-commands-test.gdb");
+        let ok = Reply::Simple(String::from("OK"));
+
+        assert_eq!(run(&mut session, "SET a/b 1"), ok);
+        assert_eq!(run(&mut session, "get a/b"), bulk("1"));
+        assert_eq!(run(&mut session, "GET a/c"), Reply::Null);
+        assert_eq!(run(&mut session, "SET a/b 2 NX"), Reply::Null);
+        assert_eq!(run(&mut session, "SET a/c 2 XX"), Reply::Null);
+        assert_eq!(run(&mut session, "SET a/c 3 NX EX 100"), ok);
+        assert_eq!(run(&mut session, "SET a/c 4 NX XX"), syntax_error());
+        assert_eq!(run(&mut session, "SET a/c 4 XX"), ok);
+        assert_eq!(run(&mut session, "GET a/c"), bulk("4"));
+        assert_eq!(run(&mut session, "SET a/c 3 EX"), syntax_error());
+        assert_eq!(run(&mut session, "EXISTS a/b a/c a/d"), Reply::Integer(2));
+        assert_eq!(run(&mut session, "INCR n"), Reply::Integer(1));
+        assert_eq!(run(&mut session, "INCRBY n 10"), Reply::Integer(11));
+        assert_eq!(run(&mut session, "DECR n"), Reply::Integer(10));
+        run(&mut session, "SET s x");
+        assert!(match run(&mut session, "INCR s") { Reply::Error(_) => true, _ => false });
+        assert!(match run(&mut session, "INCRBY n x") { Reply::Error(_) => true, _ => false });
+
+        assert_eq!(run(&mut session, "KEYS a/*"), Reply::Array(vec!(bulk("a/b"), bulk("a/c"))));
+        assert_eq!(run(&mut session, "KEYS a/?"), Reply::Array(vec!(bulk("a/b"), bulk("a/c"))));
+        assert_eq!(run(&mut session, "KEYS n*"), Reply::Array(vec!(bulk("n"))));
+        assert_eq!(run(&mut session, "SCAN 0 COUNT 2"), Reply::Array(vec!(bulk("/a/c"), Reply::Array(vec!(bulk("a/b"), bulk("a/c"))))));
+        assert_eq!(run(&mut session, "SCAN /a/c MATCH n"), Reply::Array(vec!(bulk("0"), Reply::Array(vec!(bulk("n"))))));
+        assert_eq!(run(&mut session, "SCAN 2"), Reply::Error(String::from("ERR invalid cursor")));
+
+        // The values are kept as they are, integers included.
+        assert_eq!(run(&mut session, "EXPIRE n 100"), Reply::Integer(1));
+        assert_eq!(run(&mut session, "INCR n"), Reply::Integer(11));
+        assert!(match run(&mut session, "EXPIRE n 9223372036854775807") { Reply::Error(_) => true, _ => false });
+        assert_eq!(run(&mut session, "GET n"), bulk("11"));
+        assert_eq!(run(&mut session, "DECRBY n -9223372036854775808"), out_of_range());
+
+        assert_eq!(run(&mut session, "EXPIRE a/b 0"), Reply::Integer(1));
+        assert_eq!(run(&mut session, "EXPIRE a/b 10"), Reply::Integer(0));
+        assert_eq!(run(&mut session, "DEL a/c a/d"), Reply::Integer(1));
+        assert_eq!(run(&mut session, "GET"), wrong_arity("GET"));
+        assert_eq!(run(&mut session, "FLUSHALL"), Reply::Error(String::from("ERR unknown command 'FLUSHALL'")));
+    }
+
+    #[test]
+    fn scanning_a_piece_at_a_time() {
+        let (_temp_db, mut session) = temp_session("gringotts-resp-scan-test.gdb");
+        let mut expected = Vec::new();
+        for i in 0..100 {
+            for key in vec!(format!("users/{:03}", i), format!("users/{:03}/email", i)) {
+                run(&mut session, &format!("SET {} {}", key, "x".repeat(40)));
+                expected.push(bulk(&key));
+            }
+        }
+
+        let mut scanned = Vec::new();
+        let mut cursor = String::from("0");
+        loop {
+            let (next, keys) = match run(&mut session, &format!("SCAN {} COUNT 7", cursor)) {
+                Reply::Array(mut reply) => (reply.remove(0), reply.remove(0)),
+                reply => panic!("Unexpected reply {:?}", reply),
+            };
+            match keys {
+                Reply::Array(keys) => scanned.extend(keys),
+                reply => panic!("Unexpected reply {:?}", reply),
+            }
+
+            cursor = match next {
+                Reply::Bulk(ref c) if c == "0" => break,
+                Reply::Bulk(c) => c,
+                reply => panic!("Unexpected reply {:?}", reply),
+            };
+        }
+
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn transactions() {
+        let (_temp_db, mut session) = temp_session("gringotts-resp-transactions-test.gdb");
+
+        assert_eq!(run(&mut session, "MULTI"), Reply::Simple(String::from("OK")));
+        assert_eq!(run(&mut session, "SET a 1"), Reply::Simple(String::from("QUEUED")));
+        assert_eq!(run(&mut session, "INCR a"), Reply::Simple(String::from("QUEUED")));
+        assert_eq!(run(&mut session, "EXEC"), Reply::Array(vec!(Reply::Simple(String::from("OK")), Reply::Integer(2))));
+        assert_eq!(run(&mut session, "EXEC"), Reply::Error(String::from("ERR EXEC without MULTI")));
+
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a 5");
+        assert_eq!(run(&mut session, "DISCARD"), Reply::Simple(String::from("OK")));
+        assert_eq!(run(&mut session, "GET a"), bulk("2"));
+
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a 5");
+        assert!(match run(&mut session, "HELLO") { Reply::Error(_) => true, _ => false });
+        assert!(match run(&mut session, "EXEC") { Reply::Error(ref e) => e.starts_with("EXECABORT"), _ => false });
+        assert_eq!(run(&mut session, "GET a"), bulk("2"));
+    }
+
+    #[test]
+    fn serving_redis_clients() {
+        let temp_db = TempDb::new("gringotts-resp-server-test.gdb");
+        let dbfile = share(temp_db.create());
+        let server = Server::bind_shared("127.0.0.1:0", dbfile, Protocol::Resp).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"*1\r\n$4\r\nPING\r\nHELLO 3\r\nGET missing\r\nQUIT\r\n").unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+
+        assert!(replies.starts_with("+PONG\r\n%7\r\n$6\r\nserver\r\n$9\r\ngringotts\r\n"));
+        assert!(replies.ends_with("_\r\n+OK\r\n"));
+    }
+}
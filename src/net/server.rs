@@ -0,0 +1,189 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use dbfile::Dbfile;
+use dbfile::batch::WriteBatch;
+use error::describe_panic;
+use net::protocol::*;
+use net::http;
+use net::resp;
+
+/// A database shared by every connection, and every listener, that serves it.
+pub type SharedDbfile = Arc<Mutex<Dbfile>>;
+
+pub fn share(dbfile: Dbfile) -> SharedDbfile {
+    return Arc::new(Mutex::new(dbfile));
+}
+
+/// Takes the database for one request.  If a request panicked while holding it, it may have been
+/// left half written: only batches go through the journal, and a single write can split blocks.
+/// So every request after that panics too, which each protocol reports to its client as an
+/// error, rather than carrying on with a file that may be inconsistent.
+pub fn lock_dbfile(dbfile: &SharedDbfile) -> MutexGuard<'_, Dbfile> {
+    return match dbfile.lock() {
+        Ok(guard) => guard,
+        Err(_) => panic!("The database is unavailable, since an earlier request failed while using it"),
+    };
+}
+
+/// What clients of a listener speak.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// The framed protocol in `net::protocol`, used by the `Client` in the gringotts-client crate.
+    Binary,
+    /// The Redis protocol, RESP2 or RESP3; see `net::resp`.
+    Resp,
+    /// HTTP with JSON bodies; see `net::http`.
+    Http,
+}
+
+/// Serves a database to clients over TCP, with a thread for each connection.  Requests from
+/// every connection take turns with the database.
+pub struct Server {
+    listener: TcpListener,
+    dbfile: SharedDbfile,
+    protocol: Protocol,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(address: A, dbfile: Dbfile) -> io::Result<Server> {
+        return Server::bind_shared(address, share(dbfile), Protocol::Binary);
+    }
+
+    /// Listens for clients of the given protocol, on a database that other listeners may be
+    /// serving too.
+    pub fn bind_shared<A: ToSocketAddrs>(address: A, dbfile: SharedDbfile, protocol: Protocol) -> io::Result<Server> {
+        return Ok(Server {
+            listener: TcpListener::bind(address)?,
+            dbfile: dbfile,
+            protocol: protocol,
+        });
+    }
+
+    /// The address being listened on, which is useful when binding to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return self.listener.local_addr();
+    }
+
+    /// Accepts connections until accepting fails.
+    pub fn serve(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let dbfile = self.dbfile.clone();
+            let protocol = self.protocol;
+
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or(String::from("unknown"));
+                debug!("Accepted a connection from {}", peer);
+
+                let result = match protocol {
+                    Protocol::Binary => serve_connection(stream, dbfile),
+                    Protocol::Resp => resp::serve_connection(stream, dbfile),
+                    Protocol::Http => http::serve_connection(stream, dbfile),
+                };
+                if let Err(err) = result {
+                    warn!("Dropped the connection from {}: {}", peer, err);
+                }
+            });
+        }
+
+        return Ok(());
+    }
+}
+
+/// One connection's view of the database, along with any transaction it has under way.
+struct Session {
+    dbfile: SharedDbfile,
+    batch: Option<WriteBatch>,
+}
+
+impl Session {
+    fn get_dbfile(&self) -> MutexGuard<'_, Dbfile> {
+        return lock_dbfile(&self.dbfile);
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        match request {
+            Request::Get(key) => {
+                if let Some(pending) = self.batch.as_ref().and_then(|b| b.get(&key)) {
+                    return Response::Value(pending);
+                }
+
+                return Response::Value(self.get_dbfile().get_val(&key));
+            },
+            Request::Set(key, value) => {
+                let written = match self.batch {
+                    Some(ref mut batch) => Ok(batch.set(&key, value)),
+                    None => self.get_dbfile().set_val(&key, value),
+                };
+
+                return match written {
+                    Ok(_) => Response::Ok,
+                    Err(e) => Response::Error(e.get_message()),
+                };
+            },
+            Request::Delete(key) => {
+                match self.batch {
+                    Some(ref mut batch) => batch.delete(&key),
+                    None => {
+                        self.get_dbfile().delete_val(&key);
+                    },
+                }
+                return Response::Ok;
+            },
+            Request::Scan(prefix) => {
+                return Response::Entries(self.get_dbfile().scan(&prefix));
+            },
+            Request::Begin => {
+                if (self.batch.is_some()) {
+                    return Response::Error(String::from("A transaction is already under way"));
+                }
+
+                self.batch = Some(WriteBatch::new());
+                return Response::Ok;
+            },
+            Request::Commit => {
+                let batch = match self.batch.take() {
+                    Some(b) => b,
+                    None => return Response::Error(String::from("No transaction is under way")),
+                };
+
+                return match self.get_dbfile().write_batch(batch) {
+                    Ok(_) => Response::Ok,
+                    Err(e) => Response::Error(format!("Couldn't commit: {}", e)),
+                };
+            },
+            Request::Rollback => {
+                return match self.batch.take() {
+                    Some(_) => Response::Ok,
+                    None => Response::Error(String::from("No transaction is under way")),
+                };
+            },
+        }
+    }
+}
+
+/// Answers requests on the connection until the client closes it.  A message that can't be
+/// decoded ends the connection, since the stream can't be trusted after it.
+fn serve_connection(mut stream: TcpStream, dbfile: SharedDbfile) -> io::Result<()> {
+    let mut session = Session {
+        dbfile: dbfile,
+        batch: None,
+    };
+
+    while let Some(payload) = read_frame(&mut stream)? {
+        let request = Request::decode(&payload)?;
+
+        let response = match panic::catch_unwind(panic::AssertUnwindSafe(|| session.handle(request))) {
+            Ok(r) => r,
+            Err(payload) => Response::Error(describe_panic(payload)),
+        };
+
+        write_frame(&mut stream, &response.encode())?;
+    }
+
+    return Ok(());
+}
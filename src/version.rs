@@ -1,10 +1,15 @@
 #![allow(unused_parens)]
 
-use std::cmp::Ordering;
-use std::cmp::PartialEq;
-use std::cmp::PartialOrd;
-use std::mem;
+use std::fmt;
+use std::str::FromStr;
+use error::*;
 
+pub const VERSION_BYTES: usize = 6;
+
+/// A `major.minor.build` version.  Major versions may break compatibility, minor versions add
+/// features without doing so, and build versions are bug fixes.  Versions order by major, then
+/// minor, then build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Version {
     pub major: u16,
     pub minor: u16,
@@ -12,76 +17,136 @@ pub struct Version {
 }
 
 impl Version {
-    pub fn from_bytes(bytes: Vec<u8>) -> Version {
-        if (bytes.len() != 6) {
-            panic!("Version Strings should be 8bytes long.")
+    pub fn new(major: u16, minor: u16, build: u16) -> Version {
+        return Version {
+            major: major,
+            minor: minor,
+            build: build,
+        };
+    }
+
+    /// Parses a version string such as "1.2.3".  A missing minor or build number is read as 0.
+    pub fn parse(input: &str) -> Result<Version, InvalidInputError> {
+        let invalid = || InvalidInputError::new(&format!("{} is not a valid version", input));
+
+        let mut numbers: Vec<u16> = Vec::with_capacity(3);
+        for piece in input.trim().split('.') {
+            match piece.parse::<u16>() {
+                Ok(n) => numbers.push(n),
+                Err(_) => return Err(invalid()),
+            }
         }
 
-        return Version {
-            major: (bytes[0] as u16) | ((bytes[1] as u16) << 8),
-            minor: (bytes[2] as u16) | ((bytes[3] as u16) << 8),
-            build: (bytes[4] as u16) | ((bytes[5] as u16) << 8),
+        if (numbers.len() > 3) {
+            return Err(invalid());
+        }
+
+        while (numbers.len() < 3) {
+            numbers.push(0);
         }
+
+        return Ok(Version::new(numbers[0], numbers[1], numbers[2]));
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let major_bytes: [u8; 2];
-        let minor_bytes: [u8; 2];
-        let build_bytes: [u8; 2];
-
-        unsafe {
-            major_bytes = mem::transmute::<u16, [u8;2]>(self.major.clone());
-            minor_bytes = mem::transmute::<u16, [u8;2]>(self.minor.clone());
-            build_bytes = mem::transmute::<u16, [u8;2]>(self.build.clone());
+    /// Decodes the little-endian form written by `to_bytes`.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Version, CorruptDataError> {
+        if (bytes.len() != VERSION_BYTES) {
+            let message = format!("Version should be {} bytes long, but was {}", VERSION_BYTES, bytes.len());
+            return Err(CorruptDataError::new(&message));
         }
 
-        let vec = vec![major_bytes[0], major_bytes[1],
-                       minor_bytes[0], minor_bytes[1],
-                       build_bytes[0], build_bytes[1]];
+        return Ok(Version {
+            major: u16::from_le_bytes([bytes[0], bytes[1]]),
+            minor: u16::from_le_bytes([bytes[2], bytes[3]]),
+            build: u16::from_le_bytes([bytes[4], bytes[5]]),
+        });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(VERSION_BYTES);
+        vec.extend_from_slice(&self.major.to_le_bytes());
+        vec.extend_from_slice(&self.minor.to_le_bytes());
+        vec.extend_from_slice(&self.build.to_le_bytes());
 
         return vec;
     }
-}
 
-impl PartialEq<Version> for Version {
-    fn eq(&self, other: &Version) -> bool {
-        // Check in reverse order, since that's more likely to differ
-        if ( self.build != other.build ) {
-            return false;
+    /// Whether a file written at this version can be opened by a reader at `reader`.  Anything
+    /// up to the reader's major version can be, since older formats are upgraded by migrations;
+    /// a newer major version means the format has changed in ways the reader doesn't know about.
+    /// Before 1.0.0, breaking changes only bump the minor version, so that's what's compared.
+    pub fn is_readable_by(&self, reader: &Version) -> bool {
+        if (self.major == 0 && reader.major == 0) {
+            return self.minor <= reader.minor;
         }
 
-        if ( self.minor != other.minor ) {
-            return false;
-        }
+        return self.major <= reader.major;
+    }
 
-        if ( self.major != other.major ) {
-            return false;
-        }
+    /// Whether a file written at this version has migrations to run to reach `target`.
+    pub fn needs_upgrade_to(&self, target: &Version) -> bool {
+        return self < target;
+    }
+}
 
-        return true;
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}.{}.{}", self.major, self.minor, self.build);
     }
 }
 
-impl PartialOrd<Version> for Version {
-    // Self is ___ than/to Other...
-    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
-        let mut ordering: Ordering;
+impl FromStr for Version {
+    type Err = InvalidInputError;
 
-        ordering = self.major.partial_cmp(&other.major).unwrap();
-        if (ordering != Ordering::Equal) {
-            return Some(ordering);
-        }
+    fn from_str(s: &str) -> Result<Version, InvalidInputError> {
+        return Version::parse(s);
+    }
+}
 
-        ordering = self.minor.partial_cmp(&other.minor).unwrap();
-        if (ordering != Ordering::Equal) {
-            return Some(ordering);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        ordering = self.build.partial_cmp(&other.build).unwrap();
-        if (ordering != Ordering::Equal) {
-            return Some(ordering);
-        }
+    #[test]
+    fn parsing_and_display() {
+        let version = Version::parse("1.2.3").ok().unwrap();
+        assert_eq!(version, Version::new(1, 2, 3));
+        assert_eq!(version.to_string(), "1.2.3");
+
+        assert_eq!("4".parse::<Version>().ok(), Some(Version::new(4, 0, 0)));
+        assert!(Version::parse("1.2.3.4").is_err());
+        assert!(Version::parse("1.x").is_err());
+        assert!(Version::parse("").is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Version::new(0, 0, 9) < Version::new(0, 1, 0));
+        assert!(Version::new(1, 0, 0) > Version::new(0, 9, 9));
+        assert!(Version::new(1, 2, 3) == Version::new(1, 2, 3));
+    }
+
+    #[test]
+    fn byte_encoding() {
+        let version = Version::new(1, 258, 3);
+        assert_eq!(version.to_bytes(), vec!(1, 0, 2, 1, 3, 0));
+        assert_eq!(Version::from_bytes(version.to_bytes()).ok(), Some(version));
+        assert!(Version::from_bytes(vec!(1, 0, 2, 1, 3, 0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn compatibility() {
+        let reader = Version::new(1, 2, 0);
+        assert!(Version::new(0, 5, 0).is_readable_by(&reader));
+        assert!(Version::new(1, 9, 9).is_readable_by(&reader));
+        assert!(!Version::new(2, 0, 0).is_readable_by(&reader));
+
+        assert!(Version::new(1, 1, 0).needs_upgrade_to(&reader));
+        assert!(!Version::new(1, 2, 0).needs_upgrade_to(&reader));
 
-        return Some(Ordering::Equal);
+        let reader = Version::new(0, 3, 0);
+        assert!(Version::new(0, 3, 9).is_readable_by(&reader));
+        assert!(!Version::new(0, 4, 0).is_readable_by(&reader));
+        assert!(!Version::new(1, 0, 0).is_readable_by(&reader));
     }
 }
@@ -36,6 +36,9 @@ describe("dbctl", function() {
   });
 
   afterAll(function() {
+    _.each(fs.readdirSync(test_dir), function(file) {
+      fs.unlinkSync(path.join(test_dir, file));
+    });
     fs.rmdirSync(test_dir);
   });
 
@@ -107,7 +110,7 @@ describe("dbctl", function() {
 
     it("should display the correct initial information.", function() {
       var output = dbctl("info", testdbfile);
-      var unexecuted_expects = 3;
+      var unexecuted_expects = 6;
 
       _.each(output.split(/\n/), function(line) {
         var key = line.split(/:\s+/)[0];
@@ -118,19 +121,320 @@ describe("dbctl", function() {
           unexecuted_expects--;
         }
         else if (key.match(/version/i)) {
-          expect(val).toBe("0.0.1");
+          expect(val).toBe("0.6.0");
           unexecuted_expects--;
         }
         else if (key.match(/number of blocks/i)) {
           expect(val).toBe("1");
           unexecuted_expects--;
         }
+        else if (key.match(/prefix compression/i)) {
+          expect(val).toBe("off");
+          unexecuted_expects--;
+        }
+        else if (key.match(/block compression/i)) {
+          expect(val).toBe("off");
+          unexecuted_expects--;
+        }
+        else if (key.match(/encryption/i)) {
+          expect(val).toBe("off");
+          unexecuted_expects--;
+        }
       });
 
       expect(unexecuted_expects).toBe(0);
     });
   });
 
+  describe("stats", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a", {input: "1"});
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should count keys, subtrees and blocks", function() {
+      var output = dbctl("stats", testdbfile);
+      expect(output).toMatch(/^Keys: 2$/m);
+      expect(output).toMatch(/^Subtrees: 1$/m);
+      expect(output).toMatch(/^Max Depth: 1$/m);
+      expect(output).toMatch(/^Total Blocks: 2$/m);
+      expect(output).toMatch(/^Orphaned Blocks: 0$/m);
+    });
+  });
+
+  describe("block", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should decode a block's header and entries", function() {
+      var output = dbctl("block", testdbfile, "1");
+      expect(output).toMatch(/^Type: Root \(22\)$/m);
+      expect(output).toMatch(/^NextBlock: none$/m);
+      expect(output).toMatch(/^  "a": -> block 2$/m);
+      expect(dbctl("block", testdbfile, "2")).toMatch(/^  "b": value "2"$/m);
+    });
+
+    it("should show the raw bytes with --raw", function() {
+      var output = dbctl("block", testdbfile, "1 --raw");
+      expect(output).toMatch(/^Magic \[0\.\.2\), 2 bytes$/m);
+      expect(output).toMatch(/^  00000000  42 4c /m);
+    });
+
+    it("should refuse blocks that don't exist", function() {
+      expect(dbctl("block", testdbfile, "7")).toMatch("There is no block 7");
+    });
+  });
+
+  describe("graph", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should write the blocks as a DOT graph", function() {
+      var output = dbctl("graph", testdbfile);
+      expect(output).toMatch(/^digraph gringotts \{$/m);
+      expect(output).toMatch(/^    1 \[label="1: Root\\na \.\. a\\n\d+% full"\];$/m);
+      expect(output).toMatch(/^    1 -> 2 \[label="a"\];$/m);
+    });
+  });
+
+  describe("drop", function() {
+    it("should remove the database", function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a", {input: "1"});
+      expect(fs.existsSync(testdbfile + ".lock")).toBe(false);
+
+      expect(dbctl("drop", testdbfile)).toMatch("Dropped database");
+      expect(fs.existsSync(testdbfile)).toBe(false);
+      expect(fs.existsSync(testdbfile + ".lock")).toBe(false);
+    });
+
+    it("should leave files that aren't databases alone", function() {
+      var notes = path.join(test_dir, "notes.txt");
+      fs.writeFileSync(notes, "important");
+      expect(dbctl("drop", notes)).toMatch("not a valid Gringotts database");
+      expect(fs.existsSync(notes)).toBe(true);
+      fs.unlinkSync(notes);
+    });
+  });
+
+  describe("ls and verify", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a", {input: "1"});
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should list the keys beneath a path", function() {
+      expect(dbctl("ls", testdbfile, "")).toBe('a/ = "1"\n');
+      expect(dbctl("ls", testdbfile, "a")).toBe('b = "2"\n');
+    });
+
+    it("should check every block", function() {
+      expect(dbctl("verify", testdbfile, "")).toBe("OK: 2 blocks checked\n");
+    });
+  });
+
+  describe("--format json", function() {
+    var json = function(command, extraArgs, config) {
+      return JSON.parse(dbctl(command, testdbfile, extraArgs + " --format json", config));
+    };
+
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should print results as JSON", function() {
+      var info = json("info", "");
+      expect(info.version).toBe("0.6.0");
+      expect(info.number_of_blocks).toBe(2);
+      expect(info.encryption).toBe(false);
+
+      expect(json("get", "a/b")).toEqual({key: "a/b", value: "2"});
+      expect(json("get", "a/c")).toEqual({key: "a/c", value: null});
+      expect(json("ls", "a")).toEqual({path: "a", keys: [{key: "b", value: "2", has_subtree: false}]});
+      expect(json("stats", "").keys).toBe(1);
+      expect(json("verify", "")).toEqual({ok: true, blocks_checked: 2, problems: []});
+    });
+
+    it("should print errors as objects with codes, and exit with 1", function() {
+      var error;
+      try {
+        json("block", "7");
+      }
+      catch (e) {
+        error = e;
+      }
+
+      expect(error.status).toBe(1);
+      expect(JSON.parse(error.stdout.toString()).error.code).toBe("not_found");
+    });
+
+    it("should report files that aren't databases", function() {
+      var notes = path.join(test_dir, "notes.txt");
+      fs.writeFileSync(notes, "important");
+      var error;
+      try {
+        dbctl("info", notes, "--format json");
+      }
+      catch (e) {
+        error = e;
+      }
+      fs.unlinkSync(notes);
+
+      expect(error.status).toBe(1);
+      expect(JSON.parse(error.stdout.toString()).error.code).toBe("not_a_database");
+    });
+  });
+
+  describe("create --prefix-compression", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile, '--prefix-compression');
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should report prefix compression in info", function() {
+      var output = dbctl("info", testdbfile);
+      expect(output).toMatch(/Prefix Compression: on/);
+    });
+
+    it("should store and retrieve keys with shared prefixes", function() {
+      for(var i = 0; i < 300; i++) {
+        dbctl("set", testdbfile, "users/user_" + (100000 + i), {input: "user " + i});
+      }
+
+      expect(dbctl("get", testdbfile, "users/user_100000")).toBe("user 0");
+      expect(dbctl("get", testdbfile, "users/user_100299")).toBe("user 299");
+    });
+  });
+
+  describe("create --block-compression", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile, '--block-compression');
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should report block compression in info", function() {
+      var output = dbctl("info", testdbfile);
+      expect(output).toMatch(/Block Compression: on/);
+    });
+
+    it("should store and retrieve keys across compressed blocks", function() {
+      for(var i = 0; i < 300; i++) {
+        dbctl("set", testdbfile, "config/setting_" + i, {input: "value " + i});
+      }
+
+      expect(dbctl("get", testdbfile, "config/setting_0")).toBe("value 0");
+      expect(dbctl("get", testdbfile, "config/setting_299")).toBe("value 299");
+    });
+  });
+
+  describe("create --passphrase-file", function() {
+    var passphraseFile = path.join(test_dir, "passphrase");
+    var newPassphraseFile = path.join(test_dir, "new-passphrase");
+
+    beforeAll(function() {
+      fs.writeFileSync(passphraseFile, "hunter2\n");
+      fs.writeFileSync(newPassphraseFile, "swordfish\n");
+      dbctl('create', testdbfile, '--passphrase-file ' + passphraseFile);
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+      fs.unlinkSync(passphraseFile);
+      fs.unlinkSync(newPassphraseFile);
+    });
+
+    it("should report encryption in info", function() {
+      var output = dbctl("info", testdbfile);
+      expect(output).toMatch(/Encryption: on/);
+    });
+
+    it("should only read keys with the passphrase", function() {
+      dbctl("set", testdbfile, "secret --passphrase-file " + passphraseFile, {input: "shh"});
+
+      expect(dbctl("get", testdbfile, "secret --passphrase-file " + passphraseFile)).toBe("shh");
+      expect(dbctl("get", testdbfile, "secret")).toMatch(/encrypted/);
+      expect(fs.readFileSync(testdbfile).toString()).not.toMatch(/shh/);
+    });
+
+    it("should change the passphrase with rekey", function() {
+      dbctl("rekey", testdbfile, "--passphrase-file " + passphraseFile + " --new-passphrase-file " + newPassphraseFile);
+
+      expect(dbctl("get", testdbfile, "secret --passphrase-file " + newPassphraseFile)).toBe("shh");
+      expect(dbctl("get", testdbfile, "secret --passphrase-file " + passphraseFile)).toMatch(/Incorrect passphrase/);
+    });
+  });
+
+  describe("incr", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should count up from zero", function() {
+      expect(dbctl("incr", testdbfile, "counters/hits")).toBe("1\n");
+      expect(dbctl("incr", testdbfile, "counters/hits 10")).toBe("11\n");
+      expect(dbctl("incr", testdbfile, "counters/hits -- -12")).toBe("-1\n");
+      expect(dbctl("get", testdbfile, "counters/hits")).toBe("-1");
+    });
+  });
+
+  describe("set --ttl and expire", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should hide and then remove expired values", function() {
+      dbctl("set", testdbfile, "sessions/a --ttl 1", {input: "token"});
+      dbctl("set", testdbfile, "sessions/b --ttl 3600", {input: "token"});
+      expect(dbctl("get", testdbfile, "sessions/a")).toBe("token");
+
+      exec("sleep 1.1");
+      expect(dbctl("get", testdbfile, "sessions/a")).toBe("");
+      expect(dbctl("expire", testdbfile, "")).toMatch(/Removed 1 expired keys/);
+      expect(dbctl("get", testdbfile, "sessions/b")).toBe("token");
+    });
+  });
+
   describe("get/set", function() {
     beforeAll(function() {
       dbctl('create', testdbfile);
@@ -188,4 +492,124 @@ describe("dbctl", function() {
       expect(output).toBe(val);
     });
   });
+
+  describe("shell", function() {
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    it("should run commands read from stdin", function() {
+      var commands = "set config/port 80\ncd config\nls\nbegin\nset name api\ncommit\nexit\n";
+      var output = dbctl("shell", testdbfile, "", {input: commands, env: _.extend({}, process.env, {HOME: test_dir})});
+      expect(output).toMatch(/^port = "80"$/m);
+      expect(output).toMatch(/^Committed 1 changes$/m);
+      expect(dbctl("get", testdbfile, "config/name", {input: ""})).toBe("api");
+    });
+  });
+
+  describe("dump/load", function() {
+    var loadeddbfile = path.join(test_dir, "loaded.db");
+
+    beforeAll(function() {
+      dbctl('create', testdbfile);
+      dbctl("set", testdbfile, "a", {input: "1"});
+      dbctl("set", testdbfile, "a/b", {input: "2"});
+      dbctl("set", testdbfile, "c/d", {input: "3"});
+    });
+
+    afterAll(function() {
+      fs.unlinkSync(testdbfile);
+    });
+
+    afterEach(function() {
+      if (fs.existsSync(loadeddbfile)) {
+        fs.unlinkSync(loadeddbfile);
+      }
+    });
+
+    it("should dump nested JSON", function() {
+      var output = JSON.parse(dbctl("dump", testdbfile));
+      expect(output).toEqual({a: {"/": "1", b: "2"}, c: {d: "3"}});
+    });
+
+    it("should dump JSON lines", function() {
+      var lines = dbctl("dump", testdbfile, "--lines").trim().split("\n");
+      expect(_.map(lines, JSON.parse)).toEqual([
+        {path: "a", value: "1"},
+        {path: "a/b", value: "2"},
+        {path: "c/d", value: "3"}
+      ]);
+    });
+
+    it("should rebuild a database from either dump", function() {
+      var nested = dbctl("dump", testdbfile);
+      dbctl("load", loadeddbfile, "", {input: nested});
+      expect(dbctl("dump", loadeddbfile)).toBe(nested);
+
+      fs.unlinkSync(loadeddbfile);
+
+      var lines = dbctl("dump", testdbfile, "--lines");
+      dbctl("load", loadeddbfile, "--lines", {input: lines});
+      expect(dbctl("dump", loadeddbfile, "--lines")).toBe(lines);
+    });
+
+    it("should import a sorted dump", function() {
+      var lines = dbctl("dump", testdbfile, "--lines");
+      expect(dbctl("import", loadeddbfile, "--sorted", {input: lines})).toMatch("Imported 3 keys");
+      expect(dbctl("dump", loadeddbfile, "--lines")).toBe(lines);
+    });
+
+    it("should refuse to import unsorted lines with --sorted", function() {
+      var lines = '{"path": "c/d", "value": "3"}\n{"path": "a", "value": "1"}\n';
+      expect(dbctl("import", loadeddbfile, "--sorted", {input: lines})).toMatch("out of order");
+    });
+  });
+});
+
+describe("kvctl", function() {
+  var kvdbfile = path.join(test_dir, "kv.db");
+
+  var kvctl = function(args, input) {
+    var result = child_process.spawnSync("../target/debug/kvctl", args, {
+      cwd: test_dir,
+      input: input || "",
+      env: _.extend({}, process.env, {GRINGOTTS_DBFILE: kvdbfile})
+    });
+
+    return {status: result.status, stdout: result.stdout.toString()};
+  };
+
+  beforeAll(function() {
+    fs.mkdirSync(test_dir);
+    child_process.execSync("../target/debug/dbctl create --database-file " + kvdbfile, {cwd: test_dir});
+  });
+
+  afterAll(function() {
+    _.each(fs.readdirSync(test_dir), function(file) {
+      fs.unlinkSync(path.join(test_dir, file));
+    });
+    fs.rmdirSync(test_dir);
+  });
+
+  it("should echo the old value when setting", function() {
+    expect(kvctl(["set", "a", "1"])).toEqual({status: 0, stdout: ""});
+    expect(kvctl(["set", "a"], "2")).toEqual({status: 0, stdout: "1"});
+    expect(kvctl(["get", "a"])).toEqual({status: 0, stdout: "2"});
+  });
+
+  it("should exit with 1 for missing keys", function() {
+    expect(kvctl(["get", "missing"]).status).toBe(1);
+  });
+
+  it("should prefer --dbfile over GRINGOTTS_DBFILE", function() {
+    expect(kvctl(["--dbfile", "missing.db", "get", "a"]).status).toBe(3);
+  });
+
+  it("should exit with 2 for usage errors", function() {
+    expect(kvctl(["frobnicate"]).status).toBe(2);
+  });
 });